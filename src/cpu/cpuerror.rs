@@ -9,7 +9,6 @@ pub enum CpuError {
     IllegalInstruction { pc: u16 },
     ReadingIntoInvalidReg { r: RegisterID, pc: u16 },
     ReadingFromInvalidReg { r: RegisterID, pc: u16 },
-    IndexOutOfBounds { index: usize, pc: u16 },
 }
 
 impl Display for CpuError {
@@ -28,9 +27,6 @@ impl Display for CpuError {
                 "Attempt to read into invalid register r={} at pc={}",
                 r, pc
             ),
-            Self::IndexOutOfBounds { index, pc } => {
-                write!(f, "Index out of bounds, index={}, pc={}", index, pc)
-            }
         }
     }
 }
@@ -0,0 +1,113 @@
+use std::ops::{BitOr, BitOrAssign};
+
+// https://gbdev.io/pandocs/CPU_Registers_and_Flags.html#the-flags-register-lower-8-bits-of-af-register
+
+/// Contents of the F register.
+/// Only the upper nibble holds flags, the lower one is always zero.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct Flags(u8);
+
+impl Flags {
+    pub const Z: Flags = Flags(0b1000_0000); // zero
+    pub const N: Flags = Flags(0b0100_0000); // subtraction
+    pub const H: Flags = Flags(0b0010_0000); // half-carry
+    pub const C: Flags = Flags(0b0001_0000); // carry
+
+    pub fn empty() -> Flags {
+        Flags(0)
+    }
+    /// Builds flags from a raw F value, dropping the unused lower nibble.
+    pub fn from_bits(bits: u8) -> Flags {
+        Flags(bits & 0xf0)
+    }
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(&self, other: Flags) -> bool {
+        (self.0 & other.0) == other.0
+    }
+    pub fn insert(&mut self, other: Flags) {
+        self.0 |= other.0;
+    }
+    pub fn remove(&mut self, other: Flags) {
+        self.0 &= !other.0;
+    }
+    pub fn toggle(&mut self, other: Flags) {
+        self.0 ^= other.0;
+    }
+    pub fn set(&mut self, other: Flags, on: bool) {
+        if on {
+            self.insert(other);
+        } else {
+            self.remove(other);
+        }
+    }
+
+    pub fn z(&self) -> bool {
+        self.contains(Flags::Z)
+    }
+    pub fn n(&self) -> bool {
+        self.contains(Flags::N)
+    }
+    pub fn h(&self) -> bool {
+        self.contains(Flags::H)
+    }
+    pub fn c(&self) -> bool {
+        self.contains(Flags::C)
+    }
+}
+
+impl BitOr for Flags {
+    type Output = Flags;
+
+    fn bitor(self, rhs: Flags) -> Flags {
+        Flags(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Flags {
+    fn bitor_assign(&mut self, rhs: Flags) {
+        self.0 |= rhs.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::flags::Flags;
+
+    #[test]
+    fn flag_bits() {
+        assert_eq!(Flags::Z.bits(), 0x80);
+        assert_eq!(Flags::N.bits(), 0x40);
+        assert_eq!(Flags::H.bits(), 0x20);
+        assert_eq!(Flags::C.bits(), 0x10);
+    }
+    #[test]
+    fn from_bits_masks_low_nibble() {
+        assert_eq!(Flags::from_bits(0xff).bits(), 0xf0);
+        assert_eq!(Flags::from_bits(0x0f), Flags::empty());
+    }
+    #[test]
+    fn combine_and_query() {
+        let flags = Flags::Z | Flags::C;
+        assert_eq!(flags.bits(), 0x90);
+        assert!(flags.z() && flags.c());
+        assert!(!flags.n() && !flags.h());
+        assert!(flags.contains(Flags::Z | Flags::C));
+        assert!(!flags.contains(Flags::Z | Flags::H));
+    }
+    #[test]
+    fn set_and_toggle() {
+        let mut flags = Flags::empty();
+        flags.set(Flags::H, true);
+        flags |= Flags::N;
+        assert_eq!(flags, Flags::N | Flags::H);
+
+        flags.set(Flags::H, false);
+        flags.toggle(Flags::C);
+        assert_eq!(flags, Flags::N | Flags::C);
+        flags.toggle(Flags::C);
+        assert_eq!(flags, Flags::N);
+    }
+}
@@ -1,61 +1,78 @@
 use crate::cpu::instructions::RegisterID;
-use crate::cpu::{c_flag, cpuerror::CpuError, hi_byte, CPU};
+use crate::cpu::{cpuerror::CpuError, flags::Flags, hi_byte, CPU};
 use crate::memory::Memory;
 
 impl CPU {
     pub fn add_immediate(&mut self, n: u8) {
-        let n = n as u16;
-        let a = hi_byte(self.af) as u16;
-        let result = n + a;
-
-        self.add_flag_checks(result, n);
+        let a = hi_byte(self.af);
+        let result = self.add_flag_checks(a, n, false);
+        self.set_register_a(result);
     }
     pub fn adc_immediate(&mut self, n: u8) {
-        let n = n as u16;
-        let a = hi_byte(self.af) as u16;
-        let mut result = n + a;
-
-        if c_flag(self.af) {
-            result += 1;
-        }
-
-        self.add_flag_checks(result, n);
+        let a = hi_byte(self.af);
+        let result = self.add_flag_checks(a, n, self.flags().c());
+        self.set_register_a(result);
     }
 
     pub fn sub_immediate(&mut self, n: u8) {
-        let n = n as i16;
-        let a = hi_byte(self.af) as i16;
-        let result = a - n;
-
-        self.sub_flag_checks(result, n);
+        let a = hi_byte(self.af);
+        let result = self.sub_flag_checks(a, n, false);
+        self.set_register_a(result);
     }
     pub fn sbc_immediate(&mut self, n: u8) {
-        let n = n as i16;
-        let a = hi_byte(self.af) as i16;
-        let mut result = a - n;
-
-        if c_flag(self.af) {
-            result -= 1;
-        }
-
-        self.sub_flag_checks(result, n);
+        let a = hi_byte(self.af);
+        let result = self.sub_flag_checks(a, n, self.flags().c());
+        self.set_register_a(result);
     }
 
     pub fn compare_immediate(&mut self, n: u8) {
         // basically a sub immediate but not changing A
-        let n = n as i16;
-        let a = hi_byte(self.af) as i16;
-        let result = a - n;
+        let a = hi_byte(self.af);
+        self.sub_flag_checks(a, n, false);
+    }
 
-        self.sub_flag_checks(result, n);
-        self.set_register_a(a as u8);
+    pub fn decimal_adjust_accumulator(&mut self) {
+        // turns the result of adding or subtracting two BCD numbers back into BCD,
+        // based on the flags that instruction left behind
+        let mut a = hi_byte(self.af);
+        let flags = self.flags();
+        let mut carry = flags.c();
+
+        if flags.n() {
+            // after a subtraction only the flags are considered
+            if flags.h() {
+                a = a.wrapping_sub(0x06);
+            }
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+        } else {
+            // upper digit is checked first, since fixing the lower one may change it
+            if carry || (a > 0x99) {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if flags.h() || ((a & 0x0f) > 0x09) {
+                a = a.wrapping_add(0x06);
+            }
+        }
+
+        // N is left alone
+        let mut flags = flags;
+        flags.set(Flags::Z, a == 0);
+        flags.remove(Flags::H);
+        flags.set(Flags::C, carry);
+        self.set_flags(flags);
+        self.set_register_a(a);
     }
 
     pub fn logical_template<F: Fn(u8, u8) -> u8>(&mut self, n: u8, op: F) {
         let a = hi_byte(self.af);
         let result = op(a, n);
 
-        self.set_register_a(result as u8);
+        self.set_register_a(result);
+        // N, H and C are all reset, AND sets H afterwards
+        self.set_flags(Flags::empty());
         self.zero_flag_check(result);
     }
 
@@ -74,26 +91,17 @@ impl CPU {
 
     pub fn increment_8b(&mut self, r: RegisterID, mem: &mut Memory) -> Result<(), CpuError> {
         let r2 = r.clone();
-        let n = self.r_table_lookup(r2, mem)? as u16;
-        let mut result = n + 1;
-
-        if (n <= 0b00001000) && (result > 0b00001000) {
-            self.set_halfcarry_flag_on();
-        } else {
-            self.set_halfcarry_flag_off();
-        }
+        let n = self.r_table_lookup(r2, mem)?;
+        let result = n.wrapping_add(1);
 
-        if result > 0xff {
-            result -= 0xff;
-            self.set_carry_flag_on();
-        } else {
-            self.set_carry_flag_off();
-        }
+        // carry is left alone
+        let mut flags = self.flags();
+        flags.set(Flags::Z, result == 0);
+        flags.remove(Flags::N);
+        flags.set(Flags::H, (n & 0x0f) == 0x0f);
+        self.set_flags(flags);
 
-        self.zero_flag_check(result as u8);
-        self.set_subtraction_flag_off();
-
-        self.r_table_assign(r, result as u8, mem)?;
+        self.r_table_assign(r, result, mem)?;
         Ok(())
     }
 
@@ -112,27 +120,17 @@ impl CPU {
 
     pub fn decrement_8b(&mut self, r: RegisterID, mem: &mut Memory) -> Result<(), CpuError> {
         let r2 = r.clone();
-        let n = self.r_table_lookup(r2, mem)? as i16;
-        let mut result = n - 1;
-
-        // copied from sub_flag_checks
-        if (n >= 0b00010000) && (result < 0b00001000) {
-            self.set_halfcarry_flag_on();
-        } else {
-            self.set_halfcarry_flag_off();
-        }
-
-        if result < 0 {
-            result += 0xff;
-            self.set_carry_flag_on();
-        } else {
-            self.set_carry_flag_off();
-        }
+        let n = self.r_table_lookup(r2, mem)?;
+        let result = n.wrapping_sub(1);
 
-        self.set_subtraction_flag_on();
-        self.zero_flag_check(result as u8);
+        // carry is left alone
+        let mut flags = self.flags();
+        flags.set(Flags::Z, result == 0);
+        flags.insert(Flags::N);
+        flags.set(Flags::H, (n & 0x0f) == 0x00);
+        self.set_flags(flags);
 
-        self.r_table_assign(r, result as u8, mem)?;
+        self.r_table_assign(r, result, mem)?;
         Ok(())
     }
 
@@ -157,26 +155,16 @@ impl CPU {
 
     pub fn decrement_16b(&mut self, r: RegisterID) -> Result<(), CpuError> {
         let r2 = r.clone();
-        let mut val = self.rp_table_lookup(r2)? as i32;
-        val -= 1;
-        if val < 0 {
-            // check for underflow
-            val += 0xffff;
-        }
-        self.rp_table_assign(r, val as u16)?;
+        let val = self.rp_table_lookup(r2)?;
+        self.rp_table_assign(r, val.wrapping_sub(1))?;
 
         Ok(())
     }
 
     pub fn increment_16b(&mut self, r: RegisterID) -> Result<(), CpuError> {
         let r2 = r.clone();
-        let mut val = self.rp_table_lookup(r2)? as u32;
-        val += 1;
-        if val > 0xffff {
-            // check for overflow
-            val -= 0xffff;
-        }
-        self.rp_table_assign(r, val as u16)?;
+        let val = self.rp_table_lookup(r2)?;
+        self.rp_table_assign(r, val.wrapping_add(1))?;
 
         Ok(())
     }
@@ -185,26 +173,244 @@ impl CPU {
         let r2 = r.clone();
         let hl = self.hl as u32;
         let r16 = self.rp_table_lookup(r2)? as u32;
-        let mut result = hl + r16;
+        let result = hl + r16;
 
-        if result > 0xffff {
-            result -= 0xffff;
-            self.set_carry_flag_on();
-        } else {
-            self.set_carry_flag_off();
+        // Z is left alone
+        let mut flags = self.flags();
+        flags.remove(Flags::N);
+        // check for overflow from bit 11
+        flags.set(Flags::H, (hl & 0x0fff) + (r16 & 0x0fff) > 0x0fff);
+        flags.set(Flags::C, result > 0xffff);
+        self.set_flags(flags);
+
+        self.hl = result as u16;
+        Ok(())
+    }
+
+    pub fn add_registers(&mut self, r1: RegisterID, r2: RegisterID) -> Result<(), CpuError> {
+        // HL is the only 16-bit register that can be added to
+        match r1 {
+            RegisterID::HL => self.add_hl_and_r16(r2),
+            _ => Err(CpuError::ReadingIntoInvalidReg { r: r1, pc: self.pc }),
         }
+    }
 
-        self.set_subtraction_flag_off(); // N -> 0
+    // shared by ADD SP, d and LD HL, SP+d.
+    // H and C come from adding d to the low byte of SP as unsigned numbers,
+    // even though the offset itself is signed. Z and N are always reset
+    pub fn sp_plus_signed_offset(&mut self, d: i8) -> u16 {
+        let sp = self.sp;
+        let offset = d as u8 as u16;
 
-        // check for overflow from bit 11
-        let bit_11 = 0b1 << 12;
-        if (hl < bit_11) && (result >= bit_11) {
-            self.set_halfcarry_flag_on();
-        } else {
-            self.set_halfcarry_flag_off();
+        let mut flags = Flags::empty();
+        flags.set(Flags::H, (sp & 0x0f) + (offset & 0x0f) > 0x0f);
+        flags.set(Flags::C, (sp & 0xff) + offset > 0xff);
+        self.set_flags(flags);
+
+        sp.wrapping_add(d as i16 as u16)
+    }
+
+    pub fn add_signed(&mut self, r: RegisterID, d: i8) -> Result<(), CpuError> {
+        match r {
+            RegisterID::SP => self.sp = self.sp_plus_signed_offset(d),
+            _ => return Err(CpuError::ReadingIntoInvalidReg { r, pc: self.pc }),
         }
 
-        self.hl = result as u16;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::instructions::RegisterID;
+    use crate::cpu::{flags::Flags, hi_byte, CPU};
+
+    fn cpu_with(a: u8, n: bool, h: bool, c: bool) -> CPU {
+        let mut cpu = CPU::new(0x0200, false, 0);
+        cpu.set_register_a(a);
+        cpu.set_flag(Flags::N, n);
+        cpu.set_flag(Flags::H, h);
+        cpu.set_flag(Flags::C, c);
+
+        cpu
+    }
+
+    // correction table for DAA, laid out by digit ranges instead of
+    // the flag-by-flag checks the implementation uses.
+    // returns the expected accumulator and carry flag
+    fn daa_reference(a: u8, n: bool, h: bool, c: bool) -> (u8, bool) {
+        let hi = a >> 4;
+        let lo = a & 0x0f;
+
+        if n {
+            // the SM83 ignores the digits after a subtraction
+            let correction = match (c, h) {
+                (false, false) => 0x00,
+                (false, true) => 0x06,
+                (true, false) => 0x60,
+                (true, true) => 0x66,
+            };
+            return (a.wrapping_sub(correction), c);
+        }
+
+        let (correction, carry) = match (c, hi, h, lo) {
+            (false, 0x0..=0x9, false, 0x0..=0x9) => (0x00, false),
+            (false, 0x0..=0x9, true, 0x0..=0x9) => (0x06, false),
+            (false, 0x0..=0x8, _, 0xa..=0xf) => (0x06, false),
+            (false, 0xa..=0xf, false, 0x0..=0x9) => (0x60, true),
+            (false, 0x9..=0xf, _, 0xa..=0xf) => (0x66, true),
+            (false, 0xa..=0xf, true, 0x0..=0x9) => (0x66, true),
+            (true, _, false, 0x0..=0x9) => (0x60, true),
+            (true, _, true, 0x0..=0x9) => (0x66, true),
+            (true, _, _, _) => (0x66, true),
+            _ => unreachable!(),
+        };
+        (a.wrapping_add(correction), carry)
+    }
+
+    #[test]
+    fn daa_exhaustive() {
+        for a in 0..=0xff {
+            for flags in 0..8 {
+                let (n, h, c) = (flags & 0b100 != 0, flags & 0b010 != 0, flags & 0b001 != 0);
+                let mut cpu = cpu_with(a, n, h, c);
+                cpu.decimal_adjust_accumulator();
+
+                let (expected_a, expected_c) = daa_reference(a, n, h, c);
+                let case = format!("A={:#04x} N={} H={} C={}", a, n, h, c);
+                assert_eq!(hi_byte(cpu.af), expected_a, "{}", case);
+                assert_eq!(cpu.flags().z(), expected_a == 0, "{}", case);
+                assert_eq!(cpu.flags().n(), n, "{}", case);
+                assert!(!cpu.flags().h(), "{}", case);
+                assert_eq!(cpu.flags().c(), expected_c, "{}", case);
+            }
+        }
+    }
+
+    fn to_bcd(x: u8) -> u8 {
+        ((x / 10) << 4) | (x % 10)
+    }
+
+    #[test]
+    fn daa_after_bcd_addition() {
+        for x in 0..100u8 {
+            for y in 0..100u8 {
+                let (bx, by) = (to_bcd(x), to_bcd(y));
+                let (sum, c) = bx.overflowing_add(by);
+                let h = (bx & 0x0f) + (by & 0x0f) > 0x0f;
+
+                let mut cpu = cpu_with(sum, false, h, c);
+                cpu.decimal_adjust_accumulator();
+
+                assert_eq!(hi_byte(cpu.af), to_bcd((x + y) % 100), "{} + {}", x, y);
+                assert_eq!(cpu.flags().c(), x + y >= 100, "{} + {}", x, y);
+            }
+        }
+    }
+    #[test]
+    fn daa_after_bcd_subtraction() {
+        for x in 0..100u8 {
+            for y in 0..100u8 {
+                let (bx, by) = (to_bcd(x), to_bcd(y));
+                let (diff, c) = bx.overflowing_sub(by);
+                let h = (bx & 0x0f) < (by & 0x0f);
+
+                let mut cpu = cpu_with(diff, true, h, c);
+                cpu.decimal_adjust_accumulator();
+
+                let expected = (x as i16 - y as i16).rem_euclid(100) as u8;
+                assert_eq!(hi_byte(cpu.af), to_bcd(expected), "{} - {}", x, y);
+                assert_eq!(cpu.flags().c(), x < y, "{} - {}", x, y);
+            }
+        }
+    }
+
+    macro_rules! add_sp_test {
+        ($name:tt, $sp:expr, $d:expr, $expected:expr, $h:expr, $c:expr) => {
+            #[test]
+            fn $name() {
+                let mut cpu = cpu_with(0, true, false, false);
+                cpu.set_flag(Flags::Z, true);
+                cpu.sp = $sp;
+                cpu.add_signed(RegisterID::SP, $d).unwrap();
+
+                assert_eq!(cpu.sp, $expected);
+                assert!(!cpu.flags().z());
+                assert!(!cpu.flags().n());
+                assert_eq!(cpu.flags().h(), $h);
+                assert_eq!(cpu.flags().c(), $c);
+            }
+        };
+    }
+    add_sp_test!(add_sp_positive_no_carry, 0xfff8, 2, 0xfffa, false, false);
+    add_sp_test!(add_sp_positive_halfcarry, 0x000f, 1, 0x0010, true, false);
+    add_sp_test!(add_sp_positive_carry, 0x00ff, 1, 0x0100, true, true);
+    add_sp_test!(add_sp_negative_both_carries, 0xfff8, -8, 0xfff0, true, true);
+    add_sp_test!(add_sp_negative_from_zero, 0x0000, -1, 0xffff, false, false);
+    add_sp_test!(add_sp_negative_to_zero, 0x0001, -1, 0x0000, true, true);
+    add_sp_test!(add_sp_negative_min, 0x0080, -128, 0x0000, false, true);
+    add_sp_test!(
+        add_sp_negative_within_high_byte,
+        0x0108,
+        -8,
+        0x0100,
+        true,
+        true
+    );
+    add_sp_test!(add_sp_negative_no_flags, 0xd000, -16, 0xcff0, false, false);
+
+    #[test]
+    fn ld_hl_sp_offset_shares_flags() {
+        let mut cpu = cpu_with(0, false, false, false);
+        cpu.sp = 0xfff8;
+        cpu.load_sp_to_hl_with_offset(-8);
+
+        assert_eq!(cpu.hl, 0xfff0);
+        assert!(cpu.flags().h());
+        assert!(cpu.flags().c());
+    }
+    #[test]
+    fn add_signed_requires_sp() {
+        let mut cpu = cpu_with(0, false, false, false);
+        assert!(cpu.add_signed(RegisterID::HL, 1).is_err());
+    }
+
+    #[test]
+    fn add_hl_wraps_with_carry() {
+        let mut cpu = cpu_with(0, false, false, false);
+        cpu.hl = 0xffff;
+        cpu.bc = 0x0001;
+        cpu.add_registers(RegisterID::HL, RegisterID::BC).unwrap();
+
+        assert_eq!(cpu.hl, 0x0000);
+        assert!(cpu.flags().h());
+        assert!(cpu.flags().c());
+    }
+    #[test]
+    fn add_hl_halfcarry_from_bit_11() {
+        let mut cpu = cpu_with(0, false, false, false);
+        cpu.hl = 0x0fff;
+        cpu.de = 0x0001;
+        cpu.add_hl_and_r16(RegisterID::DE).unwrap();
+
+        assert_eq!(cpu.hl, 0x1000);
+        assert!(cpu.flags().h());
+        assert!(!cpu.flags().c());
+    }
+    #[test]
+    fn add_hl_keeps_zero_flag() {
+        let mut cpu = cpu_with(0, true, false, false);
+        cpu.set_flag(Flags::Z, true);
+        cpu.hl = 0x1234;
+        cpu.add_hl_and_r16(RegisterID::HL).unwrap();
+
+        assert_eq!(cpu.hl, 0x2468);
+        assert!(cpu.flags().z());
+        assert!(!cpu.flags().n());
+    }
+    #[test]
+    fn add_registers_requires_hl() {
+        let mut cpu = cpu_with(0, false, false, false);
+        assert!(cpu.add_registers(RegisterID::SP, RegisterID::BC).is_err());
+    }
+}
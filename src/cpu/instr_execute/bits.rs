@@ -0,0 +1,141 @@
+use crate::cpu::instructions::RegisterID;
+use crate::cpu::{cpuerror::CpuError, flags::Flags, CPU};
+use crate::memory::Memory;
+
+impl CPU {
+    pub fn test_bit(&mut self, y: u8, r: RegisterID, mem: &mut Memory) -> Result<(), CpuError> {
+        let n = self.r_table_lookup(r, mem)?;
+
+        // Z is set when the bit is *off*, carry is left alone
+        let mut flags = self.flags();
+        flags.set(Flags::Z, (n & (0b1 << y)) == 0);
+        flags.remove(Flags::N);
+        flags.insert(Flags::H);
+        self.set_flags(flags);
+
+        Ok(())
+    }
+
+    pub fn reset_bit(&mut self, y: u8, r: RegisterID, mem: &mut Memory) -> Result<(), CpuError> {
+        let r2 = r.clone();
+        let n = self.r_table_lookup(r2, mem)?;
+        self.r_table_assign(r, n & !(0b1 << y), mem)?;
+
+        Ok(())
+    }
+
+    pub fn set_bit(&mut self, y: u8, r: RegisterID, mem: &mut Memory) -> Result<(), CpuError> {
+        let r2 = r.clone();
+        let n = self.r_table_lookup(r2, mem)?;
+        self.r_table_assign(r, n | (0b1 << y), mem)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::instructions::RegisterID;
+    use crate::cpu::{flags::Flags, testing, CPU};
+    use crate::memory::Memory;
+
+    fn setup() -> (CPU, Memory) {
+        let (mut cpu, mem) = testing::setup(&[], 0x0200);
+        cpu.hl = 0xc000; // (HL) points into WRAM
+
+        (cpu, mem)
+    }
+
+    // every combination of bit index and r[z] operand, (HL) included
+    fn all_operands() -> impl Iterator<Item = (u8, RegisterID)> {
+        (0..8).flat_map(|y| (0..8).map(move |z| (y, RegisterID::r_lookup(z))))
+    }
+
+    #[test]
+    fn bit_set_clears_zero() {
+        for (y, r) in all_operands() {
+            let (mut cpu, mut mem) = setup();
+            cpu.r_table_assign(r.clone(), 0b1 << y, &mut mem).unwrap();
+            cpu.test_bit(y, r.clone(), &mut mem).unwrap();
+
+            assert!(!cpu.flags().z(), "BIT {}, {}", y, r);
+            assert!(!cpu.flags().n(), "BIT {}, {}", y, r);
+            assert!(cpu.flags().h(), "BIT {}, {}", y, r);
+        }
+    }
+    #[test]
+    fn bit_clear_sets_zero() {
+        for (y, r) in all_operands() {
+            let (mut cpu, mut mem) = setup();
+            cpu.r_table_assign(r.clone(), !(0b1 << y), &mut mem)
+                .unwrap();
+            cpu.test_bit(y, r.clone(), &mut mem).unwrap();
+
+            assert!(cpu.flags().z(), "BIT {}, {}", y, r);
+            assert!(!cpu.flags().n(), "BIT {}, {}", y, r);
+            assert!(cpu.flags().h(), "BIT {}, {}", y, r);
+        }
+    }
+    #[test]
+    fn bit_preserves_carry_and_operand() {
+        for (y, r) in all_operands() {
+            let (mut cpu, mut mem) = setup();
+            cpu.set_flag(Flags::C, true);
+            cpu.r_table_assign(r.clone(), 0x5a, &mut mem).unwrap();
+            cpu.test_bit(y, r.clone(), &mut mem).unwrap();
+
+            assert!(cpu.flags().c(), "BIT {}, {}", y, r);
+            assert_eq!(cpu.r_table_lookup(r.clone(), &mut mem).unwrap(), 0x5a);
+        }
+    }
+
+    #[test]
+    fn res_clears_only_that_bit() {
+        for (y, r) in all_operands() {
+            let (mut cpu, mut mem) = setup();
+            cpu.r_table_assign(r.clone(), 0xff, &mut mem).unwrap();
+            let flags_before = cpu.af & 0xff;
+            cpu.reset_bit(y, r.clone(), &mut mem).unwrap();
+
+            let expected: u8 = !(0b1 << y);
+            assert_eq!(
+                cpu.r_table_lookup(r.clone(), &mut mem).unwrap(),
+                expected,
+                "RES {}, {}",
+                y,
+                r
+            );
+            if r != RegisterID::A {
+                assert_eq!(cpu.af & 0xff, flags_before, "RES {}, {}", y, r);
+            }
+        }
+    }
+    #[test]
+    fn set_sets_only_that_bit() {
+        for (y, r) in all_operands() {
+            let (mut cpu, mut mem) = setup();
+            cpu.r_table_assign(r.clone(), 0x00, &mut mem).unwrap();
+            let flags_before = cpu.af & 0xff;
+            cpu.set_bit(y, r.clone(), &mut mem).unwrap();
+
+            assert_eq!(
+                cpu.r_table_lookup(r.clone(), &mut mem).unwrap(),
+                0b1 << y,
+                "SET {}, {}",
+                y,
+                r
+            );
+            assert_eq!(cpu.af & 0xff, flags_before, "SET {}, {}", y, r);
+        }
+    }
+
+    #[test]
+    fn res_set_through_hl_address_hits_memory() {
+        let (mut cpu, mut mem) = setup();
+        cpu.set_bit(3, RegisterID::HLaddress, &mut mem).unwrap();
+        assert_eq!(mem.read8(0xc000), 0b1000);
+        cpu.reset_bit(3, RegisterID::HLaddress, &mut mem).unwrap();
+        assert_eq!(mem.read8(0xc000), 0);
+        assert_eq!(cpu.hl, 0xc000);
+    }
+}
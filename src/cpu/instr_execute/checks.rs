@@ -1,55 +1,63 @@
-use crate::cpu::CPU;
+use crate::cpu::{flags::Flags, CPU};
 
 impl CPU {
     pub fn enable_interrupts(&mut self) {
         self.interrupts_enabled = true;
     }
+    pub fn schedule_enable_interrupts(&mut self) {
+        // EI doesn't set IME until after the instruction following it
+        self.interrupts_scheduled = true;
+    }
     pub fn disable_interrupts(&mut self) {
         self.interrupts_enabled = false;
+        self.interrupts_scheduled = false;
     }
 
-    pub fn sub_flag_checks(&mut self, mut result: i16, prev_val: i16) {
-        if (prev_val >= 0b00010000) && (result < 0b00010000) {
-            self.set_halfcarry_flag_on();
-        } else {
-            self.set_halfcarry_flag_off();
-        }
+    // the following two compute `a + n + carry` and `a - n - carry`,
+    // setting every flag accordingly and returning the result
 
-        if result < 0 {
-            result += 0xff;
-            self.set_carry_flag_on();
-        } else {
-            self.set_carry_flag_off();
-        }
+    pub fn sub_flag_checks(&mut self, a: u8, n: u8, carry: bool) -> u8 {
+        let carry = carry as u8;
+        let result = a.wrapping_sub(n).wrapping_sub(carry);
 
-        self.set_subtraction_flag_on();
+        let mut flags = Flags::N;
+        flags.set(Flags::Z, result == 0);
+        // borrow from bit 4
+        flags.set(Flags::H, (a & 0x0f) < (n & 0x0f) + carry);
+        // borrow from past bit 7
+        flags.set(Flags::C, (a as u16) < (n as u16) + (carry as u16));
+        self.set_flags(flags);
 
-        self.zero_flag_check(result as u8);
-        self.set_register_a(result as u8);
+        result
     }
-    pub fn add_flag_checks(&mut self, mut result: u16, prev_val: u16) {
-        if (prev_val <= 0b00001111) && (result >= 0b00010000) {
-            self.set_halfcarry_flag_on();
-        } else {
-            self.set_halfcarry_flag_off();
-        }
+    pub fn add_flag_checks(&mut self, a: u8, n: u8, carry: bool) -> u8 {
+        let carry = carry as u8;
+        let result = (a as u16) + (n as u16) + (carry as u16);
 
-        if result > 0xff {
-            result -= 0xff;
-            self.set_carry_flag_on();
-        } else {
-            self.set_carry_flag_off();
-        }
+        let mut flags = Flags::empty();
+        flags.set(Flags::Z, (result as u8) == 0);
+        // carry from bit 3
+        flags.set(Flags::H, (a & 0x0f) + (n & 0x0f) + carry > 0x0f);
+        // carry from bit 7
+        flags.set(Flags::C, result > 0xff);
+        self.set_flags(flags);
 
-        self.zero_flag_check(result as u8);
-        self.set_subtraction_flag_off();
-        self.set_register_a(result as u8);
+        result as u8
     }
     pub fn zero_flag_check(&mut self, result: u8) {
-        if result == 0 {
-            self.set_zero_flag_on();
-        } else {
-            self.set_zero_flag_off();
-        }
+        self.set_flag(Flags::Z, result == 0);
+    }
+
+    pub fn set_carry_flag(&mut self) {
+        let mut flags = self.flags();
+        flags.remove(Flags::N | Flags::H);
+        flags.insert(Flags::C);
+        self.set_flags(flags);
+    }
+    pub fn complement_carry_flag(&mut self) {
+        let mut flags = self.flags();
+        flags.remove(Flags::N | Flags::H);
+        flags.toggle(Flags::C);
+        self.set_flags(flags);
     }
 }
@@ -1,5 +1,5 @@
-use crate::cpu::{c_flag, CPU, nc_flag, nz_flag, z_flag};
 use crate::cpu::instructions::FlagID;
+use crate::cpu::CPU;
 
 impl CPU {
     pub fn jump(&mut self, nn: u16) {
@@ -10,15 +10,25 @@ impl CPU {
         self.pc = self.hl;
     }
 
-    pub fn jump_conditional(&mut self, f: FlagID, nn: u16) {
-        if match f {
-            FlagID::C => c_flag(self.af),
-            FlagID::NC => nc_flag(self.af),
-            FlagID::Z => z_flag(self.af),
-            FlagID::NZ => nz_flag(self.af),
-        } {
+    pub fn condition_met(&self, f: FlagID) -> bool {
+        match f {
+            FlagID::C => self.flags().c(),
+            FlagID::NC => !self.flags().c(),
+            FlagID::Z => self.flags().z(),
+            FlagID::NZ => !self.flags().z(),
+        }
+    }
+
+    // conditional jumps report whether they were taken,
+    // since that changes how long they take to execute
+
+    pub fn jump_conditional(&mut self, f: FlagID, nn: u16) -> bool {
+        let taken = self.condition_met(f);
+        if taken {
             self.pc = nn;
         }
+
+        taken
     }
 
     pub fn jump_reg(&mut self, d: i8) {
@@ -26,15 +36,12 @@ impl CPU {
         self.pc = new_pc;
     }
 
-    pub fn jump_reg_conditional(&mut self, f: FlagID, d: i8) {
-        if match f {
-            FlagID::C => c_flag(self.af),
-            FlagID::NC => nc_flag(self.af),
-            FlagID::Z => z_flag(self.af),
-            FlagID::NZ => nz_flag(self.af),
-        } {
-            let new_pc = ((self.pc as i32) + (d as i32)) as u16;
-            self.pc = new_pc;
+    pub fn jump_reg_conditional(&mut self, f: FlagID, d: i8) -> bool {
+        let taken = self.condition_met(f);
+        if taken {
+            self.jump_reg(d);
         }
+
+        taken
     }
 }
@@ -1,11 +1,18 @@
 use crate::cpu::instructions::RegisterID;
-use crate::cpu::{cpuerror::CpuError, lo_byte, CPU};
+use crate::cpu::{cpuerror::CpuError, hi_byte, lo_byte, CPU};
 use crate::memory::Memory;
 
 impl CPU {
-    pub fn load_immediate16(&mut self, r: RegisterID, nn: u16) -> Result<(), CpuError> {
+    pub fn load_immediate16(
+        &mut self,
+        r: RegisterID,
+        nn: u16,
+        mem: &mut Memory,
+    ) -> Result<(), CpuError> {
         match r {
-            RegisterID::AF => self.af = nn,
+            // LD A, (nn) is the only one that treats nn as an address
+            RegisterID::A => self.set_register_a(mem.read8(nn)),
+            RegisterID::AF => self.set_af(nn),
             RegisterID::BC => self.bc = nn,
             RegisterID::DE => self.de = nn,
             RegisterID::HL => self.hl = nn,
@@ -26,25 +33,47 @@ impl CPU {
         Ok(())
     }
 
-    pub fn load_sp_to_hl_with_offset(&mut self, mem: &Memory, d: i8) -> Result<(), CpuError> {
-        let index = ((self.sp as i32) + (d as i32)) as usize;
-        if index > 0xffff {
-            return Err(CpuError::IndexOutOfBounds { index, pc: self.pc });
-        }
-
-        self.hl = mem[index] as u16;
-
-        Ok(())
+    pub fn load_sp_to_hl_with_offset(&mut self, d: i8) {
+        // flags are set the same way as ADD SP, d
+        self.hl = self.sp_plus_signed_offset(d);
     }
 
     pub fn load_ff00_plus_n(&mut self, mem: &Memory, n: u8) {
-        self.af |= 0xff00;
-        self.af &= mem[(0xff00) + (n as usize)] as u16;
+        self.set_register_a(mem.read8(0xff00 + n as u16));
     }
 
     pub fn load_ff00_plus_c(&mut self, mem: &Memory) {
-        self.af |= 0xff00;
-        self.af &= mem[(0xff00) + lo_byte(self.bc) as usize] as u16;
+        self.set_register_a(mem.read8(0xff00 + lo_byte(self.bc) as u16));
+    }
+
+    pub fn store_ff00_plus_n(&mut self, mem: &mut Memory, n: u8) {
+        mem.write8(0xff00 + n as u16, hi_byte(self.af));
+    }
+
+    pub fn store_ff00_plus_c(&mut self, mem: &mut Memory) {
+        mem.write8(0xff00 + lo_byte(self.bc) as u16, hi_byte(self.af));
+    }
+
+    pub fn store_immediate(&mut self, mem: &mut Memory, loc: u16) {
+        mem.write8(loc, hi_byte(self.af));
+    }
+
+    pub fn store_register(
+        &mut self,
+        r: RegisterID,
+        loc: u16,
+        mem: &mut Memory,
+    ) -> Result<(), CpuError> {
+        match r {
+            // LD (nn), SP stores little-endian, like the stack does
+            RegisterID::SP => mem.write16(loc, self.sp),
+            _ => {
+                let val = self.r_table_lookup(r, mem)?;
+                mem.write8(loc, val);
+            }
+        }
+
+        Ok(())
     }
 
     pub fn load_registers16(
@@ -55,28 +84,30 @@ impl CPU {
     ) -> Result<(), CpuError> {
         match r1 {
             RegisterID::SP => self.sp = self.registerid_to_u16(r2),
+            // LD A, (rp)
             RegisterID::A => match r2 {
                 RegisterID::HLplus => {
-                    self.set_register_a(mem[self.hl as usize]);
-                    self.hl -= 1;
+                    self.set_register_a(mem.read8(self.hl));
+                    self.hl = self.hl.wrapping_add(1);
                 }
                 RegisterID::HLminus => {
-                    self.set_register_a(mem[self.hl as usize]);
-                    self.hl += 1;
+                    self.set_register_a(mem.read8(self.hl));
+                    self.hl = self.hl.wrapping_sub(1);
                 }
-                RegisterID::BC => self.set_register_a(mem[self.bc as usize]),
-                RegisterID::DE => self.set_register_a(mem[self.de as usize]),
+                RegisterID::BC => self.set_register_a(mem.read8(self.bc)),
+                RegisterID::DE => self.set_register_a(mem.read8(self.de)),
                 _ => return Err(CpuError::ReadingFromInvalidReg { r: r2, pc: self.pc }),
             },
-            RegisterID::BC => self.bc = self.registerid_to_u16(r2),
-            RegisterID::DE => self.de = self.registerid_to_u16(r2),
+            // LD (rp), A
+            RegisterID::BC => mem.write8(self.bc, hi_byte(self.af)),
+            RegisterID::DE => mem.write8(self.de, hi_byte(self.af)),
             RegisterID::HLplus => {
-                self.hl = self.registerid_to_u16(r2);
-                self.hl += 1;
+                mem.write8(self.hl, hi_byte(self.af));
+                self.hl = self.hl.wrapping_add(1);
             }
             RegisterID::HLminus => {
-                self.hl = self.registerid_to_u16(r2);
-                self.hl -= 1;
+                mem.write8(self.hl, hi_byte(self.af));
+                self.hl = self.hl.wrapping_sub(1);
             }
 
             _ => return Err(CpuError::ReadingIntoInvalidReg { r: r1, pc: self.pc }),
@@ -97,3 +128,119 @@ impl CPU {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::instructions::RegisterID;
+    use crate::cpu::{flags::Flags, hi_byte, testing, CPU};
+    use crate::memory::Memory;
+
+    fn setup() -> (CPU, Memory) {
+        let (mut cpu, mem) = testing::setup(&[], 0x0200);
+        cpu.set_register_a(0x42);
+
+        (cpu, mem)
+    }
+
+    #[test]
+    fn store_ff00_plus_n_writes_io() {
+        let (mut cpu, mut mem) = setup();
+        cpu.store_ff00_plus_n(&mut mem, 0x80);
+        assert_eq!(mem.read8(0xff80), 0x42);
+    }
+    #[test]
+    fn store_ff00_plus_c_writes_io() {
+        let (mut cpu, mut mem) = setup();
+        cpu.bc = 0x0081;
+        cpu.store_ff00_plus_c(&mut mem);
+        assert_eq!(mem.read8(0xff81), 0x42);
+    }
+    #[test]
+    fn store_immediate_writes_a() {
+        let (mut cpu, mut mem) = setup();
+        cpu.store_immediate(&mut mem, 0xc123);
+        assert_eq!(mem.read8(0xc123), 0x42);
+    }
+    #[test]
+    fn store_sp_little_endian() {
+        let (mut cpu, mut mem) = setup();
+        cpu.sp = 0xfff8;
+        cpu.store_register(RegisterID::SP, 0xc000, &mut mem)
+            .unwrap();
+        assert_eq!(mem.read8(0xc000), 0xf8);
+        assert_eq!(mem.read8(0xc001), 0xff);
+    }
+
+    #[test]
+    fn store_through_bc_and_de() {
+        let (mut cpu, mut mem) = setup();
+        cpu.bc = 0xc010;
+        cpu.de = 0xc020;
+        cpu.load_registers16(RegisterID::BC, RegisterID::A, &mut mem)
+            .unwrap();
+        cpu.load_registers16(RegisterID::DE, RegisterID::A, &mut mem)
+            .unwrap();
+
+        assert_eq!(mem.read8(0xc010), 0x42);
+        assert_eq!(mem.read8(0xc020), 0x42);
+        assert_eq!(cpu.bc, 0xc010);
+        assert_eq!(cpu.de, 0xc020);
+    }
+    #[test]
+    fn store_through_hl_plus_and_minus() {
+        let (mut cpu, mut mem) = setup();
+        cpu.hl = 0xc000;
+        cpu.load_registers16(RegisterID::HLplus, RegisterID::A, &mut mem)
+            .unwrap();
+        assert_eq!(mem.read8(0xc000), 0x42);
+        assert_eq!(cpu.hl, 0xc001);
+
+        cpu.load_registers16(RegisterID::HLminus, RegisterID::A, &mut mem)
+            .unwrap();
+        assert_eq!(mem.read8(0xc001), 0x42);
+        assert_eq!(cpu.hl, 0xc000);
+    }
+    #[test]
+    fn load_through_hl_plus_and_minus() {
+        let (mut cpu, mut mem) = setup();
+        cpu.hl = 0xc000;
+        mem.write8(0xc000, 0x11);
+        mem.write8(0xc001, 0x22);
+        cpu.load_registers16(RegisterID::A, RegisterID::HLplus, &mut mem)
+            .unwrap();
+        assert_eq!(hi_byte(cpu.af), 0x11);
+        assert_eq!(cpu.hl, 0xc001);
+
+        cpu.load_registers16(RegisterID::A, RegisterID::HLminus, &mut mem)
+            .unwrap();
+        assert_eq!(hi_byte(cpu.af), 0x22);
+        assert_eq!(cpu.hl, 0xc000);
+    }
+
+    #[test]
+    fn load_sp_to_hl_with_offset_leaves_sp() {
+        let (mut cpu, _) = setup();
+        cpu.sp = 0xfff8;
+        cpu.load_sp_to_hl_with_offset(-8);
+        assert_eq!(cpu.hl, 0xfff0);
+        assert_eq!(cpu.sp, 0xfff8);
+    }
+
+    #[test]
+    fn load_a_from_immediate_address() {
+        let (mut cpu, mut mem) = setup();
+        mem.write8(0xc123, 0x99);
+        cpu.load_immediate16(RegisterID::A, 0xc123, &mut mem)
+            .unwrap();
+        assert_eq!(hi_byte(cpu.af), 0x99);
+    }
+    #[test]
+    fn load_ff00_plus_leaves_flags() {
+        let (mut cpu, mut mem) = setup();
+        cpu.set_flag(Flags::C, true);
+        let flags = cpu.af & 0xff;
+        mem.write8(0xff85, 0x77);
+        cpu.load_ff00_plus_n(&mem, 0x85);
+        assert_eq!(cpu.af, 0x7700 | flags);
+    }
+}
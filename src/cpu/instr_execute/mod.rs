@@ -1,5 +1,8 @@
 mod arithmetic;
+mod bits;
 mod checks;
 mod jump;
 mod load;
+mod power;
 mod shifting;
+mod stack;
@@ -0,0 +1,160 @@
+use crate::cpu::CPU;
+use crate::memory::Memory;
+
+// https://gbdev.io/pandocs/halt.html
+// https://gbdev.io/pandocs/Reducing_Power_Consumption.html#using-the-stop-instruction
+
+impl CPU {
+    pub fn halt(&mut self, mem: &Memory) {
+        if !self.interrupts_enabled && (self.pending_interrupts(mem) != 0) {
+            // HALT bug: the CPU doesn't halt at all,
+            // and fails to increment PC after fetching the next opcode
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
+    }
+
+    pub fn stop(&mut self, mem: &mut Memory) {
+        if mem.model().is_cgb() && mem.speed_switch_armed() {
+            // CGB games use STOP to switch between normal and double speed
+            mem.switch_speed();
+        } else {
+            self.stopped = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::hi_byte;
+    use crate::cpu::interrupts::Interrupt;
+    use crate::cpu::testing::setup;
+    use crate::model::Model;
+
+    #[test]
+    fn halt_waits_for_interrupt() {
+        // HALT, NOP
+        let (mut cpu, mut mem) = setup(&[0x76, 0x00], 0x0200);
+        mem.write8(0xffff, Interrupt::Timer.bit());
+
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        for _ in 0..10 {
+            assert_eq!(cpu.fetch_decode_execute(&mut mem).unwrap(), 1);
+            assert_eq!(cpu.pc, 0x0201);
+        }
+
+        // an interrupt not enabled in IE doesn't wake it up
+        mem.request_interrupt(Interrupt::VBlank);
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0201);
+
+        mem.request_interrupt(Interrupt::Timer);
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0202);
+    }
+    #[test]
+    fn halt_with_ime_services_interrupt() {
+        let (mut cpu, mut mem) = setup(&[0x76, 0x00], 0x0200);
+        cpu.interrupts_enabled = true;
+        mem.write8(0xffff, Interrupt::Serial.bit());
+
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0201);
+
+        mem.request_interrupt(Interrupt::Serial);
+        assert_eq!(cpu.fetch_decode_execute(&mut mem).unwrap(), 5);
+        assert_eq!(cpu.pc, Interrupt::Serial.vector());
+        // returns to the instruction after HALT
+        assert_eq!(cpu.pop_u16(&mut mem), 0x0201);
+    }
+    #[test]
+    fn halt_without_ime_resumes_without_servicing() {
+        let (mut cpu, mut mem) = setup(&[0x76, 0x00], 0x0200);
+        mem.write8(0xffff, Interrupt::Joypad.bit());
+
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        mem.request_interrupt(Interrupt::Joypad);
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+
+        assert_eq!(cpu.pc, 0x0202);
+        assert_eq!(mem.interrupt_flag(), Interrupt::Joypad.bit());
+    }
+
+    #[test]
+    fn halt_bug_reads_next_byte_twice() {
+        // HALT, INC A, NOP
+        let (mut cpu, mut mem) = setup(&[0x76, 0x3c, 0x00], 0x0200);
+        mem.write8(0xffff, Interrupt::VBlank.bit());
+        mem.request_interrupt(Interrupt::VBlank);
+
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0201);
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0201); // INC A, but PC didn't move past it
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0202); // INC A again
+        assert_eq!(hi_byte(cpu.af), 2);
+    }
+    #[test]
+    fn halt_bug_with_operand() {
+        // HALT, LD B, 0x04 is executed as LD B, 0x06 followed by INC B
+        let (mut cpu, mut mem) = setup(&[0x76, 0x06, 0x04], 0x0200);
+        mem.write8(0xffff, Interrupt::VBlank.bit());
+        mem.request_interrupt(Interrupt::VBlank);
+
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(hi_byte(cpu.bc), 0x06);
+        assert_eq!(cpu.pc, 0x0202);
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(hi_byte(cpu.bc), 0x07);
+    }
+
+    #[test]
+    fn stop_waits_for_joypad() {
+        // STOP, NOP
+        let (mut cpu, mut mem) = setup(&[0x10, 0x00, 0x00], 0x0200);
+
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0202);
+
+        mem.write8(0xffff, 0xff);
+        mem.request_interrupt(Interrupt::VBlank);
+        for _ in 0..10 {
+            assert_eq!(cpu.fetch_decode_execute(&mut mem).unwrap(), 1);
+            assert_eq!(cpu.pc, 0x0202);
+        }
+
+        mem.request_interrupt(Interrupt::Joypad);
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0203);
+    }
+    #[test]
+    fn stop_switches_speed_when_armed() {
+        let (mut cpu, mut mem) = setup(&[0x10, 0x00, 0x00], 0x0200);
+        mem.set_model(Model::Cgb);
+        mem.write8(0xff4d, 0x01); // KEY1, prepare speed switch
+
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert!(mem.double_speed());
+        assert!(!mem.speed_switch_armed());
+        assert_eq!(mem.read8(0xff4d) & 0x81, 0x80);
+
+        // didn't enter stop mode
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0203);
+    }
+    #[test]
+    fn stop_never_switches_speed_on_dmg() {
+        let (mut cpu, mut mem) = setup(&[0x10, 0x00, 0x00], 0x0200);
+        mem.write8(0xff4d, 0x01);
+
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert!(!mem.double_speed());
+        // stopped instead
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0202);
+    }
+}
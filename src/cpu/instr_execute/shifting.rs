@@ -1,39 +1,34 @@
 use crate::cpu::instructions::RegisterID;
-use crate::cpu::{c_flag, cpuerror::CpuError, hi_byte, CPU};
+use crate::cpu::{cpuerror::CpuError, flags::Flags, hi_byte, CPU};
 use crate::memory::Memory;
 
 impl CPU {
+    // every CB-prefixed shift/rotate sets Z from the result,
+    // resets N and H and puts the bit shifted out into C
+    fn shift_flag_checks(&mut self, result: u8, carry: bool) {
+        let mut flags = Flags::empty();
+        flags.set(Flags::Z, result == 0);
+        flags.set(Flags::C, carry);
+        self.set_flags(flags);
+    }
+
     pub fn rotate_left(&mut self, r: RegisterID, mem: &mut Memory) -> Result<(), CpuError> {
         let r2 = r.clone();
-        let mut n = self.r_table_lookup(r2, mem)?;
-        if (n >> 7) != 0 {
-            n <<= 1;
-            n |= 0b1;
-            self.set_carry_flag_on();
-        } else {
-            n <<= 1;
-            self.set_carry_flag_off();
-        }
-
-        self.zero_flag_check(n);
-        self.r_table_assign(r, n, mem)?;
+        let n = self.r_table_lookup(r2, mem)?;
+        let result = n.rotate_left(1);
+
+        self.shift_flag_checks(result, (n >> 7) != 0);
+        self.r_table_assign(r, result, mem)?;
 
         Ok(())
     }
     pub fn rotate_right(&mut self, r: RegisterID, mem: &mut Memory) -> Result<(), CpuError> {
         let r2 = r.clone();
-        let mut n = self.r_table_lookup(r2, mem)?;
-        if (n & 0b1) != 0 {
-            n >>= 1;
-            n |= 0x80;
-            self.set_carry_flag_on();
-        } else {
-            n >>= 1;
-            self.set_carry_flag_off();
-        }
-
-        self.zero_flag_check(n);
-        self.r_table_assign(r, n, mem)?;
+        let n = self.r_table_lookup(r2, mem)?;
+        let result = n.rotate_right(1);
+
+        self.shift_flag_checks(result, (n & 0b1) != 0);
+        self.r_table_assign(r, result, mem)?;
 
         Ok(())
     }
@@ -44,20 +39,12 @@ impl CPU {
         mem: &mut Memory,
     ) -> Result<(), CpuError> {
         let r2 = r.clone();
-        let mut n = self.r_table_lookup(r2, mem)?;
-        if (n >> 7) != 0 {
-            self.set_carry_flag_on();
-        } else {
-            self.set_carry_flag_off();
-        }
-
-        n <<= 1;
-        if c_flag(self.af) {
-            n |= 0b1;
-        }
+        let n = self.r_table_lookup(r2, mem)?;
+        let carry_bit = if self.flags().c() { 0b1 } else { 0b0 };
+        let result = (n << 1) | carry_bit;
 
-        self.zero_flag_check(n);
-        self.r_table_assign(r, n, mem)?;
+        self.shift_flag_checks(result, (n >> 7) != 0);
+        self.r_table_assign(r, result, mem)?;
 
         Ok(())
     }
@@ -67,20 +54,12 @@ impl CPU {
         mem: &mut Memory,
     ) -> Result<(), CpuError> {
         let r2 = r.clone();
-        let mut n = self.r_table_lookup(r2, mem)?;
-        if (n & 0b1) != 0 {
-            self.set_carry_flag_on();
-        } else {
-            self.set_carry_flag_off();
-        }
-
-        n >>= 1;
-        if c_flag(self.af) {
-            n |= 0x80;
-        }
+        let n = self.r_table_lookup(r2, mem)?;
+        let carry_bit = if self.flags().c() { 0x80 } else { 0x0 };
+        let result = (n >> 1) | carry_bit;
 
-        self.zero_flag_check(n);
-        self.r_table_assign(r, n, mem)?;
+        self.shift_flag_checks(result, (n & 0b1) != 0);
+        self.r_table_assign(r, result, mem)?;
 
         Ok(())
     }
@@ -92,14 +71,10 @@ impl CPU {
     ) -> Result<(), CpuError> {
         let r2 = r.clone();
         let n = self.r_table_lookup(r2, mem)?;
-        if (n >> 7) != 0 {
-            self.set_carry_flag_on();
-        } else {
-            self.set_carry_flag_off();
-        }
+        let result = n << 1;
 
-        self.zero_flag_check(n << 1);
-        self.r_table_assign(r, n << 1, mem)?;
+        self.shift_flag_checks(result, (n >> 7) != 0);
+        self.r_table_assign(r, result, mem)?;
 
         Ok(())
     }
@@ -110,14 +85,9 @@ impl CPU {
     ) -> Result<(), CpuError> {
         let r2 = r.clone();
         let n = self.r_table_lookup(r2, mem)? as i8;
-        if (n & 0b1) != 0 {
-            self.set_carry_flag_on();
-        } else {
-            self.set_carry_flag_off();
-        }
-
         let result = (n >> 1) as u8;
-        self.zero_flag_check(result);
+
+        self.shift_flag_checks(result, (n & 0b1) != 0);
         self.r_table_assign(r, result, mem)?;
 
         Ok(())
@@ -130,7 +100,7 @@ impl CPU {
         let nibble2 = n & 0x0f;
 
         let result = (nibble2 << 4) | nibble1;
-        self.zero_flag_check(result);
+        self.shift_flag_checks(result, false);
         self.r_table_assign(r, result, mem)?;
 
         Ok(())
@@ -139,14 +109,9 @@ impl CPU {
     pub fn shift_right_logical(&mut self, r: RegisterID, mem: &mut Memory) -> Result<(), CpuError> {
         let r2 = r.clone();
         let n = self.r_table_lookup(r2, mem)?;
-        if (n & 0b1) != 0 {
-            self.set_carry_flag_on();
-        } else {
-            self.set_carry_flag_off();
-        }
-
         let result = n >> 1;
-        self.zero_flag_check(result);
+
+        self.shift_flag_checks(result, (n & 0b1) != 0);
         self.r_table_assign(r, result, mem)?;
 
         Ok(())
@@ -155,61 +120,42 @@ impl CPU {
     pub fn complement_accumulator(&mut self) {
         let a = hi_byte(self.af);
         self.set_register_a(!a);
-        self.set_subtraction_flag_on();
-        self.set_halfcarry_flag_on();
+
+        // Z and C are left alone
+        let mut flags = self.flags();
+        flags.insert(Flags::N | Flags::H);
+        self.set_flags(flags);
+    }
+
+    // unlike their CB-prefixed counterparts, the accumulator
+    // rotates always reset Z
+    fn rotate_accumulator_flag_checks(&mut self, carry: bool) {
+        let mut flags = Flags::empty();
+        flags.set(Flags::C, carry);
+        self.set_flags(flags);
     }
 
     pub fn rotate_right_accumulator(&mut self) {
-        let mut a = hi_byte(self.af);
-        let rightmost_on = a & 0b1 != 0;
-
-        a >>= 1;
-        if rightmost_on {
-            self.set_carry_flag_on();
-            a |= 0x80;
-        } else {
-            self.set_carry_flag_off();
-        }
-
-        self.set_register_a(a);
+        let a = hi_byte(self.af);
+        self.rotate_accumulator_flag_checks((a & 0b1) != 0);
+        self.set_register_a(a.rotate_right(1));
     }
     pub fn rotate_left_accumulator(&mut self) {
-        let mut a = hi_byte(self.af);
-        let leftmost_on = a & 0x80 != 0;
-
-        a <<= 1;
-        if leftmost_on {
-            self.set_carry_flag_on();
-            a |= 0b1;
-        } else {
-            self.set_carry_flag_off();
-        }
-
-        self.set_register_a(a);
+        let a = hi_byte(self.af);
+        self.rotate_accumulator_flag_checks((a & 0x80) != 0);
+        self.set_register_a(a.rotate_left(1));
     }
 
     pub fn rotate_right_thru_carry_accumulator(&mut self) {
-        let carry_bit = if c_flag(self.af) { 0x80 } else { 0x0 };
-        let mut a = hi_byte(self.af);
-        if (a & 0b1) != 0 {
-            self.set_carry_flag_on();
-        } else {
-            self.set_carry_flag_off();
-        }
-
-        a = (a >> 1) | carry_bit;
-        self.set_register_a(a);
+        let carry_bit = if self.flags().c() { 0x80 } else { 0x0 };
+        let a = hi_byte(self.af);
+        self.rotate_accumulator_flag_checks((a & 0b1) != 0);
+        self.set_register_a((a >> 1) | carry_bit);
     }
     pub fn rotate_left_thru_carry_accumulator(&mut self) {
-        let carry_bit = if c_flag(self.af) { 0b1 } else { 0b0 };
-        let mut a = hi_byte(self.af);
-        if (a & 0x80) != 0 {
-            self.set_carry_flag_on();
-        } else {
-            self.set_carry_flag_off();
-        }
-
-        a = (a << 1) | carry_bit;
-        self.set_register_a(a);
+        let carry_bit = if self.flags().c() { 0b1 } else { 0b0 };
+        let a = hi_byte(self.af);
+        self.rotate_accumulator_flag_checks((a & 0x80) != 0);
+        self.set_register_a((a << 1) | carry_bit);
     }
 }
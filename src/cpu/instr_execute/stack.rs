@@ -0,0 +1,223 @@
+use crate::cpu::instructions::{FlagID, RegisterID};
+use crate::cpu::{cpuerror::CpuError, hi_byte, lo_byte, CPU};
+use crate::memory::Memory;
+
+impl CPU {
+    // the stack grows downwards, with the high byte pushed first
+    pub fn push_u16(&mut self, val: u16, mem: &mut Memory) {
+        self.sp = self.sp.wrapping_sub(1);
+        mem.write8(self.sp, hi_byte(val));
+        self.sp = self.sp.wrapping_sub(1);
+        mem.write8(self.sp, lo_byte(val));
+    }
+    pub fn pop_u16(&mut self, mem: &mut Memory) -> u16 {
+        let val = mem.read16(self.sp);
+        self.sp = self.sp.wrapping_add(2);
+
+        val
+    }
+
+    pub fn push(&mut self, r: RegisterID, mem: &mut Memory) -> Result<(), CpuError> {
+        let val = match r {
+            RegisterID::AF => self.af,
+            RegisterID::BC => self.bc,
+            RegisterID::DE => self.de,
+            RegisterID::HL => self.hl,
+            _ => return Err(CpuError::ReadingFromInvalidReg { r, pc: self.pc }),
+        };
+        self.push_u16(val, mem);
+
+        Ok(())
+    }
+    pub fn pop(&mut self, r: RegisterID, mem: &mut Memory) -> Result<(), CpuError> {
+        match r {
+            RegisterID::AF => {
+                let val = self.pop_u16(mem);
+                self.set_af(val);
+            }
+            RegisterID::BC => self.bc = self.pop_u16(mem),
+            RegisterID::DE => self.de = self.pop_u16(mem),
+            RegisterID::HL => self.hl = self.pop_u16(mem),
+            _ => return Err(CpuError::ReadingIntoInvalidReg { r, pc: self.pc }),
+        }
+
+        Ok(())
+    }
+
+    pub fn call(&mut self, nn: u16, mem: &mut Memory) {
+        // program counter already points to the next instruction
+        self.push_u16(self.pc, mem);
+        self.pc = nn;
+    }
+    pub fn call_conditional(&mut self, f: FlagID, nn: u16, mem: &mut Memory) -> bool {
+        let taken = self.condition_met(f);
+        if taken {
+            self.call(nn, mem);
+        }
+
+        taken
+    }
+
+    pub fn ret(&mut self, mem: &mut Memory) {
+        self.pc = self.pop_u16(mem);
+    }
+    pub fn ret_conditional(&mut self, f: FlagID, mem: &mut Memory) -> bool {
+        let taken = self.condition_met(f);
+        if taken {
+            self.ret(mem);
+        }
+
+        taken
+    }
+    pub fn ret_enable_interrupts(&mut self, mem: &mut Memory) {
+        // unlike EI, interrupts are enabled right away
+        self.ret(mem);
+        self.enable_interrupts();
+    }
+
+    pub fn restart(&mut self, arg: u8, mem: &mut Memory) {
+        self.call(arg as u16, mem);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::instructions::{FlagID, RegisterID};
+    use crate::cpu::testing::setup;
+
+    #[test]
+    fn push_writes_high_byte_first() {
+        let (mut cpu, mut mem) = setup(&[], 0x0200);
+        cpu.bc = 0x1234;
+        cpu.push(RegisterID::BC, &mut mem).unwrap();
+
+        assert_eq!(cpu.sp, 0xfffc);
+        assert_eq!(mem.read8(0xfffd), 0x12);
+        assert_eq!(mem.read8(0xfffc), 0x34);
+    }
+    #[test]
+    fn pop_reads_low_byte_first() {
+        let (mut cpu, mut mem) = setup(&[], 0x0200);
+        cpu.sp = 0xfffc;
+        mem.write8(0xfffc, 0x34);
+        mem.write8(0xfffd, 0x12);
+        cpu.pop(RegisterID::DE, &mut mem).unwrap();
+
+        assert_eq!(cpu.sp, 0xfffe);
+        assert_eq!(cpu.de, 0x1234);
+    }
+    #[test]
+    fn push_pop_roundtrip() {
+        let (mut cpu, mut mem) = setup(&[], 0x0200);
+        cpu.hl = 0xbeef;
+        cpu.push(RegisterID::HL, &mut mem).unwrap();
+        cpu.pop(RegisterID::BC, &mut mem).unwrap();
+
+        assert_eq!(cpu.bc, 0xbeef);
+        assert_eq!(cpu.sp, 0xfffe);
+    }
+    #[test]
+    fn pop_af_masks_low_nibble() {
+        let (mut cpu, mut mem) = setup(&[], 0x0200);
+        cpu.bc = 0x12ff;
+        cpu.push(RegisterID::BC, &mut mem).unwrap();
+        cpu.pop(RegisterID::AF, &mut mem).unwrap();
+
+        assert_eq!(cpu.af, 0x12f0);
+    }
+    #[test]
+    fn push_sp_invalid() {
+        let (mut cpu, mut mem) = setup(&[], 0x0200);
+        assert!(cpu.push(RegisterID::SP, &mut mem).is_err());
+    }
+
+    #[test]
+    fn call_pushes_return_address() {
+        let (mut cpu, mut mem) = setup(&[], 0x0200);
+        cpu.pc = 0x0203;
+        cpu.call(0x4000, &mut mem);
+
+        assert_eq!(cpu.pc, 0x4000);
+        assert_eq!(cpu.sp, 0xfffc);
+        assert_eq!(mem.read8(0xfffd), 0x02);
+        assert_eq!(mem.read8(0xfffc), 0x03);
+    }
+    #[test]
+    fn call_then_ret() {
+        let (mut cpu, mut mem) = setup(&[], 0x0200);
+        cpu.pc = 0x0203;
+        cpu.call(0x4000, &mut mem);
+        cpu.ret(&mut mem);
+
+        assert_eq!(cpu.pc, 0x0203);
+        assert_eq!(cpu.sp, 0xfffe);
+    }
+    #[test]
+    fn call_conditional_not_taken() {
+        let (mut cpu, mut mem) = setup(&[], 0x0200);
+        assert!(!cpu.flags().z());
+        assert!(!cpu.call_conditional(FlagID::Z, 0x4000, &mut mem));
+
+        assert_eq!(cpu.pc, 0x0200);
+        assert_eq!(cpu.sp, 0xfffe);
+    }
+    #[test]
+    fn call_conditional_taken() {
+        let (mut cpu, mut mem) = setup(&[], 0x0200);
+        assert!(cpu.call_conditional(FlagID::NZ, 0x4000, &mut mem));
+
+        assert_eq!(cpu.pc, 0x4000);
+        assert_eq!(cpu.sp, 0xfffc);
+    }
+    #[test]
+    fn ret_conditional_not_taken() {
+        let (mut cpu, mut mem) = setup(&[], 0x0200);
+        cpu.call(0x4000, &mut mem);
+        assert!(!cpu.flags().c());
+        assert!(!cpu.ret_conditional(FlagID::C, &mut mem));
+
+        assert_eq!(cpu.pc, 0x4000);
+        assert_eq!(cpu.sp, 0xfffc);
+    }
+    #[test]
+    fn ret_conditional_taken() {
+        let (mut cpu, mut mem) = setup(&[], 0x0200);
+        cpu.call(0x4000, &mut mem);
+        assert!(cpu.ret_conditional(FlagID::NC, &mut mem));
+
+        assert_eq!(cpu.pc, 0x0200);
+        assert_eq!(cpu.sp, 0xfffe);
+    }
+    #[test]
+    fn reti_enables_interrupts() {
+        let (mut cpu, mut mem) = setup(&[], 0x0200);
+        cpu.call(0x0040, &mut mem);
+        cpu.ret_enable_interrupts(&mut mem);
+
+        assert_eq!(cpu.pc, 0x0200);
+        assert!(cpu.interrupts_enabled);
+    }
+
+    #[test]
+    fn rst_jumps_to_vector() {
+        for arg in (0..8).map(|y| y << 3) {
+            let (mut cpu, mut mem) = setup(&[], 0x0200);
+            cpu.pc = 0x0201;
+            cpu.restart(arg, &mut mem);
+
+            assert_eq!(cpu.pc, arg as u16);
+            assert_eq!(cpu.pop_u16(&mut mem), 0x0201);
+        }
+    }
+
+    #[test]
+    fn stack_pointer_wraps() {
+        let (mut cpu, mut mem) = setup(&[], 0x0200);
+        cpu.sp = 0x0000;
+        cpu.push_u16(0xabcd, &mut mem);
+
+        assert_eq!(cpu.sp, 0xfffe);
+        assert_eq!(cpu.pop_u16(&mut mem), 0xabcd);
+        assert_eq!(cpu.sp, 0x0000);
+    }
+}
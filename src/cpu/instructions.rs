@@ -101,6 +101,7 @@ impl FlagID {
     }
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Instruction {
     NOP,
     STOP,
@@ -205,9 +206,10 @@ impl Instruction {
         ((bytes >> 8) & 0xff) as u8
     }
     fn second_and_third_bytes_reversed(bytes: u32) -> u16 {
-        let hi = ((bytes >> 16) & 0xff) as u16;
-        let lo = ((bytes >> 8) & 0xff) as u16;
-        (lo << 8) & hi
+        // immediates are little-endian, so the third byte is the high one
+        let lo = ((bytes >> 16) & 0xff) as u16;
+        let hi = ((bytes >> 8) & 0xff) as u16;
+        (hi << 8) | lo
     }
 
     // "x", "y", "z", "p", and "q" below are referencing the following document:
@@ -225,7 +227,151 @@ impl Instruction {
         Self::opcode_y(opcode) >> 1
     }
     fn opcode_q(opcode: u8) -> bool {
-        (Self::opcode_y(opcode) % 0b001) == 0b001
+        (Self::opcode_y(opcode) & 0b001) == 0b001
+    }
+
+    /// Number of bytes (opcode included) taken up by the instruction
+    /// starting with `opcode`. Only the first byte is needed to know this,
+    /// so the CPU can fetch exactly as many operand bytes as it needs.
+    pub fn length(opcode: u8) -> u16 {
+        let y = Self::opcode_y(opcode);
+        let z = Self::opcode_z(opcode);
+        let q = Self::opcode_q(opcode);
+
+        match Self::opcode_x(opcode) {
+            0 => match z {
+                0 => match y {
+                    0 => 1, // NOP
+                    1 => 3, // LD (nn), SP
+                    _ => 2, // STOP, JR d, JR cc[y-4], d
+                },
+                1 if q => 1, // ADD HL, rp[p]
+                1 => 3,      // LD rp[p], nn
+                6 => 2,      // LD r[y], n
+                _ => 1,
+            },
+            1 | 2 => 1,
+            _ => match z {
+                0 if y < 4 => 1,                // RET cc[y]
+                0 => 2,                         // LDH (n), A / ADD SP, d / LDH A, (n) / LD HL, SP+d
+                2 if y < 4 => 3,                // JP cc[y], nn
+                2 if (y == 5) || (y == 7) => 3, // LD (nn), A / LD A, (nn)
+                3 if y == 0 => 3,               // JP nn
+                3 if y == 1 => 2,               // CB prefix
+                4 if y < 4 => 3,                // CALL cc[y], nn
+                5 if q && (y == 1) => 3,        // CALL nn
+                6 => 2,                         // alu[y] n
+                _ => 1,
+            },
+        }
+    }
+
+    /// Number of M-cycles (4 clock ticks each) the instruction takes to execute.
+    /// Conditional jumps, calls, and returns take longer when the branch is taken.
+    pub fn cycles(&self, branch_taken: bool) -> u8 {
+        // (HL) operands cost an extra memory access over plain registers
+        let hl_cost = |r: &RegisterID, reg: u8, hl: u8| {
+            if *r == RegisterID::HLaddress {
+                hl
+            } else {
+                reg
+            }
+        };
+
+        match self {
+            Instruction::NOP
+            | Instruction::STOP
+            | Instruction::HALT
+            | Instruction::ILLEGAL
+            | Instruction::RLCA
+            | Instruction::RRCA
+            | Instruction::RLA
+            | Instruction::RRA
+            | Instruction::DAA
+            | Instruction::CPL
+            | Instruction::SCF
+            | Instruction::CCF
+            | Instruction::DI
+            | Instruction::EI
+            | Instruction::JumpToHL => 1,
+
+            Instruction::Load16 { r, .. } => {
+                // LD A, (nn) also has to read from memory
+                if *r == RegisterID::A {
+                    4
+                } else {
+                    3
+                }
+            }
+            Instruction::Load8 { r, .. } => hl_cost(r, 2, 3),
+            Instruction::LoadReg16 { .. } => 2,
+            Instruction::LoadReg8 { r1, r2 } => {
+                if (*r1 == RegisterID::HLaddress) || (*r2 == RegisterID::HLaddress) {
+                    2
+                } else {
+                    1
+                }
+            }
+            Instruction::LoadSPToHLWithOffset { .. } => 3,
+            Instruction::LoadFF00PlusC => 2,
+            Instruction::LoadFF00PlusImmediate { .. } => 3,
+
+            Instruction::StoreFF00Plus { .. } => 3,
+            Instruction::StoreReg { .. } => 5, // LD (nn), SP
+            Instruction::StoreImmediate { .. } => 4,
+            Instruction::StoreFF00PlusC => 2,
+
+            Instruction::Jump { .. } => 4,
+            Instruction::JumpConditional { .. } => 3 + branch_taken as u8,
+            Instruction::JR { .. } => 3,
+            Instruction::JumpRegConditional { .. } => 2 + branch_taken as u8,
+
+            Instruction::RLC { r }
+            | Instruction::RRC { r }
+            | Instruction::RL { r }
+            | Instruction::RR { r }
+            | Instruction::SLA { r }
+            | Instruction::SRA { r }
+            | Instruction::SWAP { r }
+            | Instruction::SRL { r }
+            | Instruction::RES { r, .. }
+            | Instruction::SET { r, .. } => hl_cost(r, 2, 4),
+            Instruction::BIT { r, .. } => hl_cost(r, 2, 3),
+
+            Instruction::AddRegisters { .. } => 2,
+            Instruction::AddSigned { .. } => 4,
+
+            Instruction::DEC8b { r } | Instruction::INC8b { r } => hl_cost(r, 1, 3),
+            Instruction::DEC16b { .. } | Instruction::INC16b { .. } => 2,
+            Instruction::AddHLAndR16 { .. } => 2,
+
+            Instruction::RET { .. } => 2 + 3 * branch_taken as u8,
+            Instruction::RETNoParam | Instruction::RETI => 4,
+            Instruction::POP { .. } => 3,
+
+            Instruction::CallConditional { .. } => 3 + 3 * branch_taken as u8,
+            Instruction::Call { .. } => 6,
+            Instruction::PUSH { .. } => 4,
+            Instruction::RST { .. } => 4,
+
+            Instruction::AddImmediate { .. }
+            | Instruction::AdcImmediate { .. }
+            | Instruction::SubImmediate { .. }
+            | Instruction::SbcImmediate { .. }
+            | Instruction::AndImmediate { .. }
+            | Instruction::XorImmediate { .. }
+            | Instruction::OrImmediate { .. }
+            | Instruction::CpImmediate { .. } => 2,
+
+            Instruction::AddRegister { r }
+            | Instruction::AdcRegister { r }
+            | Instruction::SubRegister { r }
+            | Instruction::SbcRegister { r }
+            | Instruction::AndRegister { r }
+            | Instruction::XorRegister { r }
+            | Instruction::OrRegister { r }
+            | Instruction::CpRegister { r } => hl_cost(r, 1, 2),
+        }
     }
 
     pub fn from_bytes(bytes: u32) -> Instruction {
@@ -549,3 +695,112 @@ impl Instruction {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::instructions::{FlagID, Instruction, RegisterID};
+
+    macro_rules! length_test {
+        ($name:tt, $opcode:expr, $expected:expr) => {
+            #[test]
+            fn $name() {
+                assert_eq!(Instruction::length($opcode), $expected);
+            }
+        };
+    }
+    length_test!(nop_length, 0x00, 1);
+    length_test!(ld_nn_sp_length, 0x08, 3);
+    length_test!(stop_length, 0x10, 2);
+    length_test!(jr_length, 0x18, 2);
+    length_test!(jr_cc_length, 0x20, 2);
+    length_test!(ld_rp_nn_length, 0x21, 3);
+    length_test!(add_hl_rp_length, 0x09, 1);
+    length_test!(ld_r_n_length, 0x3e, 2);
+    length_test!(ld_r_r_length, 0x41, 1);
+    length_test!(halt_length, 0x76, 1);
+    length_test!(alu_r_length, 0xa8, 1);
+    length_test!(ret_cc_length, 0xc0, 1);
+    length_test!(ldh_n_a_length, 0xe0, 2);
+    length_test!(add_sp_d_length, 0xe8, 2);
+    length_test!(ld_hl_sp_d_length, 0xf8, 2);
+    length_test!(pop_length, 0xc1, 1);
+    length_test!(jp_cc_length, 0xc2, 3);
+    length_test!(ld_c_a_length, 0xe2, 1);
+    length_test!(ld_nn_a_length, 0xea, 3);
+    length_test!(ld_a_nn_length, 0xfa, 3);
+    length_test!(jp_length, 0xc3, 3);
+    length_test!(cb_prefix_length, 0xcb, 2);
+    length_test!(di_length, 0xf3, 1);
+    length_test!(call_cc_length, 0xc4, 3);
+    length_test!(push_length, 0xc5, 1);
+    length_test!(call_length, 0xcd, 3);
+    length_test!(alu_n_length, 0xfe, 2);
+    length_test!(rst_length, 0xff, 1);
+    length_test!(illegal_length, 0xd3, 1);
+
+    #[test]
+    fn immediate_u16_is_little_endian() {
+        assert_eq!(
+            Instruction::from_bytes(0xc3_50_01_00),
+            Instruction::Jump { nn: 0x0150 }
+        );
+        assert_eq!(
+            Instruction::from_bytes(0x21_34_12_00),
+            Instruction::Load16 {
+                r: RegisterID::HL,
+                nn: 0x1234
+            }
+        );
+    }
+    #[test]
+    fn add_hl_rp_decoded() {
+        assert_eq!(
+            Instruction::from_bytes(0x19_00_00_00),
+            Instruction::AddHLAndR16 { r: RegisterID::DE }
+        );
+    }
+    #[test]
+    fn relative_jump_operand_is_signed() {
+        assert_eq!(
+            Instruction::from_bytes(0x20_fe_00_00),
+            Instruction::JumpRegConditional {
+                f: FlagID::NZ,
+                d: -2
+            }
+        );
+    }
+    #[test]
+    fn call_decoded() {
+        assert_eq!(
+            Instruction::from_bytes(0xcd_00_40_00),
+            Instruction::Call { nn: 0x4000 }
+        );
+    }
+
+    #[test]
+    fn conditional_cycles_depend_on_branch() {
+        let call = Instruction::CallConditional {
+            f: FlagID::Z,
+            nn: 0,
+        };
+        assert_eq!(call.cycles(false), 3);
+        assert_eq!(call.cycles(true), 6);
+
+        let ret = Instruction::RET { f: FlagID::NC };
+        assert_eq!(ret.cycles(false), 2);
+        assert_eq!(ret.cycles(true), 5);
+    }
+    #[test]
+    fn bit_hladdr_cycles() {
+        let bit = Instruction::BIT {
+            y: 0,
+            r: RegisterID::HLaddress,
+        };
+        let set = Instruction::SET {
+            y: 0,
+            r: RegisterID::HLaddress,
+        };
+        assert_eq!(bit.cycles(false), 3);
+        assert_eq!(set.cycles(false), 4);
+    }
+}
@@ -0,0 +1,219 @@
+use crate::cpu::{hi_byte, lo_byte, CPU};
+use crate::memory::Memory;
+
+// https://gbdev.io/pandocs/Interrupts.html
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad,
+}
+
+impl Interrupt {
+    // in order of priority, which is also their order in IE and IF
+    const ALL: [Interrupt; 5] = [
+        Interrupt::VBlank,
+        Interrupt::LcdStat,
+        Interrupt::Timer,
+        Interrupt::Serial,
+        Interrupt::Joypad,
+    ];
+
+    /// Bit this interrupt occupies in both IE and IF.
+    pub fn bit(&self) -> u8 {
+        match self {
+            Interrupt::VBlank => 0b00001,
+            Interrupt::LcdStat => 0b00010,
+            Interrupt::Timer => 0b00100,
+            Interrupt::Serial => 0b01000,
+            Interrupt::Joypad => 0b10000,
+        }
+    }
+
+    /// Address the CPU jumps to when servicing this interrupt.
+    pub fn vector(&self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x0040,
+            Interrupt::LcdStat => 0x0048,
+            Interrupt::Timer => 0x0050,
+            Interrupt::Serial => 0x0058,
+            Interrupt::Joypad => 0x0060,
+        }
+    }
+
+    /// Picks the interrupt that gets serviced first out of a set of
+    /// pending ones, i.e. the lowest bit set.
+    pub fn highest_priority(pending: u8) -> Option<Interrupt> {
+        Self::ALL.into_iter().find(|i| (pending & i.bit()) != 0)
+    }
+}
+
+impl CPU {
+    pub fn pending_interrupts(&self, mem: &Memory) -> u8 {
+        mem.interrupt_enable() & mem.interrupt_flag()
+    }
+
+    /// Services the highest priority interrupt if IME is set and one is pending,
+    /// returning the M-cycles spent doing so.
+    pub fn handle_interrupts(&mut self, mem: &mut Memory) -> Option<u8> {
+        if !self.interrupts_enabled {
+            return None;
+        }
+        Interrupt::highest_priority(self.pending_interrupts(mem))?;
+
+        self.interrupts_enabled = false;
+        self.interrupts_scheduled = false;
+
+        // the interrupt to service is only settled after the high byte of PC is pushed,
+        // so if that push lands on IE and clears the pending bit, PC ends up at 0x0000
+        self.sp = self.sp.wrapping_sub(1);
+        mem.write8(self.sp, hi_byte(self.pc));
+        let return_address = self.pc;
+        match Interrupt::highest_priority(self.pending_interrupts(mem)) {
+            Some(interrupt) => {
+                mem.clear_interrupt(interrupt);
+                self.pc = interrupt.vector();
+            }
+            None => self.pc = 0x0000,
+        }
+        self.sp = self.sp.wrapping_sub(1);
+        mem.write8(self.sp, lo_byte(return_address));
+
+        Some(5)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::interrupts::Interrupt;
+    use crate::cpu::testing::setup;
+
+    #[test]
+    fn vectors() {
+        assert_eq!(Interrupt::VBlank.vector(), 0x40);
+        assert_eq!(Interrupt::LcdStat.vector(), 0x48);
+        assert_eq!(Interrupt::Timer.vector(), 0x50);
+        assert_eq!(Interrupt::Serial.vector(), 0x58);
+        assert_eq!(Interrupt::Joypad.vector(), 0x60);
+    }
+    #[test]
+    fn priority_is_lowest_bit() {
+        assert_eq!(Interrupt::highest_priority(0), None);
+        assert_eq!(
+            Interrupt::highest_priority(0b11111),
+            Some(Interrupt::VBlank)
+        );
+        assert_eq!(Interrupt::highest_priority(0b10100), Some(Interrupt::Timer));
+        assert_eq!(
+            Interrupt::highest_priority(0b10000),
+            Some(Interrupt::Joypad)
+        );
+        // upper bits of IF/IE aren't connected to anything
+        assert_eq!(Interrupt::highest_priority(0b11100000), None);
+    }
+
+    #[test]
+    fn dispatch_pushes_pc_and_jumps() {
+        let (mut cpu, mut mem) = setup(&[0x00], 0x0200);
+        cpu.interrupts_enabled = true;
+        mem.write8(0xffff, 0b00100);
+        mem.request_interrupt(Interrupt::Timer);
+
+        assert_eq!(cpu.fetch_decode_execute(&mut mem).unwrap(), 5);
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!(cpu.sp, 0xfffc);
+        assert_eq!(mem.read8(0xfffd), 0x02);
+        assert_eq!(mem.read8(0xfffc), 0x00);
+        assert!(!cpu.interrupts_enabled);
+        assert_eq!(mem.interrupt_flag(), 0);
+    }
+    #[test]
+    fn dispatch_services_one_at_a_time() {
+        let (mut cpu, mut mem) = setup(&[0x00], 0x0200);
+        cpu.interrupts_enabled = true;
+        mem.write8(0xffff, 0b11111);
+        mem.request_interrupt(Interrupt::Joypad);
+        mem.request_interrupt(Interrupt::LcdStat);
+
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0048);
+        assert_eq!(mem.interrupt_flag(), Interrupt::Joypad.bit());
+    }
+    #[test]
+    fn no_dispatch_when_ime_off() {
+        let (mut cpu, mut mem) = setup(&[0x00], 0x0200);
+        mem.write8(0xffff, 0b00001);
+        mem.request_interrupt(Interrupt::VBlank);
+
+        assert_eq!(cpu.fetch_decode_execute(&mut mem).unwrap(), 1);
+        assert_eq!(cpu.pc, 0x0201);
+        assert_eq!(mem.interrupt_flag(), Interrupt::VBlank.bit());
+    }
+    #[test]
+    fn no_dispatch_when_not_enabled_in_ie() {
+        let (mut cpu, mut mem) = setup(&[0x00], 0x0200);
+        cpu.interrupts_enabled = true;
+        mem.write8(0xffff, 0b11110);
+        mem.request_interrupt(Interrupt::VBlank);
+
+        assert_eq!(cpu.fetch_decode_execute(&mut mem).unwrap(), 1);
+        assert_eq!(cpu.pc, 0x0201);
+    }
+    #[test]
+    fn push_over_ie_cancels_dispatch() {
+        let (mut cpu, mut mem) = setup(&[0x00], 0x0200);
+        cpu.interrupts_enabled = true;
+        cpu.sp = 0x0000; // high byte of PC (0x02) gets pushed onto IE
+        mem.write8(0xffff, 0b00001);
+        mem.request_interrupt(Interrupt::VBlank);
+
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0000);
+        assert_eq!(mem.interrupt_flag(), Interrupt::VBlank.bit());
+    }
+
+    #[test]
+    fn ei_is_delayed_by_one_instruction() {
+        // EI, NOP, NOP
+        let (mut cpu, mut mem) = setup(&[0xfb, 0x00, 0x00], 0x0200);
+        mem.write8(0xffff, 0b00001);
+        mem.request_interrupt(Interrupt::VBlank);
+
+        cpu.fetch_decode_execute(&mut mem).unwrap(); // EI
+        assert!(!cpu.interrupts_enabled);
+        cpu.fetch_decode_execute(&mut mem).unwrap(); // NOP still runs
+        assert_eq!(cpu.pc, 0x0202);
+        assert!(cpu.interrupts_enabled);
+        cpu.fetch_decode_execute(&mut mem).unwrap(); // interrupt taken
+        assert_eq!(cpu.pc, 0x0040);
+    }
+    #[test]
+    fn ei_then_di_never_enables() {
+        // EI, DI, NOP
+        let (mut cpu, mut mem) = setup(&[0xfb, 0xf3, 0x00], 0x0200);
+        mem.write8(0xffff, 0b00001);
+        mem.request_interrupt(Interrupt::VBlank);
+
+        for _ in 0..3 {
+            cpu.fetch_decode_execute(&mut mem).unwrap();
+        }
+        assert!(!cpu.interrupts_enabled);
+        assert_eq!(cpu.pc, 0x0203);
+    }
+    #[test]
+    fn reti_enables_immediately() {
+        // RETI, returning to a NOP at 0x0300
+        let (mut cpu, mut mem) = setup(&[0xd9], 0x0200);
+        cpu.push_u16(0x0300, &mut mem);
+        mem.write8(0xffff, 0b00001);
+        mem.request_interrupt(Interrupt::VBlank);
+
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert!(cpu.interrupts_enabled);
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0040);
+    }
+}
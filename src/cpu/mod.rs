@@ -1,27 +1,20 @@
+use flags::Flags;
 use instructions::{Instruction, RegisterID};
+use interrupts::Interrupt;
 
 use crate::memory::Memory;
+use crate::model::Model;
 
 pub mod cpuerror;
+mod flags;
 mod instr_execute;
 mod instructions;
+pub mod interrupts;
+#[cfg(test)]
+mod testing;
 
 use cpuerror::CpuError;
 
-fn z_flag(af: u16) -> bool {
-    ((af >> 6) & 0b1) == 0b1
-}
-fn nz_flag(af: u16) -> bool {
-    !z_flag(af)
-}
-
-fn c_flag(af: u16) -> bool {
-    ((af >> 3) & 0b1) == 0b1
-}
-fn nc_flag(af: u16) -> bool {
-    !c_flag(af)
-}
-
 fn hi_byte(x: u16) -> u8 {
     (x >> 8) as u8
 }
@@ -37,7 +30,14 @@ pub struct CPU {
     sp: u16, // stack pointer
     pc: u16, // program counter/pointer
 
-    interrupts_enabled: bool,
+    interrupts_enabled: bool,   // IME
+    interrupts_scheduled: bool, // set by EI, IME is set after the next instruction
+
+    halted: bool,   // HALT, waiting for an interrupt
+    halt_bug: bool, // next opcode fetch doesn't increment PC
+    stopped: bool,  // STOP, waiting for a button press
+
+    cycles: u64, // M-cycles elapsed since power on
 }
 
 impl CPU {
@@ -50,72 +50,105 @@ impl CPU {
             sp: 0,
             pc,
             interrupts_enabled,
+            interrupts_scheduled: false,
+            halted: false,
+            halt_bug: false,
+            stopped: false,
+            cycles: 0,
         };
 
-        if header_checksum == 0 {
-            cpu.set_carry_flag_off();
-            cpu.set_halfcarry_flag_off();
-        } else {
-            cpu.set_carry_flag_on();
-            cpu.set_halfcarry_flag_on();
+        if header_checksum != 0 {
+            cpu.set_flags(Flags::H | Flags::C);
         }
 
         cpu
     }
 
-    fn set_carry_flag_on(&mut self) {
-        let flags = lo_byte(self.af) | 0b1000;
-        self.af &= 0xff00;
-        self.af |= flags as u16;
-    }
-    fn set_carry_flag_off(&mut self) {
-        let flags = lo_byte(self.af) & 0b0111;
-        self.af &= 0xff00;
-        self.af |= flags as u16;
-    }
-    fn complement_carry_flag(&mut self) {
-        if c_flag(self.af) {
-            self.set_carry_flag_off();
+    /// Builds a CPU in the state the boot ROM leaves it in on the given model,
+    /// about to execute the cartridge entry point at 0x0100.
+    pub fn post_boot(model: Model, mem: &Memory) -> Self {
+        // https://gbdev.io/pandocs/Power_Up_Sequence.html#cpu-registers
+        let mut cpu = CPU::new(0x0100, false, 0);
+        cpu.sp = 0xfffe;
+
+        let header = &mem.header;
+        let checksum_flags = if header.header_checksum == 0 {
+            Flags::Z
         } else {
-            self.set_carry_flag_on();
+            Flags::Z | Flags::H | Flags::C
+        };
+
+        match model {
+            Model::Dmg0 => {
+                cpu.set_af(0x0100);
+                cpu.bc = 0xff13;
+                cpu.de = 0x00c1;
+                cpu.hl = 0x8403;
+            }
+            Model::Dmg | Model::Mgb => {
+                let a: u16 = if model == Model::Mgb { 0xff } else { 0x01 };
+                cpu.set_af((a << 8) | checksum_flags.bits() as u16);
+                cpu.bc = 0x0013;
+                cpu.de = 0x00d8;
+                cpu.hl = 0x014d;
+            }
+            Model::Sgb | Model::Sgb2 => {
+                let a: u16 = if model == Model::Sgb2 { 0xff } else { 0x01 };
+                cpu.set_af(a << 8);
+                cpu.bc = 0x0014;
+                cpu.de = 0x0000;
+                cpu.hl = 0xc060;
+            }
+            Model::Cgb | Model::Agb => {
+                let mut b = 0x00;
+                if header.cgb_support() {
+                    cpu.de = 0xff56;
+                    cpu.hl = 0x000d;
+                } else {
+                    // the boot ROM picks a palette for DMG games from the title
+                    if header.nintendo_licensed() {
+                        b = header.title_checksum();
+                    }
+                    cpu.de = 0x0008;
+                    cpu.hl = if b == 0x43 || b == 0x58 {
+                        0x991a
+                    } else {
+                        0x007c
+                    };
+                }
+
+                let mut flags = Flags::Z;
+                if model == Model::Agb {
+                    // the AGB boot ROM ends with an extra INC B
+                    flags = Flags::empty();
+                    flags.set(Flags::Z, b == 0xff);
+                    flags.set(Flags::H, (b & 0x0f) == 0x0f);
+                    b = b.wrapping_add(1);
+                }
+                cpu.set_af(0x1100 | flags.bits() as u16);
+                cpu.bc = (b as u16) << 8;
+            }
         }
-    }
 
-    fn set_zero_flag_on(&mut self) {
-        let flags = lo_byte(self.af) | 0b1000000;
-        self.af &= 0xff00;
-        self.af |= flags as u16;
-    }
-    fn set_zero_flag_off(&mut self) {
-        let flags = lo_byte(self.af) & 0b0111111;
-        self.af &= 0xff00;
-        self.af |= flags as u16;
+        cpu
     }
 
-    fn set_halfcarry_flag_on(&mut self) {
-        let flags = lo_byte(self.af) | 0b10000;
-        self.af &= 0xff00;
-        self.af |= flags as u16;
-    }
-    fn set_halfcarry_flag_off(&mut self) {
-        let flags = lo_byte(self.af) & 0b01111;
-        self.af &= 0xff00;
-        self.af |= flags as u16;
+    fn flags(&self) -> Flags {
+        Flags::from_bits(lo_byte(self.af))
     }
-
-    fn set_subtraction_flag_on(&mut self) {
-        let flags = lo_byte(self.af) | 0b100000;
+    fn set_flags(&mut self, flags: Flags) {
         self.af &= 0xff00;
-        self.af |= flags as u16;
+        self.af |= flags.bits() as u16;
     }
-    fn set_subtraction_flag_off(&mut self) {
-        let flags = lo_byte(self.af) & 0b011111;
-        self.af &= 0xff00;
-        self.af |= flags as u16;
+    fn set_flag(&mut self, flag: Flags, on: bool) {
+        let mut flags = self.flags();
+        flags.set(flag, on);
+        self.set_flags(flags);
     }
 
-    fn reset_flags(&mut self) {
-        self.af &= 0xff00;
+    fn set_af(&mut self, val: u16) {
+        // lower nibble of F isn't backed by anything
+        self.af = val & 0xfff0;
     }
 
     fn registerid_to_u16(&mut self, r: RegisterID) -> u16 {
@@ -176,7 +209,7 @@ impl CPU {
             RegisterID::E => lo_byte(self.de),
             RegisterID::H => hi_byte(self.hl),
             RegisterID::L => lo_byte(self.hl),
-            RegisterID::HLaddress => mem[self.hl as usize],
+            RegisterID::HLaddress => mem.read8(self.hl),
 
             _ => return Err(CpuError::ReadingFromInvalidReg { r, pc: self.pc }),
         };
@@ -211,7 +244,7 @@ impl CPU {
                 self.hl &= 0xff00;
                 self.hl |= val as u16;
             }
-            RegisterID::HLaddress => mem[self.hl as usize] = val,
+            RegisterID::HLaddress => mem.write8(self.hl, val),
 
             _ => return Err(CpuError::ReadingIntoInvalidReg { r, pc: self.pc }),
         }
@@ -224,12 +257,46 @@ impl CPU {
         self.af |= (new_val as u16) << 8;
     }
 
-    pub fn fetch_decode_execute(&mut self, mem: &mut Memory) -> Result<(), CpuError> {
-        let bytes = self.fetch_instr_u32(mem)?;
+    /// Runs a single instruction, returning how many M-cycles it took
+    /// so the rest of the hardware can be stepped by the same amount.
+    pub fn fetch_decode_execute(&mut self, mem: &mut Memory) -> Result<u8, CpuError> {
+        // in low power modes nothing runs, but time still passes for the other hardware.
+        // there's no joypad yet, so a requested joypad interrupt stands in for a button press
+        if self.stopped && ((mem.interrupt_flag() & Interrupt::Joypad.bit()) == 0) {
+            self.cycles += 1;
+            return Ok(1);
+        }
+        self.stopped = false;
+        // HALT ends once any interrupt is pending, even if IME is off
+        if self.halted && (self.pending_interrupts(mem) == 0) {
+            self.cycles += 1;
+            return Ok(1);
+        }
+        self.halted = false;
+
+        if let Some(cycles) = self.handle_interrupts(mem) {
+            self.cycles += cycles as u64;
+            return Ok(cycles);
+        }
+
+        // an EI right before this instruction only takes effect once it's done
+        let enable_interrupts = self.interrupts_scheduled;
+
+        let bytes = self.fetch_instr(mem)?;
         let instr = Instruction::from_bytes(bytes);
-        self.execute(instr, mem)?;
+        let cycles = self.execute(instr, mem)?;
+        self.cycles += cycles as u64;
 
-        Ok(())
+        if enable_interrupts && self.interrupts_scheduled {
+            self.interrupts_scheduled = false;
+            self.interrupts_enabled = true;
+        }
+
+        Ok(cycles)
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
     }
 
     fn fetch_pc_u8(&mut self, mem: &Memory) -> Result<u8, CpuError> {
@@ -237,288 +304,481 @@ impl CPU {
             // possibly change to be more general for other fetches
             Err(CpuError::FetchError { pc: self.pc })
         } else {
-            let result = mem[self.pc as usize];
+            let result = mem.read8(self.pc);
             self.pc += 1;
             Ok(result)
         }
     }
-    fn fetch_instr_u32(&mut self, mem: &Memory) -> Result<u32, CpuError> {
-        // always increments program counter by 3
-        Ok(((self.fetch_pc_u8(mem)? as u32) << 24)
-            | ((self.fetch_pc_u8(mem)? as u32) << 16)
-            | ((self.fetch_pc_u8(mem)? as u32) << 8))
+    fn fetch_instr(&mut self, mem: &Memory) -> Result<u32, CpuError> {
+        // increments program counter by the length of the instruction,
+        // packing the bytes in the same layout `Instruction::from_bytes` expects.
+        // bytes past the end of the instruction are left as zero
+        let opcode = self.fetch_pc_u8(mem)?;
+        if self.halt_bug {
+            self.pc = self.pc.wrapping_sub(1);
+            self.halt_bug = false;
+        }
+        let mut bytes = (opcode as u32) << 24;
+        for i in 1..Instruction::length(opcode) {
+            bytes |= (self.fetch_pc_u8(mem)? as u32) << (24 - 8 * i);
+        }
+
+        Ok(bytes)
     }
 
-    fn execute(&mut self, instr: Instruction, mem: &mut Memory) -> Result<(), CpuError> {
-        // This function is kind of smelly since it decreases the program counter after determining the instruction
-        // but the motivation is that instructions should store data critical to the operation,
-        // and do that in one call to `Instruction::from_bytes`.
-        // The other alternative would be to repeatedly call that
-        // and potentially lose information within the opcodes themselves
+    fn execute(&mut self, instr: Instruction, mem: &mut Memory) -> Result<u8, CpuError> {
+        // The program counter already points past the instruction (and its operands) at this point.
         // All instructions implementations can be found in the instr_execute submodule
+        let cycles_not_taken = instr.cycles(false);
+        let cycles_taken = instr.cycles(true);
+        let mut branch_taken = false;
+
         match instr {
-            Instruction::NOP => self.pc -= 2, // no operation
+            Instruction::NOP => {} // no operation
             Instruction::ILLEGAL => {
-                self.pc -= 3;
-                return Err(CpuError::IllegalInstruction { pc: self.pc });
+                // illegal opcodes are all a single byte
+                return Err(CpuError::IllegalInstruction { pc: self.pc - 1 });
             }
 
             Instruction::Load16 { r, nn } => {
-                self.pc -= 0;
-                self.load_immediate16(r, nn)?;
+                self.load_immediate16(r, nn, mem)?;
             }
             Instruction::Load8 { r, n } => {
-                self.pc -= 1;
                 self.load_immediate8(r, n, mem)?;
             }
             Instruction::LoadFF00PlusImmediate { n } => {
-                self.pc -= 1;
                 self.load_ff00_plus_n(mem, n);
             }
             Instruction::LoadReg16 { r1, r2 } => {
-                self.pc -= 2;
                 self.load_registers16(r1, r2, mem)?;
             }
             Instruction::LoadReg8 { r1, r2 } => {
-                self.pc -= 2;
                 self.load_registers8(r1, r2, mem)?;
             }
             Instruction::LoadSPToHLWithOffset { d } => {
-                self.pc -= 1;
-                self.load_sp_to_hl_with_offset(mem, d)?;
+                self.load_sp_to_hl_with_offset(d);
             }
             Instruction::LoadFF00PlusC => {
-                self.pc -= 2;
                 self.load_ff00_plus_c(mem);
             }
 
-            Instruction::StoreFF00Plus { r, n } => self.pc -= 1,
-            Instruction::StoreReg { r1, loc } => self.pc -= 0,
-            Instruction::StoreImmediate { loc } => self.pc -= 0,
-            Instruction::StoreFF00PlusC => self.pc -= 2,
+            // A is the only register encoded for LDH (n), A
+            Instruction::StoreFF00Plus { n, .. } => self.store_ff00_plus_n(mem, n),
+            Instruction::StoreReg { r1, loc } => self.store_register(r1, loc, mem)?,
+            Instruction::StoreImmediate { loc } => self.store_immediate(mem, loc),
+            Instruction::StoreFF00PlusC => self.store_ff00_plus_c(mem),
 
-            // really no need to change the program counter prior to some jumps
             Instruction::Jump { nn } => self.jump(nn),
-            Instruction::JumpConditional { f, nn } => self.jump_conditional(f, nn),
+            Instruction::JumpConditional { f, nn } => branch_taken = self.jump_conditional(f, nn),
             Instruction::JR { d } => self.jump_reg(d),
             Instruction::JumpRegConditional { f, d } => {
-                self.pc -= 1;
-                self.jump_reg_conditional(f, d);
+                branch_taken = self.jump_reg_conditional(f, d);
             }
             Instruction::JumpToHL => self.jump_to_hl(),
 
             // CB-prefixed
             Instruction::RLC { r } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.rotate_left(r, mem)?;
             }
             Instruction::RRC { r } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.rotate_right(r, mem)?;
             }
             Instruction::RL { r } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.rotate_left_thru_carry(r, mem)?;
             }
             Instruction::RR { r } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.rotate_right_thru_carry(r, mem)?;
             }
             Instruction::SLA { r } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.shift_left_arithmetic(r, mem)?;
             }
             Instruction::SRA { r } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.shift_right_arithmetic(r, mem)?;
             }
             Instruction::SWAP { r } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.swap_nibbles_instr(r, mem)?;
             }
             Instruction::SRL { r } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.shift_right_logical(r, mem)?;
             }
 
             // also CB-prefixed (y is included in opcode)
-            Instruction::BIT { y, r } => self.pc -= 1,
-            Instruction::RES { y, r } => self.pc -= 1,
-            Instruction::SET { y, r } => self.pc -= 1,
+            Instruction::BIT { y, r } => self.test_bit(y, r, mem)?,
+            Instruction::RES { y, r } => self.reset_bit(y, r, mem)?,
+            Instruction::SET { y, r } => self.set_bit(y, r, mem)?,
 
-            Instruction::AddRegisters { r1, r2 } => self.pc -= 2,
-            Instruction::AddSigned { r, d } => self.pc -= 1,
+            Instruction::AddRegisters { r1, r2 } => self.add_registers(r1, r2)?,
+            Instruction::AddSigned { r, d } => self.add_signed(r, d)?,
 
             Instruction::DEC8b { r } => {
-                self.pc -= 2;
                 self.decrement_8b(r, mem)?;
             }
             Instruction::INC8b { r } => {
-                self.pc -= 2;
                 self.increment_8b(r, mem)?;
             }
 
             Instruction::DEC16b { r } => {
-                self.pc -= 2;
                 self.decrement_16b(r)?;
             }
             Instruction::INC16b { r } => {
-                self.pc -= 2;
                 self.increment_16b(r)?;
             }
             Instruction::AddHLAndR16 { r } => {
-                self.pc -= 2;
                 self.add_hl_and_r16(r)?;
             }
 
             Instruction::RLCA => {
-                self.pc -= 2;
                 self.rotate_left_accumulator();
             }
             Instruction::RRCA => {
-                self.pc -= 2;
                 self.rotate_right_accumulator();
             }
             Instruction::RLA => {
-                self.pc -= 2;
                 self.rotate_left_thru_carry_accumulator();
             }
             Instruction::RRA => {
-                self.pc -= 2;
                 self.rotate_right_thru_carry_accumulator();
             }
-            Instruction::DAA => self.pc -= 2,
+            Instruction::DAA => self.decimal_adjust_accumulator(),
             Instruction::CPL => {
-                self.pc -= 2;
                 self.complement_accumulator();
             }
             Instruction::SCF => {
-                self.pc -= 2;
-                self.set_carry_flag_on();
+                self.set_carry_flag();
             }
             Instruction::CCF => {
-                self.pc -= 2;
                 self.complement_carry_flag();
             }
 
-            Instruction::RET { f } => self.pc -= 2,
+            Instruction::RET { f } => branch_taken = self.ret_conditional(f, mem),
 
-            Instruction::RETNoParam => self.pc -= 2,
-            Instruction::RETI => self.pc -= 2,
+            Instruction::RETNoParam => self.ret(mem),
+            Instruction::RETI => self.ret_enable_interrupts(mem),
 
-            Instruction::POP { r } => self.pc -= 2,
+            Instruction::POP { r } => self.pop(r, mem)?,
 
             // interrupts
             Instruction::DI => {
-                self.pc -= 2;
                 self.disable_interrupts();
             }
             Instruction::EI => {
-                self.pc -= 2;
-                self.enable_interrupts();
+                self.schedule_enable_interrupts();
+            }
+            Instruction::STOP => self.stop(mem), // low power standby mode
+            Instruction::HALT => self.halt(mem), // halt until interrupt occurs
+            Instruction::CallConditional { f, nn } => {
+                branch_taken = self.call_conditional(f, nn, mem);
             }
-            Instruction::STOP => self.pc -= 2, // low power standby mode
-            Instruction::HALT => self.pc -= 1, // halt until interrupt occurs... somehow.
-            // TODO: research STOP and HALT instructions
-            Instruction::CallConditional { f, nn } => self.pc -= 0,
-            Instruction::Call { nn } => self.pc -= 0,
+            Instruction::Call { nn } => self.call(nn, mem),
 
-            Instruction::PUSH { r } => self.pc -= 2,
+            Instruction::PUSH { r } => self.push(r, mem)?,
 
-            Instruction::RST { arg } => self.pc -= 2, // "arg" is included in opcode
+            Instruction::RST { arg } => self.restart(arg, mem), // "arg" is included in opcode
 
             Instruction::AddImmediate { n } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.add_immediate(n);
             }
             Instruction::AdcImmediate { n } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.adc_immediate(n);
             }
             Instruction::SubImmediate { n } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.sub_immediate(n);
             }
             Instruction::SbcImmediate { n } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.sbc_immediate(n);
             }
             Instruction::AndImmediate { n } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.logical_template(n, |n1, n2| n1 & n2);
-                self.set_halfcarry_flag_on(); // AND sets half-carry on
+                self.set_flag(Flags::H, true); // AND sets half-carry on
             }
             Instruction::XorImmediate { n } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.logical_template(n, |n1, n2| n1 ^ n2);
             }
             Instruction::OrImmediate { n } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.logical_template(n, |n1, n2| n1 | n2);
             }
             Instruction::CpImmediate { n } => {
-                self.pc -= 1;
-                self.reset_flags();
                 self.compare_immediate(n);
             }
 
             Instruction::AddRegister { r } => {
-                self.pc -= 2;
-                self.reset_flags();
                 self.add_register(r, mem)?;
             }
             Instruction::AdcRegister { r } => {
-                self.pc -= 2;
-                self.reset_flags();
                 self.adc_register(r, mem)?;
             }
             Instruction::SubRegister { r } => {
-                self.pc -= 2;
-                self.reset_flags();
                 self.sub_register(r, mem)?;
             }
             Instruction::SbcRegister { r } => {
-                self.pc -= 2;
-                self.reset_flags();
                 self.sbc_register(r, mem)?;
             }
             Instruction::AndRegister { r } => {
-                self.pc -= 2;
-                self.reset_flags();
                 self.logical_reg_template(r, mem, |n1, n2| n1 & n2)?;
+                self.set_flag(Flags::H, true); // AND sets half-carry on
             }
             Instruction::XorRegister { r } => {
-                self.pc -= 2;
-                self.reset_flags();
                 self.logical_reg_template(r, mem, |n1, n2| n1 ^ n2)?;
             }
             Instruction::OrRegister { r } => {
-                self.pc -= 2;
-                self.reset_flags();
                 self.logical_reg_template(r, mem, |n1, n2| n1 | n2)?;
             }
             Instruction::CpRegister { r } => {
-                self.pc -= 2;
-                self.reset_flags();
                 self.compare_register(r, mem)?;
             }
         }
 
-        Ok(())
+        if branch_taken {
+            Ok(cycles_taken)
+        } else {
+            Ok(cycles_not_taken)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::testing::setup;
+    use crate::cpu::CPU;
+    use crate::memory::Memory;
+    use crate::model::Model;
+
+    macro_rules! pc_advance_test {
+        ($name:tt, $program:expr, $expected:expr) => {
+            #[test]
+            fn $name() {
+                let (mut cpu, mut mem) = setup(&$program, 0x0200);
+                cpu.fetch_decode_execute(&mut mem).unwrap();
+                assert_eq!(cpu.pc, 0x0200 + $expected);
+            }
+        };
+    }
+    pc_advance_test!(nop_advances_1, [0x00], 1);
+    pc_advance_test!(ld_rp_nn_advances_3, [0x01, 0x34, 0x12], 3);
+    pc_advance_test!(ld_r_n_advances_2, [0x06, 0x42], 2);
+    pc_advance_test!(ld_r_r_advances_1, [0x41], 1);
+    pc_advance_test!(alu_n_advances_2, [0xc6, 0x01], 2);
+    pc_advance_test!(cb_advances_2, [0xcb, 0x37], 2);
+    pc_advance_test!(halt_advances_1, [0x76], 1);
+    pc_advance_test!(jr_not_taken_advances_2, [0x38, 0x10], 2);
+
+    #[test]
+    fn jr_is_relative_to_next_instruction() {
+        let (mut cpu, mut mem) = setup(&[0x18, 0xfe], 0x0200);
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0200);
+    }
+    #[test]
+    fn jp_lands_on_operand() {
+        let (mut cpu, mut mem) = setup(&[0xc3, 0x50, 0x01], 0x0200);
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.pc, 0x0150);
+    }
+    #[test]
+    fn ld_rp_nn_loads_operand() {
+        let (mut cpu, mut mem) = setup(&[0x21, 0x34, 0x12], 0x0200);
+        cpu.fetch_decode_execute(&mut mem).unwrap();
+        assert_eq!(cpu.hl, 0x1234);
+    }
+
+    #[test]
+    fn single_byte_instruction_at_end_of_rom() {
+        let (mut cpu, mut mem) = setup(&[0x00], 0x7fff);
+        assert!(cpu.fetch_decode_execute(&mut mem).is_ok());
+        assert_eq!(cpu.pc, 0x8000);
+    }
+    #[test]
+    fn two_byte_instruction_at_end_of_rom() {
+        let (mut cpu, mut mem) = setup(&[0x06, 0x42], 0x7ffe);
+        assert!(cpu.fetch_decode_execute(&mut mem).is_ok());
+        assert_eq!(cpu.bc >> 8, 0x42);
+    }
+    #[test]
+    fn three_byte_instruction_at_end_of_rom() {
+        let (mut cpu, mut mem) = setup(&[0xc3, 0x00, 0x01], 0x7ffd);
+        assert!(cpu.fetch_decode_execute(&mut mem).is_ok());
+        assert_eq!(cpu.pc, 0x0100);
+    }
+
+    macro_rules! cycles_test {
+        ($name:tt, $program:expr, $expected:expr) => {
+            #[test]
+            fn $name() {
+                let (mut cpu, mut mem) = setup(&$program, 0x0200);
+                assert_eq!(cpu.fetch_decode_execute(&mut mem).unwrap(), $expected);
+            }
+        };
+    }
+    cycles_test!(nop_cycles, [0x00], 1);
+    cycles_test!(ld_r_n_cycles, [0x06, 0x42], 2);
+    cycles_test!(ld_hladdr_n_cycles, [0x36, 0x42], 3);
+    cycles_test!(ld_r_hladdr_cycles, [0x7e], 2);
+    cycles_test!(inc_hladdr_cycles, [0x34], 3);
+    cycles_test!(jp_cycles, [0xc3, 0x00, 0x03], 4);
+    cycles_test!(jr_cycles, [0x18, 0x00], 3);
+    cycles_test!(cb_swap_cycles, [0xcb, 0x37], 2);
+    // flags are all off after `setup`
+    cycles_test!(jr_nz_taken_cycles, [0x20, 0x00], 3);
+    cycles_test!(jr_z_not_taken_cycles, [0x28, 0x00], 2);
+    cycles_test!(jp_nc_taken_cycles, [0xd2, 0x00, 0x03], 4);
+    cycles_test!(jp_c_not_taken_cycles, [0xda, 0x00, 0x03], 3);
+    cycles_test!(call_nz_taken_cycles, [0xc4, 0x00, 0x03], 6);
+    cycles_test!(call_z_not_taken_cycles, [0xcc, 0x00, 0x03], 3);
+    cycles_test!(ret_nc_taken_cycles, [0xd0], 5);
+    cycles_test!(ret_c_not_taken_cycles, [0xd8], 2);
+    cycles_test!(push_cycles, [0xc5], 4);
+    cycles_test!(rst_cycles, [0xff], 4);
+
+    #[test]
+    fn cycles_accumulate() {
+        let (mut cpu, mut mem) = setup(&[0x00, 0x06, 0x42, 0xc3, 0x00, 0x02], 0x0200);
+        for _ in 0..3 {
+            cpu.fetch_decode_execute(&mut mem).unwrap();
+        }
+        assert_eq!(cpu.cycles(), 1 + 2 + 4);
+    }
+
+    // runs the whole program, then compares AF against known-good values
+    macro_rules! af_dump_test {
+        ($name:tt, $program:expr, $steps:expr, $expected:expr) => {
+            #[test]
+            fn $name() {
+                let (mut cpu, mut mem) = setup(&$program, 0x0200);
+                for _ in 0..$steps {
+                    cpu.fetch_decode_execute(&mut mem).unwrap();
+                }
+                assert_eq!(cpu.af, $expected, "AF = {:#06x}", cpu.af);
+            }
+        };
+    }
+    af_dump_test!(add_to_zero_af, [0x3e, 0x3a, 0xc6, 0xc6], 2, 0x00b0);
+    af_dump_test!(sub_to_zero_af, [0x3e, 0x3e, 0xd6, 0x3e], 2, 0x00c0);
+    af_dump_test!(sub_with_borrow_af, [0x3e, 0x3b, 0xd6, 0x4f], 2, 0xec70);
+    af_dump_test!(cp_af, [0x3e, 0x3c, 0xfe, 0x40], 2, 0x3c50);
+    af_dump_test!(and_af, [0x3e, 0x5a, 0xe6, 0x3f], 2, 0x1a20);
+    af_dump_test!(xor_a_af, [0xaf], 1, 0x0080);
+    af_dump_test!(inc_halfcarry_af, [0x3e, 0x0f, 0x3c], 2, 0x1020);
+    af_dump_test!(dec_to_zero_af, [0x3e, 0x01, 0x3d], 2, 0x00c0);
+    af_dump_test!(cpl_af, [0x3e, 0x80, 0x2f], 2, 0x7f60);
+    af_dump_test!(scf_af, [0x37], 1, 0x0010);
+    af_dump_test!(rlca_af, [0x3e, 0x85, 0x07], 2, 0x0b10);
+    af_dump_test!(rlca_resets_zero_af, [0xaf, 0x07], 2, 0x0000);
+    af_dump_test!(
+        pop_af_masks_low_nibble_af,
+        [0x31, 0x00, 0xd0, 0x01, 0xff, 0x12, 0xc5, 0xf1],
+        4,
+        0x12f0
+    );
+
+    // header checksum lives at 0x014d, the CGB flag at 0x0143
+    fn post_boot_registers(model: Model, checksum: u8, cgb_flag: u8) -> [u16; 6] {
+        let mut rom = vec![0; 0x8000];
+        rom[0x014d] = checksum;
+        rom[0x0143] = cgb_flag;
+        let mem = Memory::from(rom).unwrap();
+        let cpu = CPU::post_boot(model, &mem);
+
+        [cpu.af, cpu.bc, cpu.de, cpu.hl, cpu.sp, cpu.pc]
+    }
+    macro_rules! post_boot_test {
+        ($name:tt, $model:expr, $checksum:expr, $cgb_flag:expr, $expected:expr) => {
+            #[test]
+            fn $name() {
+                assert_eq!(post_boot_registers($model, $checksum, $cgb_flag), $expected);
+            }
+        };
+    }
+    post_boot_test!(
+        post_boot_dmg0,
+        Model::Dmg0,
+        0x42,
+        0x00,
+        [0x0100, 0xff13, 0x00c1, 0x8403, 0xfffe, 0x0100]
+    );
+    post_boot_test!(
+        post_boot_dmg_zero_checksum,
+        Model::Dmg,
+        0x00,
+        0x00,
+        [0x0180, 0x0013, 0x00d8, 0x014d, 0xfffe, 0x0100]
+    );
+    post_boot_test!(
+        post_boot_dmg,
+        Model::Dmg,
+        0x42,
+        0x00,
+        [0x01b0, 0x0013, 0x00d8, 0x014d, 0xfffe, 0x0100]
+    );
+    post_boot_test!(
+        post_boot_mgb,
+        Model::Mgb,
+        0x42,
+        0x00,
+        [0xffb0, 0x0013, 0x00d8, 0x014d, 0xfffe, 0x0100]
+    );
+    post_boot_test!(
+        post_boot_sgb,
+        Model::Sgb,
+        0x42,
+        0x00,
+        [0x0100, 0x0014, 0x0000, 0xc060, 0xfffe, 0x0100]
+    );
+    post_boot_test!(
+        post_boot_sgb2,
+        Model::Sgb2,
+        0x42,
+        0x00,
+        [0xff00, 0x0014, 0x0000, 0xc060, 0xfffe, 0x0100]
+    );
+    post_boot_test!(
+        post_boot_cgb,
+        Model::Cgb,
+        0x42,
+        0x80,
+        [0x1180, 0x0000, 0xff56, 0x000d, 0xfffe, 0x0100]
+    );
+    post_boot_test!(
+        post_boot_cgb_dmg_mode,
+        Model::Cgb,
+        0x42,
+        0x00,
+        [0x1180, 0x0000, 0x0008, 0x007c, 0xfffe, 0x0100]
+    );
+    post_boot_test!(
+        post_boot_agb,
+        Model::Agb,
+        0x42,
+        0xc0,
+        [0x1100, 0x0100, 0xff56, 0x000d, 0xfffe, 0x0100]
+    );
+
+    #[test]
+    fn boot_rom_hands_over_to_cartridge() {
+        // LD A, 0x01; LDH (0x50), A; then execution falls through into the cartridge
+        let mut boot_rom = vec![0; 0x0100];
+        boot_rom[..4].copy_from_slice(&[0x3e, 0x01, 0xe0, 0x50]);
+        let mut rom = vec![0; 0x8000];
+        rom[0x0004] = 0x06; // LD B, 0x42
+        rom[0x0005] = 0x42;
+
+        let mut mem = Memory::from(rom).unwrap();
+        mem.load_boot_rom(boot_rom).unwrap();
+        let mut cpu = CPU::new(0, false, 0);
+        for _ in 0..3 {
+            cpu.fetch_decode_execute(&mut mem).unwrap();
+        }
+
+        assert_eq!(cpu.bc, 0x4200);
+        assert_eq!(cpu.pc, 0x0006);
+    }
+
+    #[test]
+    fn illegal_instruction_reports_its_address() {
+        let (mut cpu, mut mem) = setup(&[0xd3], 0x0200);
+        let result = cpu.fetch_decode_execute(&mut mem);
+        assert_eq!(
+            result,
+            Err(crate::cpu::CpuError::IllegalInstruction { pc: 0x0200 })
+        );
     }
 }
@@ -0,0 +1,16 @@
+use crate::cpu::CPU;
+use crate::memory::Memory;
+
+// helpers shared by the CPU's tests
+
+/// A plain 32 KiB cart with `program` at `start`, and a CPU about to run it with
+/// the stack at the top of HRAM.
+pub fn setup(program: &[u8], start: u16) -> (CPU, Memory) {
+    let start_index = start as usize;
+    let mut rom = vec![0; 0x8000];
+    rom[start_index..start_index + program.len()].copy_from_slice(program);
+    let mut cpu = CPU::new(start, false, 0);
+    cpu.sp = 0xfffe;
+
+    (cpu, Memory::from(rom).unwrap())
+}
@@ -1,12 +1,26 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{stdin, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use signal_hook::consts::{SIGINT, SIGTERM};
 
 mod memory;
-use memory::Memory;
+use memory::{FrameDirectory, Memory, RtcClock, StaticImage};
 
 mod cpu;
 use cpu::CPU;
 
+mod model;
+use model::Model;
+
+mod ppu;
+use ppu::{Ppu, Renderer, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+mod save;
+use save::SaveFile;
+
 type Result<T, E = Box<dyn std::error::Error>> = std::result::Result<T, E>;
 
 fn main() -> Result<()> {
@@ -15,22 +29,158 @@ fn main() -> Result<()> {
     stdin().read_line(&mut filename)?;
 
     println!("(-) reading from {}...", filename.trim());
+    let rom_data = read_file(filename.trim())?;
 
-    let mut f = File::open(filename.trim().to_string())?;
-    let mut rom_data = Vec::new();
-    // read the whole file
-    f.read_to_end(&mut rom_data)?;
+    println!("enter boot ROM to run first (leave empty to skip)");
+    let mut boot_filename = String::new();
+    stdin().read_line(&mut boot_filename)?;
 
-    // TODO: investigate more boot-time initialization things at https://gbdev.io/pandocs/Power_Up_Sequence.html
     let mut mem = Memory::from(rom_data).expect("Memory given was invalid and cannot be read"); // TODO: handle more gracefully
-    let mut cpu = CPU::new(0, false, mem.header.header_checksum);
-    loop {
-        // TODO: display graphics, handle errors more gracefully
-        let execution_result = cpu.fetch_decode_execute(&mut mem);
-
-        if let Err(e) = execution_result {
-            println!("{}", e);
-            break Ok(());
+
+    // run each game on the fanciest hardware it knows about
+    let model = if mem.header.cgb_support() {
+        Model::Cgb
+    } else if mem.header.sgb_included() {
+        Model::Sgb
+    } else {
+        Model::Dmg
+    };
+
+    let mut cpu = if boot_filename.trim().is_empty() {
+        // no boot ROM, start from the state it would have left behind
+        mem.post_boot(model);
+        CPU::post_boot(model, &mem)
+    } else {
+        println!("(-) reading boot ROM from {}...", boot_filename.trim());
+        mem.load_boot_rom(read_file(boot_filename.trim())?)?;
+        mem.set_model(model);
+        if !mem.header.logo_matches() || !mem.header.checksum_matches() {
+            println!("(!) cartridge header is invalid, the boot ROM will lock up");
         }
+        CPU::new(0, false, 0)
+    };
+
+    let mut save = SaveFile::for_rom(Path::new(filename.trim()));
+    if mem.has_battery() {
+        println!("(-) using save file {}...", save.path().display());
+        save.load(&mut mem)?;
     }
+
+    if mem.has_camera() {
+        println!("enter a .pgm image or a directory of them for the camera (leave empty for a test pattern)");
+        let mut image_filename = String::new();
+        stdin().read_line(&mut image_filename)?;
+
+        let image_path = Path::new(image_filename.trim());
+        if image_path.is_dir() {
+            mem.set_image_source(FrameDirectory::open(image_path)?);
+        } else if !image_filename.trim().is_empty() {
+            mem.set_image_source(StaticImage::open(image_path)?);
+        }
+    }
+
+    if mem.has_clock() {
+        println!("run the cartridge's clock from emulated time instead of the host's? (y/N)");
+        let mut clock_answer = String::new();
+        stdin().read_line(&mut clock_answer)?;
+        if clock_answer.trim().eq_ignore_ascii_case("y") {
+            mem.set_rtc_clock(RtcClock::Emulated);
+        }
+    }
+
+    // no motor to drive, so just say what it's doing
+    mem.set_rumble_callback(|on| println!("(-) rumble {}", if on { "on" } else { "off" }));
+    mem.set_infrared_led_callback(|on| {
+        println!("(-) infrared LED {}", if on { "on" } else { "off" })
+    });
+    // and no motion or infrared input either, so hold the cart flat in the dark
+    mem.set_tilt(0.0, 0.0);
+    mem.set_infrared_light(false);
+
+    println!("draw with the pixel FIFO, for games with effects partway through a line? (y/N)");
+    let mut fifo_answer = String::new();
+    stdin().read_line(&mut fifo_answer)?;
+    let mut ppu = Ppu::new();
+    if fifo_answer.trim().eq_ignore_ascii_case("y") {
+        ppu.set_renderer(Renderer::Fifo);
+    }
+
+    println!("enter a .pgm file to save the last frame to when stopping (leave empty to skip)");
+    let mut screenshot_filename = String::new();
+    stdin().read_line(&mut screenshot_filename)?;
+
+    // ctrl-c and friends stop the loop rather than the whole process, so the save
+    // still gets written
+    let quit = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, Arc::clone(&quit))?;
+    }
+
+    let mut screen = Vec::new();
+    let result = run(&mut cpu, &mut mem, &mut ppu, &mut save, &quit, &mut screen);
+    let flushed = save.flush(&mut mem);
+
+    if !screenshot_filename.trim().is_empty() {
+        if screen.is_empty() {
+            println!("(!) no frame was finished, nothing to save");
+        } else {
+            println!(
+                "(-) saving the last frame to {}...",
+                screenshot_filename.trim()
+            );
+            write_screenshot(Path::new(screenshot_filename.trim()), &screen)?;
+        }
+    }
+    result.and(flushed)
+}
+
+// runs until the CPU hits something it can't handle or we're told to stop,
+// keeping the last whole frame in `screen`
+fn run(
+    cpu: &mut CPU,
+    mem: &mut Memory,
+    ppu: &mut Ppu,
+    save: &mut SaveFile,
+    quit: &AtomicBool,
+    screen: &mut Vec<u8>,
+) -> Result<()> {
+    while !quit.load(Ordering::Relaxed) {
+        // TODO: display the screen as it goes, handle errors more gracefully
+        let execution_result = cpu.fetch_decode_execute(mem);
+
+        match execution_result {
+            Ok(cycles) => {
+                mem.tick(cycles);
+                ppu.tick(mem, cycles);
+                if ppu.take_frame_ready() {
+                    screen.clear();
+                    screen.extend_from_slice(ppu.framebuffer());
+                }
+                save.tick(mem, cycles)?;
+            }
+            Err(e) => {
+                println!("{} (after {} M-cycles)", e, cpu.cycles());
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+// a binary PGM, shades 0 (white) to 3 (black) spread out over 0-255
+fn write_screenshot(path: &Path, screen: &[u8]) -> Result<()> {
+    let mut data = format!("P5\n{} {}\n255\n", SCREEN_WIDTH, SCREEN_HEIGHT).into_bytes();
+    data.extend(screen.iter().map(|shade| 0xff - (shade * 0x55)));
+    fs::write(path, data)?;
+
+    Ok(())
+}
+
+fn read_file(filename: &str) -> Result<Vec<u8>> {
+    let mut f = File::open(filename)?;
+    let mut data = Vec::new();
+    // read the whole file
+    f.read_to_end(&mut data)?;
+
+    Ok(data)
 }
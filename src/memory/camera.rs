@@ -0,0 +1,260 @@
+use super::cartridge::{Cartridge, Storage};
+use super::imagesource::{ImageSource, TestPattern, SENSOR_HEIGHT, SENSOR_WIDTH};
+
+// https://gbdev.io/pandocs/Gameboy_Camera.html
+
+const REGISTER_COUNT: usize = 0x36;
+// where the 4x4 grid of dithering thresholds starts, 3 per pixel
+const DITHER_MATRIX: usize = 0x06;
+// the finished picture goes into the first RAM bank as tiles, just past this
+const IMAGE_OFFSET: usize = 0x0100;
+
+// an exposure time that passes the image through as it is, games settle on
+// their own exposure by looking at what comes back
+const EXPOSURE_REFERENCE: u32 = 0x1000;
+
+/// The Pocket Camera, MBC3-style banking with the sensor's registers in place
+/// of the clock. Pictures come from an `ImageSource`.
+pub struct PocketCamera {
+    ram_enable: u8, // 0000-1fff, 0x0a lets RAM be written
+    rom_bank: u8,   // 2000-3fff, 6 bits
+    ram_bank: u8,   // 4000-5fff, bits 0-3 pick a RAM bank, bit 4 maps the registers instead
+
+    registers: [u8; REGISTER_COUNT],
+    busy_cycles: u64, // left until the capture's done
+    source: Box<dyn ImageSource>,
+    storage: Storage,
+}
+
+impl PocketCamera {
+    pub fn new(storage: Storage) -> PocketCamera {
+        PocketCamera {
+            ram_enable: 0,
+            rom_bank: 0,
+            ram_bank: 0,
+
+            registers: [0; REGISTER_COUNT],
+            busy_cycles: 0,
+            source: Box::new(TestPattern::new()),
+            storage,
+        }
+    }
+
+    pub fn ram_enabled(&self) -> bool {
+        self.ram_enable == 0x0a
+    }
+    fn registers_mapped(&self) -> bool {
+        (self.ram_bank & 0x10) != 0
+    }
+
+    /// ROM bank mapped to 0x4000-0x7fff, unlike other controllers bank 0 can go here too.
+    pub fn rom_bank_4000(&self) -> usize {
+        (self.rom_bank & 0x3f) as usize
+    }
+    /// RAM bank mapped to 0xa000-0xbfff.
+    pub fn ram_bank(&self) -> usize {
+        (self.ram_bank & 0x0f) as usize
+    }
+
+    /// Whether a capture is still in progress.
+    pub fn busy(&self) -> bool {
+        (self.registers[0] & 0b1) != 0
+    }
+
+    fn exposure(&self) -> u16 {
+        u16::from_be_bytes([self.registers[2], self.registers[3]])
+    }
+
+    // roughly how long the sensor takes, in M-cycles
+    fn capture_cycles(&self) -> u64 {
+        let n = (self.registers[1] & 0x80) != 0;
+        32446 + if n { 0 } else { 512 } + (16 * self.exposure() as u64)
+    }
+
+    // only exposure and the dithering are done, the sensor's gain and edge
+    // enhancement are ignored
+    fn capture(&mut self) {
+        let mut frame = vec![0; SENSOR_WIDTH * SENSOR_HEIGHT];
+        self.source.capture(&mut frame);
+
+        let exposure = self.exposure() as u32;
+        for (i, pixel) in frame.iter().enumerate() {
+            let (x, y) = (i % SENSOR_WIDTH, i / SENSOR_WIDTH);
+            let val = ((*pixel as u32 * exposure) / EXPOSURE_REFERENCE).min(0xff) as u8;
+
+            // darker than the first threshold is black, lighter than all three is white
+            let thresholds = DITHER_MATRIX + (((y & 0b11) * 4 + (x & 0b11)) * 3);
+            let colour = self.registers[thresholds..thresholds + 3]
+                .iter()
+                .filter(|&&threshold| val < threshold)
+                .count() as u8;
+
+            // 16 tiles across, 2 bytes per row of 8 pixels
+            let tile = (y / 8) * (SENSOR_WIDTH / 8) + (x / 8);
+            let offset = IMAGE_OFFSET + (tile * 16) + ((y % 8) * 2);
+            let bit = 7 - (x % 8);
+            for (plane, set) in [(0, colour & 0b01 != 0), (1, colour & 0b10 != 0)] {
+                let byte = self.storage.ram_at(offset + plane) & !(1 << bit);
+                self.storage
+                    .set_ram_at(offset + plane, byte | ((set as u8) << bit));
+            }
+        }
+    }
+}
+
+impl Cartridge for PocketCamera {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3fff => self.storage.read_rom(0, addr),
+            0x4000..=0x7fff => self.storage.read_rom(self.rom_bank_4000(), addr),
+            // only the first register reads back, the rest are write-only
+            _ if self.registers_mapped() => match addr & 0x7f {
+                0x00 => self.registers[0] & 0b111,
+                _ => 0x00,
+            },
+            // the sensor has RAM to itself while it's capturing
+            _ if self.busy() => 0x00,
+            // RAM can always be read, it's just writes that need enabling
+            _ => self.storage.read_ram(self.ram_bank(), addr),
+        }
+    }
+    fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enable = val,
+            0x2000..=0x3fff => self.rom_bank = val,
+            0x4000..=0x5fff => self.ram_bank = val,
+            // 6000-7fff, writes here go nowhere
+            0x6000..=0x7fff => {}
+            // registers repeat every 0x80 bytes
+            _ if self.registers_mapped() => {
+                let reg = addr & 0x7f;
+                if reg == 0x00 {
+                    let start = !self.busy() && (val & 0b1) != 0;
+                    self.registers[0] = val & 0b111;
+                    if start {
+                        self.busy_cycles = self.capture_cycles();
+                    }
+                } else if reg < REGISTER_COUNT {
+                    self.registers[reg] = val;
+                }
+            }
+            _ if self.ram_enabled() && !self.busy() => {
+                self.storage.write_ram(self.ram_bank(), addr, val)
+            }
+            _ => {}
+        }
+    }
+
+    fn tick(&mut self, m_cycles: u64) {
+        if !self.busy() {
+            return;
+        }
+
+        self.busy_cycles = self.busy_cycles.saturating_sub(m_cycles);
+        if self.busy_cycles == 0 {
+            self.capture();
+            self.registers[0] &= !0b1;
+        }
+    }
+    fn set_image_source(&mut self, source: Box<dyn ImageSource>) {
+        self.source = source;
+    }
+
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::camera::{PocketCamera, EXPOSURE_REFERENCE};
+    use crate::memory::cartridge::{Cartridge, Storage};
+    use crate::memory::imagesource::ImageSource;
+
+    // left half black, right half white
+    struct Halves;
+    impl ImageSource for Halves {
+        fn capture(&mut self, frame: &mut [u8]) {
+            for (i, pixel) in frame.iter_mut().enumerate() {
+                *pixel = if i % 128 < 64 { 0x00 } else { 0xff };
+            }
+        }
+    }
+
+    fn camera() -> PocketCamera {
+        let mut mbc = PocketCamera::new(Storage::new(Vec::new(), 64, 0x20000));
+        mbc.set_image_source(Box::new(Halves));
+        mbc
+    }
+    fn capture(mbc: &mut PocketCamera) {
+        mbc.write(0x4000, 0x10);
+        let [hi, lo] = (EXPOSURE_REFERENCE as u16).to_be_bytes();
+        mbc.write(0xa002, hi);
+        mbc.write(0xa003, lo);
+        // the same thresholds for every pixel
+        for i in 0..16 {
+            mbc.write(0xa006 + (i * 3), 0x40);
+            mbc.write(0xa007 + (i * 3), 0x80);
+            mbc.write(0xa008 + (i * 3), 0xc0);
+        }
+        mbc.write(0xa000, 0x01);
+    }
+
+    #[test]
+    fn rom_bank_0_selectable() {
+        let mut mbc = camera();
+        mbc.write(0x2000, 0x40);
+
+        assert_eq!(mbc.rom_bank_4000(), 0);
+    }
+    #[test]
+    fn ram_reads_without_enabling() {
+        let mut mbc = camera();
+        mbc.write(0x4000, 0x0f);
+        mbc.write(0xa000, 0x42);
+        assert_eq!(mbc.read(0xa000), 0x00);
+
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0xa000, 0x42);
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(0xa000), 0x42);
+    }
+    #[test]
+    fn only_first_register_reads_back() {
+        let mut mbc = camera();
+        mbc.write(0x4000, 0x10);
+        mbc.write(0xa001, 0x42);
+        mbc.write(0xa080, 0x06); // mirrored
+
+        assert_eq!(mbc.read(0xa001), 0x00);
+        assert_eq!(mbc.read(0xa000), 0x06);
+    }
+    #[test]
+    fn capture_takes_time() {
+        let mut mbc = camera();
+        capture(&mut mbc);
+        assert!(mbc.busy());
+        assert_eq!(mbc.read(0xa000), 0x01);
+
+        mbc.tick(32446);
+        assert!(mbc.busy());
+        mbc.tick(512 + 16 * EXPOSURE_REFERENCE as u64);
+        assert!(!mbc.busy());
+        assert_eq!(mbc.read(0xa000), 0x00);
+    }
+    #[test]
+    fn capture_dithers_into_tiles() {
+        let mut mbc = camera();
+        capture(&mut mbc);
+        mbc.tick(u64::MAX);
+
+        mbc.write(0x4000, 0x00);
+        // first tile is all black, both bitplanes set
+        assert_eq!((mbc.read(0xa100), mbc.read(0xa101)), (0xff, 0xff));
+        // tile 8 starts on the white half
+        assert_eq!((mbc.read(0xa180), mbc.read(0xa181)), (0x00, 0x00));
+    }
+}
@@ -0,0 +1,428 @@
+use super::camera::PocketCamera;
+use super::cartridgeheader::{CartridgeHeader, CartridgeType};
+use super::huc1::HuC1;
+use super::huc3::HuC3;
+use super::imagesource::ImageSource;
+use super::mbc1::Mbc1;
+use super::mbc2::Mbc2;
+use super::mbc3::Mbc3;
+use super::mbc5::Mbc5;
+use super::mbc6::Mbc6;
+use super::mbc7::{Mbc7, EEPROM_SIZE};
+use super::mmm01::Mmm01;
+use super::rtc::RtcClock;
+use super::tama5::Tama5;
+use super::MemoryError;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+/// Whatever's plugged into the cartridge slot. Gets every access to 0x0000-0x7fff and
+/// 0xa000-0xbfff, writes to ROM usually going to the controller's registers.
+pub trait Cartridge {
+    fn read(&self, addr: usize) -> u8;
+    fn write(&mut self, addr: usize, val: u8);
+
+    /// Lets hardware with its own timing keep up with the CPU.
+    fn tick(&mut self, _m_cycles: u64) {}
+    /// Picks what drives the real-time clock, if there is one.
+    fn set_rtc_clock(&mut self, _clock: RtcClock) {}
+    /// Whether the rumble motor is currently running.
+    fn rumble(&self) -> bool {
+        false
+    }
+    /// Feeds the accelerometer, if there is one.
+    fn set_tilt(&mut self, _x: f32, _y: f32) {}
+    /// Shines light on the infrared sensor, if there is one.
+    fn set_infrared_light(&mut self, _on: bool) {}
+    /// Whether the infrared LED is currently lit.
+    fn infrared_led(&self) -> bool {
+        false
+    }
+    /// Swaps out what the camera sees, if there is one.
+    fn set_image_source(&mut self, _source: Box<dyn ImageSource>) {}
+
+    fn storage(&self) -> &Storage;
+    fn storage_mut(&mut self) -> &mut Storage;
+
+    /// Battery-backed state, laid out the same way as other emulators' .sav files.
+    fn save_data(&mut self) -> Vec<u8> {
+        self.storage().save_ram()
+    }
+    fn load_save_data(&mut self, data: &[u8]) -> Result<(), MemoryError> {
+        self.storage_mut().load_ram(data).map(|_| ())
+    }
+}
+
+/// ROM and external RAM as the controller sees them. Bank numbers wrap around
+/// to the size of each, like the unconnected upper bank bits would on hardware.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Storage {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    dirty: bool, // battery-backed state changed since the last `take_dirty`
+}
+
+impl Storage {
+    pub fn new(mut rom: Vec<u8>, rom_banks: usize, ram_size: usize) -> Storage {
+        rom.resize(rom_banks * ROM_BANK_SIZE, 0);
+        Storage {
+            rom,
+            ram: vec![0; ram_size],
+            dirty: false,
+        }
+    }
+
+    pub fn rom_banks(&self) -> usize {
+        self.rom.len() / ROM_BANK_SIZE
+    }
+    /// The 16 KiB bank starting at `bank`, already wrapped.
+    pub fn rom_bank(&self, bank: usize) -> &[u8] {
+        let start = (bank % self.rom_banks()) * ROM_BANK_SIZE;
+        &self.rom[start..start + ROM_BANK_SIZE]
+    }
+    /// Reads from `bank` as if it were mapped at `addr`, open bus with no ROM at all.
+    pub fn read_rom(&self, bank: usize, addr: usize) -> u8 {
+        self.rom_at((bank * ROM_BANK_SIZE) + (addr & (ROM_BANK_SIZE - 1)))
+    }
+    /// Reads anywhere in ROM, for controllers with banks that aren't 16 KiB.
+    pub fn rom_at(&self, offset: usize) -> u8 {
+        if self.rom.is_empty() {
+            return 0xff;
+        }
+        self.rom[offset % self.rom.len()]
+    }
+    /// Reads 0xff when there's no RAM to read.
+    pub fn read_ram(&self, bank: usize, addr: usize) -> u8 {
+        self.ram_at((bank * RAM_BANK_SIZE) + (addr - 0xa000))
+    }
+    pub fn write_ram(&mut self, bank: usize, addr: usize, val: u8) {
+        self.set_ram_at((bank * RAM_BANK_SIZE) + (addr - 0xa000), val);
+    }
+    // anywhere in RAM, wrapping around past the end
+    pub fn ram_at(&self, offset: usize) -> u8 {
+        if self.ram.is_empty() {
+            return 0xff;
+        }
+        self.ram[offset % self.ram.len()]
+    }
+    pub fn set_ram_at(&mut self, offset: usize, val: u8) {
+        if !self.ram.is_empty() {
+            let len = self.ram.len();
+            self.ram[offset % len] = val;
+            self.dirty = true;
+        }
+    }
+
+    /// For controllers keeping battery-backed state outside of RAM, like a clock.
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+    /// Whether anything that belongs in the save file changed since this was last called.
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    pub fn save_ram(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+    /// Whatever comes after RAM in a save file, without loading anything, so controllers
+    /// can check it over first.
+    pub fn save_trailer<'a>(&self, data: &'a [u8]) -> Result<&'a [u8], MemoryError> {
+        if data.len() < self.ram.len() {
+            return Err(MemoryError::InvalidSaveSize {
+                size: data.len(),
+                expected: self.ram.len(),
+            });
+        }
+        Ok(&data[self.ram.len()..])
+    }
+    /// Fills RAM from the start of a save file, handing back whatever comes after it.
+    pub fn load_ram<'a>(&mut self, data: &'a [u8]) -> Result<&'a [u8], MemoryError> {
+        let rest = self.save_trailer(data)?;
+        let len = self.ram.len();
+        self.ram.copy_from_slice(&data[..len]);
+        Ok(rest)
+    }
+}
+
+/// No controller at all, 32 KiB of ROM and maybe 8 KiB of RAM wired straight to the bus.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct RomOnly {
+    storage: Storage,
+}
+
+impl RomOnly {
+    pub fn new(storage: Storage) -> RomOnly {
+        RomOnly { storage }
+    }
+}
+
+impl Cartridge for RomOnly {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x7fff => self.storage.read_rom(addr / ROM_BANK_SIZE, addr),
+            _ => self.storage.read_ram(0, addr),
+        }
+    }
+    fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            // nothing here to catch the write, so it goes nowhere
+            0x0000..=0x7fff => {}
+            _ => self.storage.write_ram(0, addr, val),
+        }
+    }
+
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+}
+
+/// Picks the controller the header asks for and loads the ROM into it.
+pub fn from_rom(
+    header: &CartridgeHeader,
+    data: Vec<u8>,
+) -> Result<Box<dyn Cartridge>, MemoryError> {
+    let ct = header.cartridge_type();
+    let ram_size = header.ram_size() as usize * 0x400;
+    // only once the shift count has been checked
+    let rom_banks = || 2 << header.rom_shift_count();
+
+    let wrong_ram = || MemoryError::CartTypeMismatch {
+        ct: header.cartridge_type(),
+        reason: String::from("header says RAM included with wrong cartridge type"),
+    };
+    let ram_too_large = || MemoryError::CartTypeMismatch {
+        ct: header.cartridge_type(),
+        reason: String::from("given RAM size is too large"),
+    };
+    let rom_too_large = || MemoryError::CartTypeMismatch {
+        ct: header.cartridge_type(),
+        reason: String::from("given ROM size is too large or incorrect"),
+    };
+
+    let cartridge: Box<dyn Cartridge> = match ct {
+        CartridgeType::ROM_ONLY | CartridgeType::ROM_RAM | CartridgeType::ROM_RAM_BATTERY => {
+            if header.rom_shift_count() > 0 {
+                return Err(MemoryError::CartTypeMismatch {
+                    ct,
+                    reason: String::from("given ROM size is too large"),
+                });
+            }
+
+            // the header's RAM size doesn't matter, there's room for 8 KiB
+            let ram_size = if ct == CartridgeType::ROM_ONLY {
+                // no RAM specified
+                if header.ram_size() > 0 {
+                    return Err(wrong_ram());
+                }
+                0
+            } else {
+                0x2000
+            };
+
+            Box::new(RomOnly::new(Storage::new(data, 2, ram_size)))
+        }
+        CartridgeType::MBC1 | CartridgeType::MBC1_RAM | CartridgeType::MBC1_RAM_BATTERY => {
+            if ct == CartridgeType::MBC1 && header.ram_size() > 0 {
+                return Err(wrong_ram());
+            }
+            // up to 4 banks of 8 KiB
+            if header.ram_size() > 32 {
+                return Err(ram_too_large());
+            }
+            // 32 KiB to 2 MiB, past 512 KiB the upper two bank bits
+            // pick the ROM bank instead of the RAM bank
+            if header.rom_shift_count() > 0x06 {
+                return Err(rom_too_large());
+            }
+
+            Box::new(Mbc1::new(Storage::new(data, rom_banks(), ram_size)))
+        }
+        CartridgeType::MBC2 | CartridgeType::MBC2_BATTERY => {
+            // RAM is built into the MBC, the header should say there's none
+            if header.ram_size() > 0 {
+                return Err(wrong_ram());
+            }
+            // only 4 bits of ROM bank, so at most 256 KiB
+            if header.rom_shift_count() > 0x03 {
+                return Err(rom_too_large());
+            }
+
+            // 512 half-bytes, echoed across all of a000-bfff
+            Box::new(Mbc2::new(Storage::new(data, rom_banks(), 0x0200)))
+        }
+        CartridgeType::MBC3
+        | CartridgeType::MBC3_RAM
+        | CartridgeType::MBC3_RAM_BATTERY
+        | CartridgeType::MBC3_TIMER_BATTERY
+        | CartridgeType::MBC3_TIMER_RAM_BATTERY => {
+            let has_rtc = matches!(
+                ct,
+                CartridgeType::MBC3_TIMER_BATTERY | CartridgeType::MBC3_TIMER_RAM_BATTERY
+            );
+            let has_ram = !matches!(ct, CartridgeType::MBC3 | CartridgeType::MBC3_TIMER_BATTERY);
+
+            if !has_ram && header.ram_size() > 0 {
+                return Err(wrong_ram());
+            }
+            // up to 4 banks of 8 KiB
+            if header.ram_size() > 32 {
+                return Err(ram_too_large());
+            }
+            // 7 bits of ROM bank, so at most 2 MiB
+            if header.rom_shift_count() > 0x06 {
+                return Err(rom_too_large());
+            }
+
+            Box::new(Mbc3::new(
+                Storage::new(data, rom_banks(), ram_size),
+                has_rtc,
+            ))
+        }
+        CartridgeType::MBC5
+        | CartridgeType::MBC5_RAM
+        | CartridgeType::MBC5_RAM_BATTERY
+        | CartridgeType::MBC5_RUMBLE
+        | CartridgeType::MBC5_RUMBLE_RAM
+        | CartridgeType::MBC5_RUMBLE_RAM_BATTERY => {
+            let has_rumble = matches!(
+                ct,
+                CartridgeType::MBC5_RUMBLE
+                    | CartridgeType::MBC5_RUMBLE_RAM
+                    | CartridgeType::MBC5_RUMBLE_RAM_BATTERY
+            );
+            let has_ram = !matches!(ct, CartridgeType::MBC5 | CartridgeType::MBC5_RUMBLE);
+
+            if !has_ram && header.ram_size() > 0 {
+                return Err(wrong_ram());
+            }
+            // up to 16 banks of 8 KiB, the header can't ask for more
+            // 9 bits of ROM bank, so at most 8 MiB
+            if header.rom_shift_count() > 0x08 {
+                return Err(rom_too_large());
+            }
+
+            Box::new(Mbc5::new(
+                Storage::new(data, rom_banks(), ram_size),
+                has_rumble,
+            ))
+        }
+        CartridgeType::MBC6 => {
+            // 8 banks of 4 KiB
+            if header.ram_size() > 32 {
+                return Err(ram_too_large());
+            }
+            // the flash chip comes on top of at most 1 MiB of ROM
+            if header.rom_shift_count() > 0x05 {
+                return Err(rom_too_large());
+            }
+
+            Box::new(Mbc6::new(Storage::new(data, rom_banks(), ram_size)))
+        }
+        CartridgeType::MBC7_SENSOR_RUMBLE_RAM_BATTERY => {
+            // the EEPROM's size isn't in the header, which usually says there's no RAM
+            if header.rom_shift_count() > 0x06 {
+                return Err(rom_too_large());
+            }
+
+            Box::new(Mbc7::new(Storage::new(data, rom_banks(), EEPROM_SIZE)))
+        }
+        CartridgeType::MMM01 | CartridgeType::MMM01_RAM | CartridgeType::MMM01_RAM_BATTERY => {
+            if ct == CartridgeType::MMM01 && header.ram_size() > 0 {
+                return Err(wrong_ram());
+            }
+            // 4 bits of RAM bank, but the header can't ask for more than 128 KiB
+            // 9 bits of ROM bank, so at most 8 MiB
+            if header.rom_shift_count() > 0x08 {
+                return Err(rom_too_large());
+            }
+
+            Box::new(Mmm01::new(Storage::new(data, rom_banks(), ram_size)))
+        }
+        CartridgeType::HuC1_RAM_BATTERY => {
+            // up to 4 banks of 8 KiB
+            if header.ram_size() > 32 {
+                return Err(ram_too_large());
+            }
+            // 6 bits of ROM bank, so at most 1 MiB
+            if header.rom_shift_count() > 0x05 {
+                return Err(rom_too_large());
+            }
+
+            Box::new(HuC1::new(Storage::new(data, rom_banks(), ram_size)))
+        }
+        CartridgeType::HuC3 => {
+            // up to 4 banks of 8 KiB
+            if header.ram_size() > 32 {
+                return Err(ram_too_large());
+            }
+            // 7 bits of ROM bank, so at most 2 MiB
+            if header.rom_shift_count() > 0x06 {
+                return Err(rom_too_large());
+            }
+
+            Box::new(HuC3::new(Storage::new(data, rom_banks(), ram_size)))
+        }
+        CartridgeType::BANDAI_TAMA5 => {
+            // 5 bits of ROM bank, so at most 512 KiB
+            if header.rom_shift_count() > 0x04 {
+                return Err(rom_too_large());
+            }
+
+            // the header doesn't list the 32 bytes of RAM
+            Box::new(Tama5::new(Storage::new(data, rom_banks(), 0x20)))
+        }
+        CartridgeType::POCKET_CAMERA => {
+            // up to 16 banks of 8 KiB, the header can't ask for more
+            // 6 bits of ROM bank, so at most 1 MiB
+            if header.rom_shift_count() > 0x05 {
+                return Err(rom_too_large());
+            }
+
+            Box::new(PocketCamera::new(Storage::new(data, rom_banks(), ram_size)))
+        }
+    };
+
+    Ok(cartridge)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::cartridge::{Cartridge, RomOnly, Storage};
+
+    #[test]
+    fn rom_banks_wrap() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x4000] = 0x42;
+        let storage = Storage::new(rom, 2, 0);
+
+        assert_eq!(storage.read_rom(3, 0x4000), 0x42);
+        assert_eq!(storage.read_rom(2, 0x0000), 0x00);
+    }
+    #[test]
+    fn short_rom_is_padded() {
+        let storage = Storage::new(vec![0x11; 0x1000], 4, 0);
+
+        assert_eq!(storage.rom_banks(), 4);
+        assert_eq!(storage.read_rom(0, 0x0fff), 0x11);
+        assert_eq!(storage.read_rom(0, 0x1000), 0x00);
+    }
+    #[test]
+    fn no_ram_reads_ff() {
+        let mut storage = Storage::new(Vec::new(), 2, 0);
+        storage.write_ram(0, 0xa000, 0x42);
+
+        assert_eq!(storage.read_ram(0, 0xa000), 0xff);
+    }
+    #[test]
+    fn empty_slot_reads_ff() {
+        let cart = RomOnly::default();
+
+        assert_eq!(cart.read(0x0000), 0xff);
+        assert_eq!(cart.read(0xa000), 0xff);
+    }
+}
@@ -85,6 +85,38 @@ impl CartridgeType {
         }
     }
 
+    /// Whether the cartridge keeps its RAM (and clock, if any) powered while switched off.
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self,
+            CartridgeType::MBC1_RAM_BATTERY
+                | CartridgeType::MBC2_BATTERY
+                | CartridgeType::ROM_RAM_BATTERY
+                | CartridgeType::MMM01_RAM_BATTERY
+                | CartridgeType::MBC3_TIMER_BATTERY
+                | CartridgeType::MBC3_TIMER_RAM_BATTERY
+                | CartridgeType::MBC3_RAM_BATTERY
+                | CartridgeType::MBC5_RAM_BATTERY
+                | CartridgeType::MBC5_RUMBLE_RAM_BATTERY
+                | CartridgeType::MBC6
+                | CartridgeType::MBC7_SENSOR_RUMBLE_RAM_BATTERY
+                | CartridgeType::POCKET_CAMERA
+                | CartridgeType::BANDAI_TAMA5
+                | CartridgeType::HuC3
+                | CartridgeType::HuC1_RAM_BATTERY
+        )
+    }
+
+    /// Whether the cartridge has a real-time clock of its own.
+    pub fn has_clock(&self) -> bool {
+        matches!(
+            self,
+            CartridgeType::MBC3_TIMER_BATTERY
+                | CartridgeType::MBC3_TIMER_RAM_BATTERY
+                | CartridgeType::HuC3
+        )
+    }
+
     pub fn to_num(ty: CartridgeType) -> u8 {
         match ty {
             CartridgeType::ROM_ONLY => 0x00,
@@ -128,6 +160,13 @@ impl CartridgeType {
     }
 }
 
+// the boot ROM refuses to start a cartridge whose header doesn't carry this exact logo
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xce, 0xed, 0x66, 0x66, 0xcc, 0x0d, 0x00, 0x0b, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0c, 0x00, 0x0d,
+    0x00, 0x08, 0x11, 0x1f, 0x88, 0x89, 0x00, 0x0e, 0xdc, 0xcc, 0x6e, 0xe6, 0xdd, 0xdd, 0xd9, 0x99,
+    0xbb, 0xbb, 0x67, 0x63, 0x6e, 0x0e, 0xec, 0xcc, 0xdd, 0xdc, 0x99, 0x9f, 0xbb, 0xb9, 0x33, 0x3e,
+];
+
 #[derive(Eq, PartialEq, Debug)]
 pub struct CartridgeHeader {
     // https://gbdev.io/pandocs/The_Cartridge_Header.html
@@ -147,6 +186,8 @@ pub struct CartridgeHeader {
     version_number: u8,       // 014c, single byte
     pub header_checksum: u8,  // 014d, single byte
     global_checksum: [u8; 2], // 014e-014f, two bytes
+
+    computed_checksum: u8, // what the boot ROM computes over 0134-014c
 }
 
 impl CartridgeHeader {
@@ -167,6 +208,8 @@ impl CartridgeHeader {
             version_number: 0,
             header_checksum: 0,
             global_checksum: [0; 2],
+
+            computed_checksum: 0,
         }
     }
 
@@ -207,6 +250,11 @@ impl CartridgeHeader {
 
         self.global_checksum[0] = data[78];
         self.global_checksum[1] = data[79];
+
+        // https://gbdev.io/pandocs/The_Cartridge_Header.html#014d--header-checksum
+        self.computed_checksum = data[52..77]
+            .iter()
+            .fold(0, |x: u8, byte| x.wrapping_sub(*byte).wrapping_sub(1));
     }
 
     pub fn from(data: &[u8]) -> CartridgeHeader {
@@ -230,6 +278,25 @@ impl CartridgeHeader {
     pub fn cgb_only(&self) -> bool {
         self.cgb_only
     }
+    pub fn logo_matches(&self) -> bool {
+        self.nintendo_logo == NINTENDO_LOGO
+    }
+    pub fn checksum_matches(&self) -> bool {
+        self.computed_checksum == self.header_checksum
+    }
+    pub fn cgb_support(&self) -> bool {
+        // set for both CGB-enhanced and CGB-only games
+        (self.title[15] & 0x80) != 0
+    }
+    pub fn nintendo_licensed(&self) -> bool {
+        // 0x33 means the new licensee code is used instead
+        self.old_licensee == 0x01 || (self.old_licensee == 0x33 && self.new_licensee == *b"01")
+    }
+    pub fn title_checksum(&self) -> u8 {
+        self.title
+            .iter()
+            .fold(0, |sum: u8, byte| sum.wrapping_add(*byte))
+    }
 }
 
 #[cfg(test)]
@@ -255,6 +322,8 @@ mod tests {
                 version_number: 0,
                 header_checksum: 0,
                 global_checksum: [0; 2],
+
+                computed_checksum: 0,
             },
             CartridgeHeader::new()
         );
@@ -363,6 +432,16 @@ mod tests {
     carttype_to_num!(huc3_to_num, 0xfe, CartridgeType::HuC3);
     carttype_to_num!(huc1_to_num, 0xff, CartridgeType::HuC1_RAM_BATTERY);
 
+    #[test]
+    fn battery_carttypes() {
+        assert!(CartridgeType::MBC1_RAM_BATTERY.has_battery());
+        assert!(CartridgeType::MBC3_TIMER_BATTERY.has_battery());
+        assert!(CartridgeType::MBC5_RUMBLE_RAM_BATTERY.has_battery());
+        assert!(CartridgeType::HuC3.has_battery());
+        assert!(!CartridgeType::MBC1_RAM.has_battery());
+        assert!(!CartridgeType::ROM_ONLY.has_battery());
+    }
+
     #[test]
     fn reads_valid_full_zeroes() {
         // test should complete without panicking
@@ -419,4 +498,24 @@ mod tests {
     fn blank_header_cgb_fetch() {
         assert_eq!(false, (CartridgeHeader::new()).cgb_only());
     }
+
+    #[test]
+    fn logo_matches() {
+        let mut read_arr = [0; 80];
+        read_arr[4..52].copy_from_slice(&super::NINTENDO_LOGO);
+        assert!(CartridgeHeader::from(&read_arr).logo_matches());
+
+        read_arr[4] = 0x00;
+        assert!(!CartridgeHeader::from(&read_arr).logo_matches());
+    }
+    #[test]
+    fn checksum_matches() {
+        // 25 zero bytes give 0 - 25 = 0xe7
+        let mut read_arr = [0; 80];
+        read_arr[77] = 0xe7;
+        assert!(CartridgeHeader::from(&read_arr).checksum_matches());
+
+        read_arr[52] = 0x01;
+        assert!(!CartridgeHeader::from(&read_arr).checksum_matches());
+    }
 }
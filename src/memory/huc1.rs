@@ -0,0 +1,126 @@
+use super::cartridge::{Cartridge, Storage};
+
+// https://gbdev.io/pandocs/HuC1.html
+
+/// Hudson's HuC1, banked like a simpler MBC1 with an infrared LED and sensor
+/// that can be switched in over RAM.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct HuC1 {
+    ram_select: u8, // 0000-1fff, 0x0e maps the infrared port, anything else RAM
+    rom_bank: u8,   // 2000-3fff, 6 bits
+    ram_bank: u8,   // 4000-5fff, 2 bits
+
+    ir_led: bool,   // what the game last wrote to the port
+    ir_light: bool, // whether the sensor sees anything
+    storage: Storage,
+}
+
+impl HuC1 {
+    pub fn new(storage: Storage) -> HuC1 {
+        HuC1 {
+            storage,
+            ..HuC1::default()
+        }
+    }
+
+    fn ir_mode(&self) -> bool {
+        (self.ram_select & 0x0f) == 0x0e
+    }
+
+    /// ROM bank mapped to 0x4000-0x7fff.
+    pub fn rom_bank_4000(&self) -> usize {
+        match self.rom_bank & 0x3f {
+            0 => 1,
+            n => n as usize,
+        }
+    }
+    /// RAM bank mapped to 0xa000-0xbfff.
+    pub fn ram_bank(&self) -> usize {
+        (self.ram_bank & 0b11) as usize
+    }
+}
+
+impl Cartridge for HuC1 {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3fff => self.storage.read_rom(0, addr),
+            0x4000..=0x7fff => self.storage.read_rom(self.rom_bank_4000(), addr),
+            // bit 0 is set while light is coming in
+            _ if self.ir_mode() => 0xc0 | self.ir_light as u8,
+            // RAM doesn't need enabling
+            _ => self.storage.read_ram(self.ram_bank(), addr),
+        }
+    }
+    fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_select = val,
+            0x2000..=0x3fff => self.rom_bank = val,
+            0x4000..=0x5fff => self.ram_bank = val,
+            // 6000-7fff, writes here go nowhere
+            0x6000..=0x7fff => {}
+            _ if self.ir_mode() => self.ir_led = (val & 0b1) != 0,
+            _ => self.storage.write_ram(self.ram_bank(), addr, val),
+        }
+    }
+
+    fn set_infrared_light(&mut self, on: bool) {
+        self.ir_light = on;
+    }
+    fn infrared_led(&self) -> bool {
+        self.ir_led
+    }
+
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::cartridge::{Cartridge, Storage};
+    use crate::memory::huc1::HuC1;
+
+    fn huc1() -> HuC1 {
+        HuC1::new(Storage::new(Vec::new(), 64, 0x8000))
+    }
+
+    #[test]
+    fn bank_0_maps_to_1() {
+        let mut mbc = huc1();
+        assert_eq!(mbc.rom_bank_4000(), 1);
+
+        mbc.write(0x2000, 0x40); // only the lower 6 bits are kept
+        assert_eq!(mbc.rom_bank_4000(), 1);
+        mbc.write(0x2000, 0x3f);
+        assert_eq!(mbc.rom_bank_4000(), 0x3f);
+    }
+    #[test]
+    fn ram_banks() {
+        let mut mbc = huc1();
+        mbc.write(0x4000, 0x02);
+        mbc.write(0xa000, 0x42);
+        mbc.write(0x4000, 0x01);
+        assert_eq!(mbc.read(0xa000), 0x00);
+
+        mbc.write(0x4000, 0x02);
+        assert_eq!(mbc.read(0xa000), 0x42);
+    }
+    #[test]
+    fn infrared_port_replaces_ram() {
+        let mut mbc = huc1();
+        mbc.write(0xa000, 0x42);
+        mbc.write(0x0000, 0x0e);
+        assert_eq!(mbc.read(0xa000), 0xc0);
+
+        mbc.set_infrared_light(true);
+        assert_eq!(mbc.read(0xa000), 0xc1);
+        mbc.write(0xa000, 0x01);
+        assert!(mbc.infrared_led());
+
+        mbc.write(0x0000, 0x0a);
+        assert_eq!(mbc.read(0xa000), 0x42);
+    }
+}
@@ -0,0 +1,356 @@
+use super::cartridge::{Cartridge, Storage};
+use super::rtc::{ClockSource, RtcClock};
+use super::MemoryError;
+
+// https://gbdev.io/pandocs/HuC3.html
+
+const MINUTES_PER_DAY: u64 = 1440;
+
+// SameBoy's layout, all little-endian: a unix timestamp as a u64, minutes, days,
+// alarm minutes and alarm days as u16s, then whether the alarm's on
+pub const CLOCK_SAVE_SIZE: usize = 17;
+
+/// The HuC3's clock, which only counts minutes into the day and days.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Clock {
+    source: ClockSource,
+    minutes: u16, // 0-1439
+    days: u16,    // 12 bits, wrapping
+    seconds: u64, // into the current minute, not visible to the game
+}
+
+impl Clock {
+    fn new() -> Clock {
+        Clock {
+            source: ClockSource::new(),
+            minutes: 0,
+            days: 0,
+            seconds: 0,
+        }
+    }
+
+    fn advance(&mut self, seconds: u64) {
+        let total = self.seconds + seconds;
+        self.seconds = total % 60;
+        let total = self.minutes as u64 + total / 60;
+        self.minutes = (total % MINUTES_PER_DAY) as u16;
+        self.days = ((self.days as u64 + total / MINUTES_PER_DAY) & 0x0fff) as u16;
+    }
+
+    fn update(&mut self) {
+        let seconds = self.source.elapsed();
+        self.advance(seconds);
+    }
+
+    fn tick(&mut self, m_cycles: u64) {
+        let seconds = self.source.tick(m_cycles);
+        self.advance(seconds);
+    }
+}
+
+/// Hudson's HuC3: MBC-style banking plus a clock that's driven by commands written
+/// into 0xa000-0xbfff, and the same infrared port as the HuC1.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HuC3 {
+    mode: u8,     // 0000-1fff, lower nibble picks what a000-bfff does
+    rom_bank: u8, // 2000-3fff, 7 bits
+    ram_bank: u8, // 4000-5fff, 2 bits
+
+    clock: Clock,
+    clock_memory: [u8; 0x100], // one nibble each, the first few hold the time
+    clock_addr: u8,
+    command: u8,  // last command, in the upper nibble of a mode 0xc read
+    response: u8, // its result, in the lower nibble
+
+    ir_led: bool,
+    ir_light: bool,
+    storage: Storage,
+}
+
+impl HuC3 {
+    pub fn new(storage: Storage) -> HuC3 {
+        HuC3 {
+            mode: 0,
+            rom_bank: 0,
+            ram_bank: 0,
+
+            clock: Clock::new(),
+            clock_memory: [0; 0x100],
+            clock_addr: 0,
+            command: 0,
+            response: 0,
+
+            ir_led: false,
+            ir_light: false,
+            storage,
+        }
+    }
+
+    /// ROM bank mapped to 0x4000-0x7fff.
+    pub fn rom_bank_4000(&self) -> usize {
+        match self.rom_bank & 0x7f {
+            0 => 1,
+            n => n as usize,
+        }
+    }
+    /// RAM bank mapped to 0xa000-0xbfff.
+    pub fn ram_bank(&self) -> usize {
+        (self.ram_bank & 0b11) as usize
+    }
+
+    // the time lives in clock memory as 3 nibbles of minutes then 3 of days,
+    // but only gets copied in or out when the game asks
+    fn time_to_memory(&mut self) {
+        self.clock.update();
+        for i in 0..3 {
+            self.clock_memory[i] = ((self.clock.minutes >> (i * 4)) & 0x0f) as u8;
+            self.clock_memory[i + 3] = ((self.clock.days >> (i * 4)) & 0x0f) as u8;
+        }
+    }
+    fn memory_to_time(&mut self) {
+        self.clock.update();
+        let nibbles = |start: usize| {
+            (0..3).fold(0, |val, i| {
+                val | ((self.clock_memory[start + i] as u16) << (i * 4))
+            })
+        };
+        self.clock.minutes = nibbles(0) % MINUTES_PER_DAY as u16;
+        self.clock.days = nibbles(3);
+        self.clock.seconds = 0;
+        self.clock.source.restart_second();
+        self.storage.mark_dirty();
+    }
+
+    fn clock_command(&mut self, val: u8) {
+        let arg = val & 0x0f;
+        self.command = (val >> 4) & 0b111;
+        match self.command {
+            // read a nibble, then move along
+            0x1 => {
+                self.response = self.clock_memory[self.clock_addr as usize];
+                self.clock_addr = self.clock_addr.wrapping_add(1);
+            }
+            // write a nibble, then move along
+            0x3 => {
+                self.clock_memory[self.clock_addr as usize] = arg;
+                self.clock_addr = self.clock_addr.wrapping_add(1);
+            }
+            0x4 => self.clock_addr = (self.clock_addr & 0xf0) | arg,
+            0x5 => self.clock_addr = (self.clock_addr & 0x0f) | (arg << 4),
+            0x6 => match arg {
+                0x0 => self.time_to_memory(),
+                0x1 => self.memory_to_time(),
+                // always ready
+                0x2 => self.response = 0x1,
+                // the speaker isn't emulated
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+impl Cartridge for HuC3 {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3fff => self.storage.read_rom(0, addr),
+            0x4000..=0x7fff => self.storage.read_rom(self.rom_bank_4000(), addr),
+            _ => match self.mode & 0x0f {
+                0x0 | 0xa => self.storage.read_ram(self.ram_bank(), addr),
+                0xc => (self.command << 4) | self.response,
+                // commands finish straight away, so the clock's always ready
+                0xd => 0xff,
+                0xe => 0xc0 | self.ir_light as u8,
+                _ => 0xff,
+            },
+        }
+    }
+    fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0x0000..=0x1fff => self.mode = val,
+            0x2000..=0x3fff => self.rom_bank = val,
+            0x4000..=0x5fff => self.ram_bank = val,
+            // 6000-7fff, writes here go nowhere
+            0x6000..=0x7fff => {}
+            _ => match self.mode & 0x0f {
+                // mode 0 is read-only
+                0xa => self.storage.write_ram(self.ram_bank(), addr, val),
+                0xb => self.clock_command(val),
+                0xe => self.ir_led = (val & 0b1) != 0,
+                _ => {}
+            },
+        }
+    }
+
+    fn tick(&mut self, m_cycles: u64) {
+        self.clock.tick(m_cycles);
+    }
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.clock.update();
+        self.clock.source.set_clock(clock);
+    }
+
+    fn set_infrared_light(&mut self, on: bool) {
+        self.ir_light = on;
+    }
+    fn infrared_led(&self) -> bool {
+        self.ir_led
+    }
+
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+
+    /// Cartridge RAM followed by the clock block. Only the time makes it in, the
+    /// alarm isn't emulated and the rest of clock memory doesn't survive a reset.
+    fn save_data(&mut self) -> Vec<u8> {
+        self.clock.update();
+
+        let mut data = self.storage.save_ram();
+        data.extend_from_slice(&self.clock.source.timestamp().to_le_bytes());
+        data.extend_from_slice(&self.clock.minutes.to_le_bytes());
+        data.extend_from_slice(&self.clock.days.to_le_bytes());
+        // no alarm
+        data.extend_from_slice(&[0; 5]);
+        data
+    }
+    fn load_save_data(&mut self, data: &[u8]) -> Result<(), MemoryError> {
+        // checked before anything's loaded, so a bad save leaves everything as it was
+        let rest = self.storage.save_trailer(data)?;
+        match rest.len() {
+            // saves without a clock leave it where it is
+            0 | CLOCK_SAVE_SIZE => {}
+            _ => {
+                return Err(MemoryError::InvalidSaveSize {
+                    size: data.len(),
+                    expected: data.len() - rest.len() + CLOCK_SAVE_SIZE,
+                })
+            }
+        }
+
+        self.storage.load_ram(data)?;
+        if rest.is_empty() {
+            return Ok(());
+        }
+
+        let word = |i: usize| u16::from_le_bytes([rest[i], rest[i + 1]]);
+        self.clock.minutes = word(8) % MINUTES_PER_DAY as u16;
+        self.clock.days = word(10) & 0x0fff;
+        self.clock.seconds = 0;
+
+        // catch up on the time spent switched off
+        let timestamp = u64::from_le_bytes(rest[0..8].try_into().unwrap());
+        let seconds = self.clock.source.resume_from(timestamp);
+        self.clock.advance(seconds);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::cartridge::{Cartridge, Storage};
+    use crate::memory::huc3::{HuC3, CLOCK_SAVE_SIZE};
+    use crate::memory::rtc::{RtcClock, M_CYCLES_PER_SECOND};
+    use crate::memory::MemoryError;
+
+    fn huc3() -> HuC3 {
+        let mut mbc = HuC3::new(Storage::new(Vec::new(), 128, 0x8000));
+        mbc.set_rtc_clock(RtcClock::Emulated);
+        mbc
+    }
+    fn command(mbc: &mut HuC3, val: u8) -> u8 {
+        mbc.write(0x0000, 0x0b);
+        mbc.write(0xa000, val);
+        mbc.write(0x0000, 0x0c);
+        mbc.read(0xa000) & 0x0f
+    }
+    fn read_nibbles(mbc: &mut HuC3, addr: u8, count: usize) -> u16 {
+        command(mbc, 0x40 | (addr & 0x0f));
+        command(mbc, 0x50 | (addr >> 4));
+        (0..count).fold(0, |val, i| val | ((command(mbc, 0x10) as u16) << (i * 4)))
+    }
+
+    #[test]
+    fn ram_read_only_in_mode_0() {
+        let mut mbc = huc3();
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0xa000, 0x42);
+        mbc.write(0x0000, 0x00);
+        mbc.write(0xa000, 0x24);
+
+        assert_eq!(mbc.read(0xa000), 0x42);
+    }
+    #[test]
+    fn clock_memory_read_write() {
+        let mut mbc = huc3();
+        command(&mut mbc, 0x40);
+        command(&mut mbc, 0x52);
+        command(&mut mbc, 0x37);
+        command(&mut mbc, 0x39);
+
+        assert_eq!(read_nibbles(&mut mbc, 0x20, 2), 0x97);
+    }
+    #[test]
+    fn time_copies_into_memory() {
+        let mut mbc = huc3();
+        mbc.tick(M_CYCLES_PER_SECOND * 60 * (1440 + 62));
+        command(&mut mbc, 0x60);
+
+        assert_eq!(read_nibbles(&mut mbc, 0x00, 3), 62);
+        assert_eq!(read_nibbles(&mut mbc, 0x03, 3), 1);
+    }
+    #[test]
+    fn time_set_from_memory() {
+        let mut mbc = huc3();
+        command(&mut mbc, 0x40);
+        command(&mut mbc, 0x50);
+        for nibble in [0x3, 0x2, 0x0, 0x5, 0x0, 0x0] {
+            command(&mut mbc, 0x30 | nibble);
+        }
+        command(&mut mbc, 0x61);
+        mbc.tick(M_CYCLES_PER_SECOND * 60);
+        command(&mut mbc, 0x60);
+
+        assert_eq!(read_nibbles(&mut mbc, 0x00, 3), 0x024);
+        assert_eq!(read_nibbles(&mut mbc, 0x03, 3), 0x005);
+    }
+    #[test]
+    fn status_is_ready() {
+        let mut mbc = huc3();
+
+        assert_eq!(command(&mut mbc, 0x62), 0x1);
+        mbc.write(0x0000, 0x0d);
+        assert_eq!(mbc.read(0xa000) & 0b1, 0b1);
+    }
+    #[test]
+    fn clock_save_round_trip() {
+        let mut mbc = huc3();
+        mbc.tick(M_CYCLES_PER_SECOND * 60 * 100);
+        let data = mbc.save_data();
+        assert_eq!(data.len(), 0x8000 + CLOCK_SAVE_SIZE);
+
+        let mut loaded = huc3();
+        loaded.load_save_data(&data).unwrap();
+        command(&mut loaded, 0x60);
+        assert_eq!(read_nibbles(&mut loaded, 0x00, 3), 100);
+    }
+    #[test]
+    fn clock_save_wrong_size_invalid() {
+        let mut mbc = huc3();
+
+        assert!(mbc.load_save_data(&[0; 0x8000]).is_ok());
+        assert_eq!(
+            mbc.load_save_data(&[0x42; 0x8000 + 12]),
+            Err(MemoryError::InvalidSaveSize {
+                size: 0x8000 + 12,
+                expected: 0x8000 + CLOCK_SAVE_SIZE,
+            })
+        );
+        // nothing gets loaded from a bad save
+        mbc.write(0x0000, 0x0a);
+        assert_eq!(mbc.read(0xa000), 0x00);
+    }
+}
@@ -0,0 +1,244 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+/// Size of the Pocket Camera's sensor, in pixels.
+pub const SENSOR_WIDTH: usize = 128;
+pub const SENSOR_HEIGHT: usize = 112;
+
+// anything bigger than this on either side isn't worth scaling down
+const MAX_PGM_DIMENSION: usize = 0x2000;
+
+/// Whatever the Pocket Camera is pointed at.
+pub trait ImageSource {
+    /// Fills in the next frame, `SENSOR_WIDTH * SENSOR_HEIGHT` pixels row by row,
+    /// from 0 for black to 255 for white.
+    fn capture(&mut self, frame: &mut [u8]);
+}
+
+/// Grey bars with a gradient underneath that moves along a little with every
+/// capture, so there's always something to see.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TestPattern {
+    frame: u8,
+}
+
+impl TestPattern {
+    pub fn new() -> TestPattern {
+        TestPattern::default()
+    }
+}
+
+impl ImageSource for TestPattern {
+    fn capture(&mut self, frame: &mut [u8]) {
+        for (i, pixel) in frame.iter_mut().enumerate() {
+            let (x, y) = (i % SENSOR_WIDTH, i / SENSOR_WIDTH);
+            *pixel = if y < SENSOR_HEIGHT * 3 / 4 {
+                // white down to black in 4 bars
+                0xff - ((x * 4 / SENSOR_WIDTH) * 0x55) as u8
+            } else {
+                ((x * 2) as u8).wrapping_add(self.frame)
+            };
+        }
+        self.frame = self.frame.wrapping_add(4);
+    }
+}
+
+/// The same picture every time, loaded from a PGM file and stretched to fit the sensor.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct StaticImage {
+    pixels: Vec<u8>,
+}
+
+impl StaticImage {
+    pub fn open(path: &Path) -> Result<StaticImage> {
+        StaticImage::from_pgm(&fs::read(path)?)
+    }
+    /// Takes a binary (P5) or plain (P2) PGM image.
+    pub fn from_pgm(data: &[u8]) -> Result<StaticImage> {
+        Ok(StaticImage {
+            pixels: read_pgm(data)?,
+        })
+    }
+}
+
+impl ImageSource for StaticImage {
+    fn capture(&mut self, frame: &mut [u8]) {
+        frame.copy_from_slice(&self.pixels);
+    }
+}
+
+/// Every PGM file in a directory, played back in name order and looping at the end.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FrameDirectory {
+    frames: Vec<Vec<u8>>,
+    next: usize,
+}
+
+impl FrameDirectory {
+    pub fn open(dir: &Path) -> Result<FrameDirectory> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "pgm") {
+                paths.push(path);
+            }
+        }
+        if paths.is_empty() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                "no .pgm files in directory",
+            ));
+        }
+        paths.sort();
+
+        let frames = paths
+            .iter()
+            .map(|path| read_pgm(&fs::read(path)?))
+            .collect::<Result<_>>()?;
+        Ok(FrameDirectory { frames, next: 0 })
+    }
+}
+
+impl ImageSource for FrameDirectory {
+    fn capture(&mut self, frame: &mut [u8]) {
+        frame.copy_from_slice(&self.frames[self.next]);
+        self.next = (self.next + 1) % self.frames.len();
+    }
+}
+
+// https://netpbm.sourceforge.net/doc/pgm.html
+// reads either kind of PGM, scaled to 0-255 and stretched to the sensor's size
+fn read_pgm(data: &[u8]) -> Result<Vec<u8>> {
+    let invalid = |reason: &str| {
+        let reason = format!("bad PGM: {reason}");
+        Error::new(ErrorKind::InvalidData, reason)
+    };
+
+    let mut pos = 0;
+    // the next number in the header, skipping whitespace and comments
+    let next_token = |pos: &mut usize| -> Result<usize> {
+        loop {
+            match data.get(*pos) {
+                Some(b'#') => {
+                    while data.get(*pos).is_some_and(|&c| c != b'\n') {
+                        *pos += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => *pos += 1,
+                _ => break,
+            }
+        }
+        let start = *pos;
+        while data.get(*pos).is_some_and(u8::is_ascii_digit) {
+            *pos += 1;
+        }
+        std::str::from_utf8(&data[start..*pos])
+            .ok()
+            .and_then(|num| num.parse().ok())
+            .ok_or_else(|| invalid("expected a number"))
+    };
+
+    let plain = match data.get(0..2) {
+        Some(b"P5") => false,
+        Some(b"P2") => true,
+        _ => return Err(invalid("not a PGM file")),
+    };
+    pos += 2;
+    let width = next_token(&mut pos)?;
+    let height = next_token(&mut pos)?;
+    let max = next_token(&mut pos)?;
+    let dimensions = 1..=MAX_PGM_DIMENSION;
+    if !dimensions.contains(&width) || !dimensions.contains(&height) || max == 0 || max > 0xffff {
+        return Err(invalid("size out of range"));
+    }
+    let count = width * height;
+
+    let pixels = if plain {
+        // every pixel takes up at least a byte, so a short file can't ask for much
+        let mut pixels = Vec::with_capacity(count.min(data.len()));
+        for _ in 0..count {
+            pixels.push(next_token(&mut pos)?);
+        }
+        pixels
+    } else {
+        // exactly one whitespace character before the pixels start
+        pos += 1;
+        let bytes = if max > 0xff { 2 } else { 1 };
+        let raster = count
+            .checked_mul(bytes)
+            .and_then(|len| data.get(pos..pos.checked_add(len)?))
+            .ok_or_else(|| invalid("image data cut short"))?;
+        raster
+            .chunks(bytes)
+            .map(|pixel| match pixel {
+                [hi, lo] => u16::from_be_bytes([*hi, *lo]) as usize,
+                _ => pixel[0] as usize,
+            })
+            .collect()
+    };
+
+    // nearest neighbour is plenty for a sensor this small
+    let mut frame = Vec::with_capacity(SENSOR_WIDTH * SENSOR_HEIGHT);
+    for y in 0..SENSOR_HEIGHT {
+        for x in 0..SENSOR_WIDTH {
+            let (src_x, src_y) = (x * width / SENSOR_WIDTH, y * height / SENSOR_HEIGHT);
+            let pixel = pixels[(src_y * width) + src_x];
+            frame.push((pixel.min(max) * 0xff / max) as u8);
+        }
+    }
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+
+    use crate::memory::imagesource::{
+        ImageSource, StaticImage, TestPattern, SENSOR_HEIGHT, SENSOR_WIDTH,
+    };
+
+    #[test]
+    fn binary_pgm_is_stretched() {
+        // 2x2, dark on the left and light on the right
+        let mut data = b"P5\n# a comment\n2 2\n255\n".to_vec();
+        data.extend_from_slice(&[0x00, 0xff, 0x00, 0xff]);
+        let mut image = StaticImage::from_pgm(&data).unwrap();
+
+        let mut frame = vec![0x42; SENSOR_WIDTH * SENSOR_HEIGHT];
+        image.capture(&mut frame);
+        assert_eq!(frame[0], 0x00);
+        assert_eq!(frame[SENSOR_WIDTH - 1], 0xff);
+        assert_eq!(frame[(SENSOR_HEIGHT - 1) * SENSOR_WIDTH], 0x00);
+    }
+    #[test]
+    fn plain_pgm_is_scaled() {
+        let image = StaticImage::from_pgm(b"P2 1 1 15 15").unwrap();
+
+        assert_eq!(image.pixels[0], 0xff);
+    }
+    #[test]
+    fn truncated_pgm_invalid() {
+        assert!(StaticImage::from_pgm(b"P5 4 4 255\n\x00\x00").is_err());
+        assert!(StaticImage::from_pgm(b"P6 1 1 255\n\x00").is_err());
+    }
+    #[test]
+    fn huge_pgm_invalid() {
+        let huge = b"P5 99999999999 99999999999 255\n\x00";
+        let err = StaticImage::from_pgm(huge).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(StaticImage::from_pgm(b"P2 8192 8192 255 0 0").is_err());
+    }
+    #[test]
+    fn test_pattern_moves() {
+        let mut pattern = TestPattern::new();
+        let mut first = vec![0; SENSOR_WIDTH * SENSOR_HEIGHT];
+        let mut second = first.clone();
+        pattern.capture(&mut first);
+        pattern.capture(&mut second);
+
+        assert_eq!(first[0], 0xff);
+        assert_ne!(first, second);
+    }
+}
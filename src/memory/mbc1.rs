@@ -0,0 +1,181 @@
+use super::cartridge::{Cartridge, Storage};
+use super::cartridgeheader::NINTENDO_LOGO;
+
+// https://gbdev.io/pandocs/MBC1.html
+
+/// MBC1 and the ROM and RAM behind it. Writes to 0x0000-0x7fff land in one of the
+/// control registers, the banks they select are worked out whenever memory is read.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Mbc1 {
+    ram_enable: u8, // 0000-1fff, 0x0a in the lower nibble enables RAM
+    bank_lo: u8,    // 2000-3fff, lower 5 bits of the ROM bank
+    bank_hi: u8,    // 4000-5fff, RAM bank or upper 2 bits of the ROM bank
+    mode: u8,       // 6000-7fff, banking mode select
+
+    multicart: bool, // MBC1M, upper bits are shifted down by one
+    storage: Storage,
+}
+
+impl Mbc1 {
+    pub fn new(storage: Storage) -> Mbc1 {
+        if is_multicart(&storage) {
+            return Mbc1::multicart(storage);
+        }
+        Mbc1 {
+            storage,
+            ..Mbc1::default()
+        }
+    }
+    pub fn multicart(storage: Storage) -> Mbc1 {
+        Mbc1 {
+            multicart: true,
+            storage,
+            ..Mbc1::default()
+        }
+    }
+
+    pub fn ram_enabled(&self) -> bool {
+        (self.ram_enable & 0x0f) == 0x0a
+    }
+    fn advanced_mode(&self) -> bool {
+        (self.mode & 0b1) != 0
+    }
+    fn bank_hi_shifted(&self) -> usize {
+        let shift = if self.multicart { 4 } else { 5 };
+        ((self.bank_hi & 0b11) as usize) << shift
+    }
+
+    // bank numbers aren't masked to the cartridge size here,
+    // that's left to whoever owns the banks
+
+    /// ROM bank mapped to 0x0000-0x3fff.
+    pub fn rom_bank_0000(&self) -> usize {
+        if self.advanced_mode() {
+            self.bank_hi_shifted()
+        } else {
+            0
+        }
+    }
+    /// ROM bank mapped to 0x4000-0x7fff.
+    pub fn rom_bank_4000(&self) -> usize {
+        // only the 5 bit register is checked for 0,
+        // so banks 0x20, 0x40 and 0x60 can't be selected here either
+        let mut lo = self.bank_lo & 0x1f;
+        if lo == 0 {
+            lo = 1;
+        }
+        // bit 4 isn't connected on multicarts
+        if self.multicart {
+            lo &= 0x0f;
+        }
+
+        self.bank_hi_shifted() | lo as usize
+    }
+    /// RAM bank mapped to 0xa000-0xbfff.
+    pub fn ram_bank(&self) -> usize {
+        if self.advanced_mode() {
+            (self.bank_hi & 0b11) as usize
+        } else {
+            0
+        }
+    }
+}
+
+// MBC1M multicarts are wired so the upper bank bits start at bit 4,
+// giving each 256 KiB game its own header (and logo) at banks 0x00, 0x10, 0x20 and 0x30
+fn is_multicart(storage: &Storage) -> bool {
+    storage.rom_banks() == 64 && storage.rom_bank(0x10)[0x0104..0x0134] == NINTENDO_LOGO
+}
+
+impl Cartridge for Mbc1 {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3fff => self.storage.read_rom(self.rom_bank_0000(), addr),
+            0x4000..=0x7fff => self.storage.read_rom(self.rom_bank_4000(), addr),
+            _ if self.ram_enabled() => self.storage.read_ram(self.ram_bank(), addr),
+            _ => 0xff,
+        }
+    }
+    fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enable = val,
+            0x2000..=0x3fff => self.bank_lo = val,
+            0x4000..=0x5fff => self.bank_hi = val,
+            0x6000..=0x7fff => self.mode = val,
+            _ if self.ram_enabled() => self.storage.write_ram(self.ram_bank(), addr, val),
+            _ => {}
+        }
+    }
+
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::cartridge::{Cartridge, Storage};
+    use crate::memory::mbc1::Mbc1;
+
+    #[test]
+    fn ram_enable_needs_0a() {
+        let mut mbc = Mbc1::new(Storage::default());
+        assert!(!mbc.ram_enabled());
+
+        mbc.write(0x1234, 0x0a);
+        assert!(mbc.ram_enabled());
+        mbc.write(0x0000, 0xfa);
+        assert!(mbc.ram_enabled());
+        mbc.write(0x0000, 0x0b);
+        assert!(!mbc.ram_enabled());
+    }
+    #[test]
+    fn bank_0_maps_to_1() {
+        let mut mbc = Mbc1::new(Storage::default());
+        assert_eq!(mbc.rom_bank_4000(), 1);
+
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.rom_bank_4000(), 1);
+        mbc.write(0x2000, 0x20); // only the lower 5 bits are kept
+        assert_eq!(mbc.rom_bank_4000(), 1);
+        mbc.write(0x3fff, 0x1f);
+        assert_eq!(mbc.rom_bank_4000(), 0x1f);
+    }
+    #[test]
+    fn upper_bits_extend_rom_bank() {
+        let mut mbc = Mbc1::new(Storage::default());
+        mbc.write(0x4000, 0x02);
+        mbc.write(0x2000, 0x00);
+
+        assert_eq!(mbc.rom_bank_4000(), 0x41);
+        // simple mode doesn't touch the first bank or RAM
+        assert_eq!(mbc.rom_bank_0000(), 0);
+        assert_eq!(mbc.ram_bank(), 0);
+    }
+    #[test]
+    fn advanced_mode_remaps_first_bank_and_ram() {
+        let mut mbc = Mbc1::new(Storage::default());
+        mbc.write(0x4000, 0x03);
+        mbc.write(0x6000, 0x01);
+
+        assert_eq!(mbc.rom_bank_0000(), 0x60);
+        assert_eq!(mbc.rom_bank_4000(), 0x61);
+        assert_eq!(mbc.ram_bank(), 3);
+    }
+    #[test]
+    fn multicart_upper_bits_start_at_bit_4() {
+        let mut mbc = Mbc1::multicart(Storage::default());
+        mbc.write(0x4000, 0x01);
+        mbc.write(0x6000, 0x01);
+
+        assert_eq!(mbc.rom_bank_0000(), 0x10);
+        assert_eq!(mbc.rom_bank_4000(), 0x11);
+        mbc.write(0x2000, 0x12); // bit 4 is dropped
+        assert_eq!(mbc.rom_bank_4000(), 0x12);
+        mbc.write(0x2000, 0x10); // but still counts for the 0 check
+        assert_eq!(mbc.rom_bank_4000(), 0x10);
+    }
+}
@@ -0,0 +1,100 @@
+use super::cartridge::{Cartridge, Storage};
+
+// https://gbdev.io/pandocs/MBC2.html
+
+/// MBC2 and its built-in RAM. Both control registers live in 0x0000-0x3fff,
+/// bit 8 of the address picks which one gets written.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Mbc2 {
+    ram_enable: u8, // address bit 8 clear, 0x0a in the lower nibble enables RAM
+    rom_bank: u8,   // address bit 8 set, lower 4 bits of the ROM bank
+
+    storage: Storage, // RAM is 512 half-bytes, echoed across all of a000-bfff
+}
+
+impl Mbc2 {
+    pub fn new(storage: Storage) -> Mbc2 {
+        Mbc2 {
+            storage,
+            ..Mbc2::default()
+        }
+    }
+
+    pub fn ram_enabled(&self) -> bool {
+        (self.ram_enable & 0x0f) == 0x0a
+    }
+
+    /// ROM bank mapped to 0x4000-0x7fff.
+    pub fn rom_bank_4000(&self) -> usize {
+        match self.rom_bank & 0x0f {
+            0 => 1,
+            n => n as usize,
+        }
+    }
+}
+
+impl Cartridge for Mbc2 {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3fff => self.storage.read_rom(0, addr),
+            0x4000..=0x7fff => self.storage.read_rom(self.rom_bank_4000(), addr),
+            // RAM is only 4 bits wide, the upper bits read as 1s
+            _ if self.ram_enabled() => 0xf0 | self.storage.read_ram(0, addr),
+            _ => 0xff,
+        }
+    }
+    fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0x0000..=0x3fff if (addr & 0x0100) == 0 => self.ram_enable = val,
+            0x0000..=0x3fff => self.rom_bank = val,
+            // 4000-7fff, writes here go nowhere
+            0x4000..=0x7fff => {}
+            _ if self.ram_enabled() => self.storage.write_ram(0, addr, val & 0x0f),
+            _ => {}
+        }
+    }
+
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::cartridge::{Cartridge, Storage};
+    use crate::memory::mbc2::Mbc2;
+
+    #[test]
+    fn address_bit_8_selects_register() {
+        let mut mbc = Mbc2::new(Storage::default());
+        mbc.write(0x0100, 0x0a);
+        assert!(!mbc.ram_enabled());
+        assert_eq!(mbc.rom_bank_4000(), 0x0a);
+
+        mbc.write(0x3e00, 0x0a);
+        assert!(mbc.ram_enabled());
+        assert_eq!(mbc.rom_bank_4000(), 0x0a);
+    }
+    #[test]
+    fn bank_0_maps_to_1() {
+        let mut mbc = Mbc2::new(Storage::default());
+        assert_eq!(mbc.rom_bank_4000(), 1);
+
+        mbc.write(0x2100, 0x10); // only the lower 4 bits are kept
+        assert_eq!(mbc.rom_bank_4000(), 1);
+        mbc.write(0x2100, 0x0f);
+        assert_eq!(mbc.rom_bank_4000(), 0x0f);
+    }
+    #[test]
+    fn upper_half_ignored() {
+        let mut mbc = Mbc2::new(Storage::default());
+        mbc.write(0x4100, 0x05);
+        mbc.write(0x6000, 0x0a);
+
+        assert_eq!(mbc.rom_bank_4000(), 1);
+        assert!(!mbc.ram_enabled());
+    }
+}
@@ -0,0 +1,482 @@
+use super::cartridge::{Cartridge, Storage};
+use super::rtc::{ClockSource, RtcClock};
+use super::MemoryError;
+
+// https://gbdev.io/pandocs/MBC3.html
+
+// the clock block other emulators append to save files: live registers and latched
+// registers as little-endian u32s, then a unix timestamp that's either 64 or 32 bits
+pub const RTC_SAVE_SIZE: usize = 48;
+const RTC_SAVE_SIZE_SHORT: usize = 44;
+
+/// The clock counters, in the order they're selected through 0x4000-0x5fff (0x08-0x0c).
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct RtcRegisters {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    pub days_lo: u8,
+    pub days_hi: u8, // bit 0 is day bit 8, bit 6 halts the clock, bit 7 is the day carry
+}
+
+impl RtcRegisters {
+    fn get(&self, reg: usize) -> u8 {
+        match reg {
+            0 => self.seconds,
+            1 => self.minutes,
+            2 => self.hours,
+            3 => self.days_lo,
+            _ => self.days_hi,
+        }
+    }
+    // unused bits aren't stored
+    fn set(&mut self, reg: usize, val: u8) {
+        match reg {
+            0 => self.seconds = val & 0x3f,
+            1 => self.minutes = val & 0x3f,
+            2 => self.hours = val & 0x1f,
+            3 => self.days_lo = val,
+            _ => self.days_hi = val & 0xc1,
+        }
+    }
+
+    fn halted(&self) -> bool {
+        (self.days_hi & 0x40) != 0
+    }
+
+    fn advance(&mut self, seconds: u64) {
+        if self.halted() || seconds == 0 {
+            return;
+        }
+
+        let total = self.seconds as u64 + seconds;
+        self.seconds = (total % 60) as u8;
+        let total = self.minutes as u64 + total / 60;
+        self.minutes = (total % 60) as u8;
+        let total = self.hours as u64 + total / 60;
+        self.hours = (total % 24) as u8;
+
+        let days = ((((self.days_hi & 0b1) as u64) << 8) | self.days_lo as u64) + total / 24;
+        if days > 0x1ff {
+            self.days_hi |= 0x80;
+        }
+        self.days_lo = days as u8;
+        self.days_hi = (self.days_hi & !0b1) | ((days >> 8) & 0b1) as u8;
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Rtc {
+    source: ClockSource,
+    live: RtcRegisters,
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc {
+            source: ClockSource::new(),
+            live: RtcRegisters::default(),
+        }
+    }
+
+    fn update(&mut self) {
+        let seconds = self.source.elapsed();
+        self.live.advance(seconds);
+    }
+
+    fn tick(&mut self, m_cycles: u64) {
+        if self.live.halted() {
+            return;
+        }
+        let seconds = self.source.tick(m_cycles);
+        self.live.advance(seconds);
+    }
+
+    fn write(&mut self, reg: usize, val: u8) {
+        self.update();
+        self.live.set(reg, val);
+        if reg == 0 {
+            // writing the seconds restarts the current second
+            self.source.restart_second();
+        }
+    }
+}
+
+/// MBC3, the ROM and RAM behind it and, on the TIMER variants, the real-time clock.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Mbc3 {
+    ram_enable: u8, // 0000-1fff, 0x0a in the lower nibble enables RAM and the clock
+    rom_bank: u8,   // 2000-3fff, 7 bits
+    ram_select: u8, // 4000-5fff, RAM bank 0-3 or clock register 0x08-0x0c
+    latch: u8,      // 6000-7fff, writing 0 then 1 latches the clock
+
+    rtc: Option<Rtc>,
+    latched: RtcRegisters, // what the game reads back
+    storage: Storage,
+}
+
+impl Mbc3 {
+    pub fn new(storage: Storage, has_rtc: bool) -> Mbc3 {
+        Mbc3 {
+            ram_enable: 0,
+            rom_bank: 0,
+            ram_select: 0,
+            latch: 0xff,
+
+            rtc: if has_rtc { Some(Rtc::new()) } else { None },
+            latched: RtcRegisters::default(),
+            storage,
+        }
+    }
+
+    fn rtc_save_data(&mut self) -> Option<Vec<u8>> {
+        let rtc = self.rtc.as_mut()?;
+        rtc.update();
+
+        let mut data = Vec::with_capacity(RTC_SAVE_SIZE);
+        for regs in [&rtc.live, &self.latched] {
+            for reg in 0..5 {
+                data.extend_from_slice(&(regs.get(reg) as u32).to_le_bytes());
+            }
+        }
+        data.extend_from_slice(&rtc.source.timestamp().to_le_bytes());
+
+        Some(data)
+    }
+    /// Restores the clock from a save file's block, which should already be checked over,
+    /// catching up on the time spent switched off when running from the host clock.
+    /// An empty block leaves the clock where it is.
+    fn load_rtc_save_data(&mut self, data: &[u8]) {
+        let rtc = match &mut self.rtc {
+            Some(rtc) if !data.is_empty() => rtc,
+            _ => return,
+        };
+
+        let word = |i: usize| u32::from_le_bytes(data[i * 4..i * 4 + 4].try_into().unwrap());
+        for reg in 0..5 {
+            rtc.live.set(reg, word(reg) as u8);
+            self.latched.set(reg, word(reg + 5) as u8);
+        }
+
+        let timestamp = if data.len() == RTC_SAVE_SIZE {
+            u64::from_le_bytes(data[40..48].try_into().unwrap())
+        } else {
+            word(10) as u64
+        };
+        let seconds = rtc.source.resume_from(timestamp);
+        rtc.live.advance(seconds);
+    }
+
+    pub fn ram_enabled(&self) -> bool {
+        (self.ram_enable & 0x0f) == 0x0a
+    }
+
+    /// ROM bank mapped to 0x4000-0x7fff.
+    pub fn rom_bank_4000(&self) -> usize {
+        match self.rom_bank & 0x7f {
+            0 => 1,
+            n => n as usize,
+        }
+    }
+    /// RAM bank mapped to 0xa000-0xbfff, or None if a clock register is.
+    pub fn ram_bank(&self) -> Option<usize> {
+        match self.ram_select {
+            0x00..=0x03 => Some(self.ram_select as usize),
+            _ => None,
+        }
+    }
+
+    // clock register selected in 0xa000-0xbfff, if it's there to be accessed
+    fn rtc_register(&self) -> Option<usize> {
+        match (&self.rtc, self.ram_select) {
+            (Some(_), 0x08..=0x0c) if self.ram_enabled() => Some((self.ram_select - 0x08) as usize),
+            _ => None,
+        }
+    }
+
+    fn write_latch(&mut self, val: u8) {
+        if self.latch == 0x00 && val == 0x01 {
+            if let Some(rtc) = &mut self.rtc {
+                rtc.update();
+                self.latched = rtc.live;
+            }
+        }
+        self.latch = val;
+    }
+}
+
+impl Cartridge for Mbc3 {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3fff => self.storage.read_rom(0, addr),
+            0x4000..=0x7fff => self.storage.read_rom(self.rom_bank_4000(), addr),
+            // clock registers share the space with RAM
+            _ => match (self.rtc_register(), self.ram_bank()) {
+                (Some(reg), _) => self.latched.get(reg),
+                (None, Some(bank)) if self.ram_enabled() => self.storage.read_ram(bank, addr),
+                _ => 0xff,
+            },
+        }
+    }
+    fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enable = val,
+            0x2000..=0x3fff => self.rom_bank = val,
+            0x4000..=0x5fff => self.ram_select = val,
+            0x6000..=0x7fff => self.write_latch(val),
+            _ => match (self.rtc_register(), self.ram_bank()) {
+                (Some(reg), _) => {
+                    self.latched.set(reg, val);
+                    if let Some(rtc) = &mut self.rtc {
+                        rtc.write(reg, val);
+                    }
+                    self.storage.mark_dirty();
+                }
+                (None, Some(bank)) if self.ram_enabled() => self.storage.write_ram(bank, addr, val),
+                // with no clock register to go to the write is dropped
+                _ => {}
+            },
+        }
+    }
+
+    fn tick(&mut self, m_cycles: u64) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(m_cycles);
+        }
+    }
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.update();
+            rtc.source.set_clock(clock);
+        }
+    }
+
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+
+    /// Cartridge RAM followed by the clock block on carts with a timer.
+    fn save_data(&mut self) -> Vec<u8> {
+        let mut data = self.storage.save_ram();
+        if let Some(rtc_data) = self.rtc_save_data() {
+            data.extend(rtc_data);
+        }
+        data
+    }
+    fn load_save_data(&mut self, data: &[u8]) -> Result<(), MemoryError> {
+        // checked before anything's loaded, so a bad save leaves everything as it was
+        let rest = self.storage.save_trailer(data)?;
+        match (&self.rtc, rest.len()) {
+            // without a clock anything past RAM is left alone, like on other controllers
+            (None, _) => {}
+            // older saves might not have a clock block
+            (Some(_), 0 | RTC_SAVE_SIZE | RTC_SAVE_SIZE_SHORT) => {}
+            _ => {
+                return Err(MemoryError::InvalidSaveSize {
+                    size: data.len(),
+                    expected: data.len() - rest.len() + RTC_SAVE_SIZE,
+                })
+            }
+        }
+
+        self.storage.load_ram(data)?;
+        self.load_rtc_save_data(rest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::cartridge::{Cartridge, Storage};
+    use crate::memory::mbc3::{Mbc3, RtcRegisters, RTC_SAVE_SIZE};
+    use crate::memory::rtc::{RtcClock, M_CYCLES_PER_SECOND};
+    use crate::memory::MemoryError;
+
+    fn read_rtc(mbc: &mut Mbc3, reg: u8) -> u8 {
+        mbc.write(0x4000, 0x08 + reg);
+        mbc.read(0xa000)
+    }
+    fn latch(mbc: &mut Mbc3) {
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x6000, 0x01);
+    }
+    fn with_rtc(has_rtc: bool) -> Mbc3 {
+        Mbc3::new(Storage::default(), has_rtc)
+    }
+    fn emulated_rtc() -> Mbc3 {
+        let mut mbc = with_rtc(true);
+        mbc.set_rtc_clock(RtcClock::Emulated);
+        mbc.write(0x0000, 0x0a);
+        mbc
+    }
+
+    #[test]
+    fn bank_0_maps_to_1() {
+        let mut mbc = with_rtc(false);
+        assert_eq!(mbc.rom_bank_4000(), 1);
+
+        mbc.write(0x2000, 0x80); // only the lower 7 bits are kept
+        assert_eq!(mbc.rom_bank_4000(), 1);
+        mbc.write(0x2000, 0x7f);
+        assert_eq!(mbc.rom_bank_4000(), 0x7f);
+    }
+    #[test]
+    fn ram_select_picks_bank_or_clock() {
+        let mut mbc = with_rtc(true);
+        mbc.write(0x4000, 0x02);
+        assert_eq!(mbc.ram_bank(), Some(2));
+
+        mbc.write(0x4000, 0x08);
+        assert_eq!(mbc.ram_bank(), None);
+        // still needs enabling
+        assert_eq!(mbc.read(0xa000), 0xff);
+        mbc.write(0x0000, 0x0a);
+        assert_eq!(mbc.read(0xa000), 0x00);
+    }
+    #[test]
+    fn no_clock_without_timer() {
+        let mut mbc = with_rtc(false);
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0x4000, 0x08);
+
+        mbc.write(0xa000, 0x01);
+        assert_eq!(mbc.read(0xa000), 0xff);
+    }
+    #[test]
+    fn latch_freezes_reads() {
+        let mut mbc = emulated_rtc();
+        mbc.tick(M_CYCLES_PER_SECOND * 61);
+        assert_eq!(read_rtc(&mut mbc, 0), 0);
+
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0), 1);
+        assert_eq!(read_rtc(&mut mbc, 1), 1);
+
+        mbc.tick(M_CYCLES_PER_SECOND);
+        assert_eq!(read_rtc(&mut mbc, 0), 1);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0), 2);
+    }
+    #[test]
+    fn latch_needs_0_then_1() {
+        let mut mbc = emulated_rtc();
+        mbc.tick(M_CYCLES_PER_SECOND * 5);
+        mbc.write(0x6000, 0x01);
+        mbc.write(0x6000, 0x01);
+
+        assert_eq!(read_rtc(&mut mbc, 0), 0);
+    }
+    #[test]
+    fn partial_seconds_carry_over() {
+        let mut mbc = emulated_rtc();
+        mbc.tick(M_CYCLES_PER_SECOND / 2);
+        mbc.tick(M_CYCLES_PER_SECOND / 2);
+        latch(&mut mbc);
+
+        assert_eq!(read_rtc(&mut mbc, 0), 1);
+    }
+    #[test]
+    fn writes_set_the_clock() {
+        let mut mbc = emulated_rtc();
+        mbc.write(0x4000, 0x0a);
+        mbc.write(0xa000, 0x17);
+        assert_eq!(mbc.read(0xa000), 0x17);
+
+        mbc.tick(M_CYCLES_PER_SECOND * 3600);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 2), 0x00);
+        assert_eq!(read_rtc(&mut mbc, 3), 0x01);
+    }
+    #[test]
+    fn halt_stops_the_clock() {
+        let mut mbc = emulated_rtc();
+        mbc.write(0x4000, 0x0c);
+        mbc.write(0xa000, 0x40);
+        mbc.write(0x4000, 0x08);
+
+        mbc.tick(M_CYCLES_PER_SECOND * 10);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, 0), 0);
+    }
+    #[test]
+    fn day_counter_overflow_sets_carry() {
+        let mut regs = RtcRegisters {
+            days_lo: 0xff,
+            days_hi: 0x01,
+            hours: 23,
+            minutes: 59,
+            seconds: 59,
+        };
+        regs.advance(1);
+
+        assert_eq!(
+            regs,
+            RtcRegisters {
+                days_hi: 0x80,
+                ..RtcRegisters::default()
+            }
+        );
+    }
+    #[test]
+    fn rtc_save_layout() {
+        let mut mbc = emulated_rtc();
+        mbc.tick(M_CYCLES_PER_SECOND * 62);
+        latch(&mut mbc);
+        mbc.tick(M_CYCLES_PER_SECOND);
+
+        let data = mbc.rtc_save_data().unwrap();
+        assert_eq!(data.len(), RTC_SAVE_SIZE);
+        assert_eq!(data[0..8], [3, 0, 0, 0, 1, 0, 0, 0]); // live
+        assert_eq!(data[20..28], [2, 0, 0, 0, 1, 0, 0, 0]); // latched
+    }
+    #[test]
+    fn rtc_save_round_trip() {
+        let mut mbc = emulated_rtc();
+        mbc.tick(M_CYCLES_PER_SECOND * 3725);
+        latch(&mut mbc);
+        let data = mbc.rtc_save_data().unwrap();
+
+        let mut loaded = emulated_rtc();
+        loaded.load_rtc_save_data(&data);
+        assert_eq!(read_rtc(&mut loaded, 0), 5);
+        assert_eq!(read_rtc(&mut loaded, 1), 2);
+        assert_eq!(read_rtc(&mut loaded, 2), 1);
+    }
+    #[test]
+    fn rtc_load_catches_up_on_host_time() {
+        let mut mbc = emulated_rtc();
+        let mut data = mbc.rtc_save_data().unwrap();
+        // saved two hours ago
+        let timestamp = u64::from_le_bytes(data[40..48].try_into().unwrap()) - 7200;
+        data[40..48].copy_from_slice(&timestamp.to_le_bytes());
+
+        let mut loaded = with_rtc(true);
+        loaded.load_rtc_save_data(&data);
+        loaded.write(0x0000, 0x0a);
+        latch(&mut loaded);
+        assert_eq!(read_rtc(&mut loaded, 2), 2);
+    }
+    #[test]
+    fn rtc_save_wrong_size_invalid() {
+        let mut mbc = Mbc3::new(Storage::new(Vec::new(), 2, 0x2000), true);
+
+        assert!(mbc.load_save_data(&[0; 0x2000]).is_ok());
+        assert_eq!(
+            mbc.load_save_data(&[0x42; 0x2000 + 12]),
+            Err(MemoryError::InvalidSaveSize {
+                size: 0x2000 + 12,
+                expected: 0x2000 + RTC_SAVE_SIZE,
+            })
+        );
+        // nothing gets loaded from a bad save
+        mbc.write(0x0000, 0x0a);
+        assert_eq!(mbc.read(0xa000), 0x00);
+    }
+    #[test]
+    fn no_rtc_save_without_timer() {
+        assert_eq!(with_rtc(false).rtc_save_data(), None);
+    }
+}
@@ -0,0 +1,118 @@
+use super::cartridge::{Cartridge, Storage};
+
+// https://gbdev.io/pandocs/MBC5.html
+
+/// MBC5 and the ROM and RAM behind it.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Mbc5 {
+    ram_enable: u8,  // 0000-1fff, exactly 0x0a enables RAM
+    rom_bank_lo: u8, // 2000-2fff, lower 8 bits of the ROM bank
+    rom_bank_hi: u8, // 3000-3fff, bit 8 of the ROM bank
+    ram_bank: u8,    // 4000-5fff, RAM bank, bit 3 drives the motor on rumble carts
+
+    has_rumble: bool,
+    storage: Storage,
+}
+
+impl Mbc5 {
+    pub fn new(storage: Storage, has_rumble: bool) -> Mbc5 {
+        Mbc5 {
+            has_rumble,
+            storage,
+            ..Mbc5::default()
+        }
+    }
+
+    pub fn ram_enabled(&self) -> bool {
+        self.ram_enable == 0x0a
+    }
+
+    /// ROM bank mapped to 0x4000-0x7fff. Unlike older MBCs, bank 0 can be selected here.
+    pub fn rom_bank_4000(&self) -> usize {
+        (((self.rom_bank_hi & 0b1) as usize) << 8) | self.rom_bank_lo as usize
+    }
+    /// RAM bank mapped to 0xa000-0xbfff.
+    pub fn ram_bank(&self) -> usize {
+        if self.has_rumble {
+            (self.ram_bank & 0b0111) as usize
+        } else {
+            (self.ram_bank & 0x0f) as usize
+        }
+    }
+}
+
+impl Cartridge for Mbc5 {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3fff => self.storage.read_rom(0, addr),
+            0x4000..=0x7fff => self.storage.read_rom(self.rom_bank_4000(), addr),
+            _ if self.ram_enabled() => self.storage.read_ram(self.ram_bank(), addr),
+            _ => 0xff,
+        }
+    }
+    fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enable = val,
+            0x2000..=0x2fff => self.rom_bank_lo = val,
+            0x3000..=0x3fff => self.rom_bank_hi = val,
+            0x4000..=0x5fff => self.ram_bank = val,
+            // 6000-7fff, writes here go nowhere
+            0x6000..=0x7fff => {}
+            _ if self.ram_enabled() => self.storage.write_ram(self.ram_bank(), addr, val),
+            _ => {}
+        }
+    }
+
+    fn rumble(&self) -> bool {
+        self.has_rumble && (self.ram_bank & 0b1000) != 0
+    }
+
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::cartridge::{Cartridge, Storage};
+    use crate::memory::mbc5::Mbc5;
+
+    #[test]
+    fn nine_bit_rom_bank() {
+        let mut mbc = Mbc5::new(Storage::default(), false);
+        assert_eq!(mbc.rom_bank_4000(), 0);
+
+        mbc.write(0x2000, 0xff);
+        mbc.write(0x3000, 0x01);
+        assert_eq!(mbc.rom_bank_4000(), 0x1ff);
+        mbc.write(0x3000, 0xfe); // only bit 0 is kept
+        assert_eq!(mbc.rom_bank_4000(), 0xff);
+    }
+    #[test]
+    fn ram_enable_needs_exactly_0a() {
+        let mut mbc = Mbc5::new(Storage::default(), false);
+        mbc.write(0x0000, 0x1a);
+        assert!(!mbc.ram_enabled());
+        mbc.write(0x0000, 0x0a);
+        assert!(mbc.ram_enabled());
+    }
+    #[test]
+    fn rumble_takes_bit_3() {
+        let mut mbc = Mbc5::new(Storage::default(), true);
+        mbc.write(0x4000, 0x0b);
+
+        assert!(mbc.rumble());
+        assert_eq!(mbc.ram_bank(), 0x03);
+    }
+    #[test]
+    fn no_rumble_without_motor() {
+        let mut mbc = Mbc5::new(Storage::default(), false);
+        mbc.write(0x4000, 0x0b);
+
+        assert!(!mbc.rumble());
+        assert_eq!(mbc.ram_bank(), 0x0b);
+    }
+}
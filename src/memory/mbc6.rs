@@ -0,0 +1,331 @@
+use super::cartridge::{Cartridge, Storage};
+use super::MemoryError;
+
+// https://gbdev.io/pandocs/MBC6.html
+
+const FLASH_SIZE: usize = 0x100000;
+const FLASH_SECTOR_SIZE: usize = 0x20000;
+
+// what the flash chip (a Macronix MX29F008) answers with in ID mode
+const FLASH_MANUFACTURER_ID: u8 = 0xc2;
+const FLASH_DEVICE_ID: u8 = 0x81;
+
+/// Where the flash chip is in a command sequence. Every command starts with
+/// 0xaa to 0x5555 then 0x55 to 0x2aaa, erasing needs that twice.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum FlashState {
+    Ready,
+    Unlock1,    // got 0xaa
+    Unlock2,    // got 0x55, waiting for the command
+    EraseReady, // got 0x80
+    EraseUnlock1,
+    EraseUnlock2,
+    Program, // got 0xa0, the next write programs a byte
+    Id,      // got 0x90, reads return the chip's ID until reset
+}
+
+/// The flash chip the MBC6 can map in place of ROM, read back like ROM but
+/// only written through its command sequences.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Flash {
+    data: Vec<u8>,
+    state: FlashState,
+}
+
+impl Flash {
+    fn new() -> Flash {
+        Flash {
+            data: vec![0xff; FLASH_SIZE],
+            state: FlashState::Ready,
+        }
+    }
+
+    fn read(&self, offset: usize) -> u8 {
+        if self.state == FlashState::Id {
+            return match offset & 0b1 {
+                0 => FLASH_MANUFACTURER_ID,
+                _ => FLASH_DEVICE_ID,
+            };
+        }
+        self.data[offset % FLASH_SIZE]
+    }
+
+    // hands back whether anything got programmed or erased
+    fn write(&mut self, offset: usize, val: u8) -> bool {
+        let offset = offset % FLASH_SIZE;
+        let command_addr = offset & 0x7fff;
+        let mut changed = false;
+
+        self.state = match (self.state, command_addr, val) {
+            (FlashState::Program, _, _) => {
+                // programming can only clear bits, erasing sets them again
+                self.data[offset] &= val;
+                changed = true;
+                FlashState::Ready
+            }
+            // reset works from anywhere else
+            (_, _, 0xf0) => FlashState::Ready,
+
+            (FlashState::Ready, 0x5555, 0xaa) => FlashState::Unlock1,
+            (FlashState::Unlock1, 0x2aaa, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, 0x5555, 0x80) => FlashState::EraseReady,
+            (FlashState::Unlock2, 0x5555, 0x90) => FlashState::Id,
+            (FlashState::Unlock2, 0x5555, 0xa0) => FlashState::Program,
+
+            (FlashState::EraseReady, 0x5555, 0xaa) => FlashState::EraseUnlock1,
+            (FlashState::EraseUnlock1, 0x2aaa, 0x55) => FlashState::EraseUnlock2,
+            (FlashState::EraseUnlock2, 0x5555, 0x10) => {
+                self.data.fill(0xff);
+                changed = true;
+                FlashState::Ready
+            }
+            (FlashState::EraseUnlock2, _, 0x30) => {
+                let start = offset - (offset % FLASH_SECTOR_SIZE);
+                self.data[start..start + FLASH_SECTOR_SIZE].fill(0xff);
+                changed = true;
+                FlashState::Ready
+            }
+
+            // ID mode only ends on a reset
+            (FlashState::Id, _, _) => FlashState::Id,
+            // anything out of sequence drops the command
+            _ => FlashState::Ready,
+        };
+        changed
+    }
+}
+
+/// MBC6, with two 8 KiB ROM or flash windows and two 4 KiB RAM windows, each banked on its own.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Mbc6 {
+    ram_enable: u8,   // 0000-03ff, 0x0a enables RAM
+    ram_bank_a: u8,   // 0400-07ff, RAM mapped to a000-afff
+    ram_bank_b: u8,   // 0800-0bff, RAM mapped to b000-bfff
+    flash_enable: u8, // 0c00-0fff, bit 0 lets flash be mapped at all
+    flash_write: u8,  // 1000, bit 0 lets flash be written
+    rom_bank_a: u8,   // 2000-27ff, ROM or flash mapped to 4000-5fff
+    rom_select_a: u8, // 2800-2fff, 0x08 maps flash instead of ROM
+    rom_bank_b: u8,   // 3000-37ff, ROM or flash mapped to 6000-7fff
+    rom_select_b: u8, // 3800-3fff, 0x08 maps flash instead of ROM
+    flash: Flash,
+    storage: Storage,
+}
+
+impl Mbc6 {
+    pub fn new(storage: Storage) -> Mbc6 {
+        Mbc6 {
+            ram_enable: 0,
+            ram_bank_a: 0,
+            ram_bank_b: 0,
+            flash_enable: 0,
+            flash_write: 0,
+            rom_bank_a: 0,
+            rom_select_a: 0,
+            rom_bank_b: 0,
+            rom_select_b: 0,
+            flash: Flash::new(),
+            storage,
+        }
+    }
+
+    pub fn ram_enabled(&self) -> bool {
+        self.ram_enable == 0x0a
+    }
+
+    // the window at 4000-5fff or 6000-7fff, as its bank and whether it's flash
+    fn rom_window(&self, addr: usize) -> (usize, bool) {
+        let (bank, select) = if addr < 0x6000 {
+            (self.rom_bank_a, self.rom_select_a)
+        } else {
+            (self.rom_bank_b, self.rom_select_b)
+        };
+        let flash = select == 0x08 && (self.flash_enable & 0b1) != 0;
+
+        (bank as usize, flash)
+    }
+    fn ram_window(&self, addr: usize) -> usize {
+        let bank = if addr < 0xb000 {
+            self.ram_bank_a
+        } else {
+            self.ram_bank_b
+        };
+        (bank as usize * 0x1000) + (addr & 0x0fff)
+    }
+}
+
+impl Cartridge for Mbc6 {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3fff => self.storage.rom_at(addr),
+            0x4000..=0x7fff => {
+                let offset = |bank: usize| (bank * 0x2000) + (addr & 0x1fff);
+                match self.rom_window(addr) {
+                    (bank, true) => self.flash.read(offset(bank)),
+                    (bank, false) => self.storage.rom_at(offset(bank)),
+                }
+            }
+            _ if self.ram_enabled() => self.storage.ram_at(self.ram_window(addr)),
+            _ => 0xff,
+        }
+    }
+    fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0x0000..=0x03ff => self.ram_enable = val,
+            0x0400..=0x07ff => self.ram_bank_a = val,
+            0x0800..=0x0bff => self.ram_bank_b = val,
+            0x0c00..=0x0fff => self.flash_enable = val,
+            0x1000 => self.flash_write = val,
+            0x2000..=0x27ff => self.rom_bank_a = val,
+            0x2800..=0x2fff => self.rom_select_a = val,
+            0x3000..=0x37ff => self.rom_bank_b = val,
+            0x3800..=0x3fff => self.rom_select_b = val,
+            0x4000..=0x7fff => {
+                if let (bank, true) = self.rom_window(addr) {
+                    let offset = (bank * 0x2000) + (addr & 0x1fff);
+                    if (self.flash_write & 0b1) != 0 && self.flash.write(offset, val) {
+                        self.storage.mark_dirty();
+                    }
+                }
+            }
+            0xa000..=0xbfff if self.ram_enabled() => {
+                let offset = self.ram_window(addr);
+                self.storage.set_ram_at(offset, val);
+            }
+            _ => {}
+        }
+    }
+
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+
+    /// Cartridge RAM followed by the whole flash chip.
+    fn save_data(&mut self) -> Vec<u8> {
+        let mut data = self.storage.save_ram();
+        data.extend_from_slice(&self.flash.data);
+        data
+    }
+    fn load_save_data(&mut self, data: &[u8]) -> Result<(), MemoryError> {
+        let rest = self.storage.load_ram(data)?;
+        // saves without flash in them leave it erased
+        if rest.len() >= FLASH_SIZE {
+            self.flash.data.copy_from_slice(&rest[..FLASH_SIZE]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::cartridge::{Cartridge, Storage};
+    use crate::memory::mbc6::{Mbc6, FLASH_DEVICE_ID, FLASH_MANUFACTURER_ID, FLASH_SIZE};
+
+    // every 8 KiB bank starts with its own number
+    fn mbc6() -> Mbc6 {
+        let mut rom = vec![0; 0x100000];
+        for bank in 0..0x80 {
+            rom[bank * 0x2000] = bank as u8;
+        }
+        Mbc6::new(Storage::new(rom, 64, 0x8000))
+    }
+    fn map_flash(mbc: &mut Mbc6) {
+        mbc.write(0x0c00, 0x01);
+        mbc.write(0x1000, 0x01);
+        mbc.write(0x2800, 0x08);
+        mbc.write(0x3800, 0x08);
+    }
+    // flash commands go through whatever banks put the addresses in reach
+    fn flash_command(mbc: &mut Mbc6, command: u8) {
+        mbc.write(0x2000, 0x02);
+        mbc.write(0x3000, 0x01);
+        mbc.write(0x5555, 0xaa);
+        mbc.write(0x6aaa, 0x55);
+        mbc.write(0x5555, command);
+    }
+
+    #[test]
+    fn rom_windows_bank_separately() {
+        let mut mbc = mbc6();
+        mbc.write(0x2000, 0x05);
+        mbc.write(0x3000, 0x7e);
+
+        assert_eq!(mbc.read(0x4000), 0x05);
+        assert_eq!(mbc.read(0x6000), 0x7e);
+        assert_eq!(mbc.read(0x2000), 0x01); // 0000-3fff is always the first 16 KiB
+    }
+    #[test]
+    fn ram_windows_bank_separately() {
+        let mut mbc = mbc6();
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0x0400, 0x03);
+        mbc.write(0x0800, 0x03);
+        mbc.write(0xa000, 0x42);
+
+        assert_eq!(mbc.read(0xb000), 0x42);
+        mbc.write(0x0800, 0x04);
+        assert_eq!(mbc.read(0xb000), 0x00);
+    }
+    #[test]
+    fn flash_starts_erased() {
+        let mut mbc = mbc6();
+        map_flash(&mut mbc);
+
+        assert_eq!(mbc.read(0x4000), 0xff);
+    }
+    #[test]
+    fn flash_needs_enabling() {
+        let mut mbc = mbc6();
+        mbc.write(0x2800, 0x08);
+
+        assert_eq!(mbc.read(0x4000), 0x00);
+    }
+    #[test]
+    fn flash_programs_and_erases() {
+        let mut mbc = mbc6();
+        map_flash(&mut mbc);
+        flash_command(&mut mbc, 0xa0);
+        mbc.write(0x4123, 0x3c);
+        assert_eq!(mbc.read(0x4123), 0x3c);
+
+        // plain writes don't do anything
+        mbc.write(0x4123, 0x00);
+        assert_eq!(mbc.read(0x4123), 0x3c);
+
+        flash_command(&mut mbc, 0x80);
+        mbc.write(0x5555, 0xaa);
+        mbc.write(0x6aaa, 0x55);
+        mbc.write(0x4000, 0x30);
+        assert_eq!(mbc.read(0x4123), 0xff);
+    }
+    #[test]
+    fn flash_id_mode() {
+        let mut mbc = mbc6();
+        map_flash(&mut mbc);
+        flash_command(&mut mbc, 0x90);
+
+        assert_eq!(mbc.read(0x4000), FLASH_MANUFACTURER_ID);
+        assert_eq!(mbc.read(0x4001), FLASH_DEVICE_ID);
+        mbc.write(0x4000, 0xf0);
+        assert_eq!(mbc.read(0x4000), 0xff);
+    }
+    #[test]
+    fn save_includes_flash() {
+        let mut mbc = mbc6();
+        map_flash(&mut mbc);
+        flash_command(&mut mbc, 0xa0);
+        mbc.write(0x4000, 0x12);
+
+        let data = mbc.save_data();
+        assert_eq!(data.len(), 0x8000 + FLASH_SIZE);
+        assert_eq!(data[0x8000 + 0x4000], 0x12);
+
+        let mut loaded = mbc6();
+        loaded.load_save_data(&data).unwrap();
+        map_flash(&mut loaded);
+        loaded.write(0x2000, 0x02);
+        assert_eq!(loaded.read(0x4000), 0x12);
+    }
+}
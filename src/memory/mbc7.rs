@@ -0,0 +1,391 @@
+use super::cartridge::{Cartridge, Storage};
+
+// https://gbdev.io/pandocs/MBC7.html
+
+/// Size of the 93LC56 EEPROM, kept in the cartridge's `Storage` as RAM.
+pub const EEPROM_SIZE: usize = 0x100;
+
+// what the accelerometer reads lying flat, and how far 1g moves it
+const ACCEL_CENTRE: u16 = 0x81d0;
+const ACCEL_PER_G: f32 = 112.0;
+// what the latched values read as between being erased and latched again
+const ACCEL_ERASED: u16 = 0x8000;
+
+/// Where the EEPROM is in taking in or giving out a command, one clock at a time.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum EepromState {
+    Idle, // waiting for a start bit
+    Command {
+        bits: u8,
+        value: u16,
+    }, // 2 opcode bits then 8 address bits
+    Reading {
+        word: u16,
+        bits_left: u8,
+    }, // shifting a word out on DO
+    Writing {
+        addr: Option<u8>,
+        bits: u8,
+        value: u16,
+    }, // None writes every word
+    Done, // waiting for CS to drop
+}
+
+/// The EEPROM's serial pins as seen through 0xa080.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Eeprom {
+    cs: bool,
+    clk: bool,
+    di: bool,
+    do_: bool,
+    write_enabled: bool,
+    state: EepromState,
+}
+
+impl Eeprom {
+    fn new() -> Eeprom {
+        Eeprom {
+            cs: false,
+            clk: false,
+            di: false,
+            do_: true,
+            write_enabled: false,
+            state: EepromState::Idle,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        ((self.cs as u8) << 7) | ((self.clk as u8) << 6) | ((self.di as u8) << 1) | self.do_ as u8
+    }
+
+    fn write(&mut self, val: u8, storage: &mut Storage) {
+        let cs = (val & 0x80) != 0;
+        let clk = (val & 0x40) != 0;
+        self.di = (val & 0x02) != 0;
+
+        if !cs {
+            self.state = EepromState::Idle;
+        } else if clk && !self.clk && self.cs {
+            self.clock_in(storage);
+        }
+        self.cs = cs;
+        self.clk = clk;
+    }
+
+    // words are 16 bits, kept little-endian
+    fn word(storage: &Storage, addr: u8) -> u16 {
+        let offset = (addr & 0x7f) as usize * 2;
+        u16::from_le_bytes([storage.ram_at(offset), storage.ram_at(offset + 1)])
+    }
+    fn set_word(storage: &mut Storage, addr: u8, word: u16) {
+        let offset = (addr & 0x7f) as usize * 2;
+        let [lo, hi] = word.to_le_bytes();
+        storage.set_ram_at(offset, lo);
+        storage.set_ram_at(offset + 1, hi);
+    }
+
+    // everything happens on the rising edge of CLK
+    fn clock_in(&mut self, storage: &mut Storage) {
+        let di = self.di as u16;
+        self.state = match self.state {
+            EepromState::Idle if self.di => EepromState::Command { bits: 0, value: 0 },
+            EepromState::Idle => EepromState::Idle,
+            EepromState::Command { bits, value } => {
+                let value = (value << 1) | di;
+                if bits + 1 < 10 {
+                    EepromState::Command {
+                        bits: bits + 1,
+                        value,
+                    }
+                } else {
+                    self.command((value >> 8) as u8, value as u8, storage)
+                }
+            }
+            EepromState::Reading { word, bits_left } => {
+                self.do_ = ((word >> (bits_left - 1)) & 0b1) != 0;
+                match bits_left - 1 {
+                    0 => EepromState::Done,
+                    bits_left => EepromState::Reading { word, bits_left },
+                }
+            }
+            EepromState::Writing { addr, bits, value } => {
+                let value = (value << 1) | di;
+                if bits + 1 < 16 {
+                    EepromState::Writing {
+                        addr,
+                        bits: bits + 1,
+                        value,
+                    }
+                } else {
+                    self.finish_write(addr, value, storage)
+                }
+            }
+            EepromState::Done => EepromState::Done,
+        };
+    }
+
+    fn finish_write(&mut self, addr: Option<u8>, word: u16, storage: &mut Storage) -> EepromState {
+        if self.write_enabled {
+            match addr {
+                Some(addr) => Self::set_word(storage, addr, word),
+                None => (0..0x80).for_each(|addr| Self::set_word(storage, addr, word)),
+            }
+        }
+        // writes finish instantly, so it's always ready
+        self.do_ = true;
+        EepromState::Done
+    }
+
+    fn command(&mut self, opcode: u8, addr: u8, storage: &mut Storage) -> EepromState {
+        match (opcode, addr >> 6) {
+            // READ, a dummy 0 goes out before the word
+            (0b10, _) => {
+                self.do_ = false;
+                EepromState::Reading {
+                    word: Self::word(storage, addr),
+                    bits_left: 16,
+                }
+            }
+            // WRITE
+            (0b01, _) => EepromState::Writing {
+                addr: Some(addr),
+                bits: 0,
+                value: 0,
+            },
+            // ERASE
+            (0b11, _) => {
+                if self.write_enabled {
+                    Self::set_word(storage, addr, 0xffff);
+                }
+                self.do_ = true;
+                EepromState::Done
+            }
+            // the rest share opcode 00 and use the top address bits
+            (_, 0b11) => {
+                self.write_enabled = true;
+                EepromState::Done
+            }
+            (_, 0b00) => {
+                self.write_enabled = false;
+                EepromState::Done
+            }
+            // ERAL
+            (_, 0b10) => {
+                if self.write_enabled {
+                    (0..0x80).for_each(|addr| Self::set_word(storage, addr, 0xffff));
+                }
+                self.do_ = true;
+                EepromState::Done
+            }
+            // WRAL
+            _ => EepromState::Writing {
+                addr: None,
+                bits: 0,
+                value: 0,
+            },
+        }
+    }
+}
+
+/// MBC7, with a two-axis accelerometer and a serial EEPROM in place of RAM.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mbc7 {
+    ram_enable_1: u8, // 0000-1fff, needs 0x0a
+    rom_bank: u8,     // 2000-3fff, 7 bits
+    ram_enable_2: u8, // 4000-5fff, needs 0x40
+
+    tilt: (f32, f32), // in g, set by whoever's holding the cartridge
+    accel_x: u16,     // latched readings
+    accel_y: u16,
+    eeprom: Eeprom,
+    storage: Storage, // the EEPROM's contents
+}
+
+impl Mbc7 {
+    pub fn new(storage: Storage) -> Mbc7 {
+        Mbc7 {
+            ram_enable_1: 0,
+            rom_bank: 0,
+            ram_enable_2: 0,
+
+            tilt: (0.0, 0.0),
+            accel_x: ACCEL_ERASED,
+            accel_y: ACCEL_ERASED,
+            eeprom: Eeprom::new(),
+            storage,
+        }
+    }
+
+    fn registers_enabled(&self) -> bool {
+        self.ram_enable_1 == 0x0a && self.ram_enable_2 == 0x40
+    }
+
+    fn accel(tilt: f32) -> u16 {
+        (ACCEL_CENTRE as f32 + (tilt * ACCEL_PER_G)) as u16
+    }
+
+    // the registers repeat every 0x100 bytes across a000-afff,
+    // with address bits 4-7 picking which one
+    fn register_read(&self, addr: usize) -> u8 {
+        match addr & 0xf0 {
+            0x20 => self.accel_x as u8,
+            0x30 => (self.accel_x >> 8) as u8,
+            0x40 => self.accel_y as u8,
+            0x50 => (self.accel_y >> 8) as u8,
+            0x60 => 0x00,
+            0x80 => self.eeprom.read(),
+            _ => 0xff,
+        }
+    }
+    fn register_write(&mut self, addr: usize, val: u8) {
+        match (addr & 0xf0, val) {
+            // erase, then latch
+            (0x00, 0x55) => {
+                self.accel_x = ACCEL_ERASED;
+                self.accel_y = ACCEL_ERASED;
+            }
+            (0x10, 0xaa) if self.accel_x == ACCEL_ERASED => {
+                self.accel_x = Self::accel(self.tilt.0);
+                self.accel_y = Self::accel(self.tilt.1);
+            }
+            (0x80, _) => self.eeprom.write(val, &mut self.storage),
+            _ => {}
+        }
+    }
+}
+
+impl Cartridge for Mbc7 {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3fff => self.storage.read_rom(0, addr),
+            0x4000..=0x7fff => self.storage.read_rom((self.rom_bank & 0x7f) as usize, addr),
+            0xa000..=0xafff if self.registers_enabled() => self.register_read(addr),
+            _ => 0xff,
+        }
+    }
+    fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0x0000..=0x1fff => self.ram_enable_1 = val,
+            0x2000..=0x3fff => self.rom_bank = val,
+            0x4000..=0x5fff => self.ram_enable_2 = val,
+            0xa000..=0xafff if self.registers_enabled() => self.register_write(addr, val),
+            _ => {}
+        }
+    }
+
+    fn set_tilt(&mut self, x: f32, y: f32) {
+        self.tilt = (x, y);
+    }
+
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::cartridge::{Cartridge, Storage};
+    use crate::memory::mbc7::{Mbc7, ACCEL_CENTRE, ACCEL_ERASED, EEPROM_SIZE};
+
+    fn mbc7() -> Mbc7 {
+        let mut mbc = Mbc7::new(Storage::new(Vec::new(), 2, EEPROM_SIZE));
+        mbc.write(0x0000, 0x0a);
+        mbc.write(0x4000, 0x40);
+        mbc
+    }
+
+    // bit-bangs the EEPROM's pins the way games do
+    fn clock_bit(mbc: &mut Mbc7, bit: bool) -> bool {
+        let di = (bit as u8) << 1;
+        mbc.write(0xa080, 0x80 | di);
+        mbc.write(0xa080, 0xc0 | di);
+        (mbc.read(0xa080) & 0b1) != 0
+    }
+    fn send(mbc: &mut Mbc7, bits: u32, count: u8) {
+        for i in (0..count).rev() {
+            clock_bit(mbc, ((bits >> i) & 0b1) != 0);
+        }
+    }
+    fn deselect(mbc: &mut Mbc7) {
+        mbc.write(0xa080, 0x00);
+    }
+    fn eeprom_read(mbc: &mut Mbc7, addr: u8) -> u16 {
+        send(mbc, 0b110 << 8 | addr as u32, 11);
+        let mut word = 0;
+        for _ in 0..16 {
+            word = (word << 1) | clock_bit(mbc, false) as u16;
+        }
+        deselect(mbc);
+        word
+    }
+    fn eeprom_write(mbc: &mut Mbc7, addr: u8, word: u16) {
+        send(mbc, (0b101 << 24) | ((addr as u32) << 16) | word as u32, 27);
+        deselect(mbc);
+    }
+    fn write_enable(mbc: &mut Mbc7) {
+        send(mbc, 0b100_1100_0000, 11);
+        deselect(mbc);
+    }
+
+    #[test]
+    fn registers_need_both_enables() {
+        let mut mbc = Mbc7::new(Storage::new(Vec::new(), 2, EEPROM_SIZE));
+        mbc.write(0x0000, 0x0a);
+        assert_eq!(mbc.read(0xa060), 0xff);
+
+        mbc.write(0x4000, 0x40);
+        assert_eq!(mbc.read(0xa060), 0x00);
+    }
+    #[test]
+    fn accelerometer_latches_tilt() {
+        let mut mbc = mbc7();
+        mbc.set_tilt(1.0, -0.5);
+        assert_eq!(mbc.read(0xa030), (ACCEL_ERASED >> 8) as u8);
+
+        mbc.write(0xa000, 0x55);
+        mbc.write(0xa010, 0xaa);
+        let x = u16::from_le_bytes([mbc.read(0xa020), mbc.read(0xa030)]);
+        let y = u16::from_le_bytes([mbc.read(0xa040), mbc.read(0xa050)]);
+        assert_eq!(x, ACCEL_CENTRE + 112);
+        assert_eq!(y, ACCEL_CENTRE - 56);
+    }
+    #[test]
+    fn accelerometer_needs_erasing_first() {
+        let mut mbc = mbc7();
+        mbc.write(0xa000, 0x55);
+        mbc.write(0xa010, 0xaa);
+        mbc.set_tilt(1.0, 1.0);
+        mbc.write(0xa010, 0xaa);
+
+        assert_eq!(mbc.read(0xa020), ACCEL_CENTRE as u8);
+    }
+    #[test]
+    fn eeprom_starts_write_protected() {
+        let mut mbc = mbc7();
+        eeprom_write(&mut mbc, 0x05, 0x1234);
+
+        assert_eq!(eeprom_read(&mut mbc, 0x05), 0x0000);
+    }
+    #[test]
+    fn eeprom_write_then_read() {
+        let mut mbc = mbc7();
+        write_enable(&mut mbc);
+        eeprom_write(&mut mbc, 0x05, 0x1234);
+
+        assert_eq!(eeprom_read(&mut mbc, 0x05), 0x1234);
+        assert_eq!(mbc.save_data()[0x0a..0x0c], [0x34, 0x12]);
+    }
+    #[test]
+    fn eeprom_erase() {
+        let mut mbc = mbc7();
+        write_enable(&mut mbc);
+        eeprom_write(&mut mbc, 0x05, 0x1234);
+        send(&mut mbc, 0b111 << 8 | 0x05, 11);
+        deselect(&mut mbc);
+
+        assert_eq!(eeprom_read(&mut mbc, 0x05), 0xffff);
+    }
+}
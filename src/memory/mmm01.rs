@@ -0,0 +1,212 @@
+use super::cartridge::{Cartridge, Storage};
+
+// https://gbdev.io/pandocs/MMM01.html
+
+/// MMM01, used for multi-game compilations. Starts out showing the menu in the last
+/// 32 KiB of ROM, which picks a game by setting the upper bank bits and masks and then
+/// locks them in, after which the game sees what looks like an MBC1 of its own.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Mmm01 {
+    mapped: bool, // 0000-1fff bit 6, can't be unset again
+
+    ram_enable: u8,  // 0000-1fff, lower nibble 0x0a enables RAM
+    ram_mask: u8,    // 0000-1fff bits 4-5, the RAM bank bits the game can't change
+    rom_lo: u8,      // 2000-3fff bits 0-4
+    rom_mid: u8,     // 2000-3fff bits 5-6
+    ram_lo: u8,      // 4000-5fff bits 0-1
+    ram_hi: u8,      // 4000-5fff bits 2-3
+    rom_hi: u8,      // 4000-5fff bits 4-5
+    mode_lock: bool, // 4000-5fff bit 6, keeps the game from changing the mode
+    mode: u8,        // 6000-7fff bit 0, like MBC1's banking mode
+    rom_mask: u8,    // 6000-7fff bits 2-5, the ROM bank bits 1-4 the game can't change
+
+    storage: Storage,
+}
+
+impl Mmm01 {
+    pub fn new(storage: Storage) -> Mmm01 {
+        Mmm01 {
+            storage,
+            ..Mmm01::default()
+        }
+    }
+
+    pub fn ram_enabled(&self) -> bool {
+        (self.ram_enable & 0x0f) == 0x0a
+    }
+
+    // the bits above the 5 the game banks with, picked by the menu
+    fn rom_base(&self) -> usize {
+        ((self.rom_hi as usize) << 7) | ((self.rom_mid as usize) << 5)
+    }
+
+    /// ROM bank mapped to 0x0000-0x3fff.
+    pub fn rom_bank_0000(&self) -> usize {
+        if !self.mapped {
+            // the menu lives in the last 32 KiB
+            return 0x1fe;
+        }
+        // the game's bank 0, whatever bits it can still change are cleared
+        self.rom_base() | (self.rom_lo & self.rom_mask) as usize
+    }
+    /// ROM bank mapped to 0x4000-0x7fff.
+    pub fn rom_bank_4000(&self) -> usize {
+        if !self.mapped {
+            return 0x1ff;
+        }
+        // 0 maps to 1 like on MBC1, but only counting the bits the game controls
+        let mut rom_lo = self.rom_lo;
+        if (rom_lo & !self.rom_mask & 0x1f) == 0 {
+            rom_lo |= 0b1;
+        }
+        self.rom_base() | rom_lo as usize
+    }
+    /// RAM bank mapped to 0xa000-0xbfff.
+    pub fn ram_bank(&self) -> usize {
+        // in mode 0 the game only gets the first bank of its RAM
+        let ram_lo = match self.mode {
+            0 => self.ram_lo & self.ram_mask,
+            _ => self.ram_lo,
+        };
+        ((self.ram_hi << 2) | ram_lo) as usize
+    }
+
+    // once mapped, the masked bits keep what the menu left in them
+    fn masked(old: u8, new: u8, mask: u8) -> u8 {
+        (old & mask) | (new & !mask)
+    }
+}
+
+impl Cartridge for Mmm01 {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3fff => self.storage.read_rom(self.rom_bank_0000(), addr),
+            0x4000..=0x7fff => self.storage.read_rom(self.rom_bank_4000(), addr),
+            _ if self.ram_enabled() => self.storage.read_ram(self.ram_bank(), addr),
+            _ => 0xff,
+        }
+    }
+    fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0x0000..=0x1fff => {
+                self.ram_enable = val & 0x0f;
+                if !self.mapped {
+                    self.ram_mask = (val >> 4) & 0b11;
+                    self.mapped = (val & 0x40) != 0;
+                }
+            }
+            0x2000..=0x3fff => {
+                let rom_lo = val & 0x1f;
+                if self.mapped {
+                    self.rom_lo = Self::masked(self.rom_lo, rom_lo, self.rom_mask);
+                } else {
+                    self.rom_lo = rom_lo;
+                    self.rom_mid = (val >> 5) & 0b11;
+                }
+            }
+            0x4000..=0x5fff => {
+                let ram_lo = val & 0b11;
+                if self.mapped {
+                    self.ram_lo = Self::masked(self.ram_lo, ram_lo, self.ram_mask);
+                } else {
+                    self.ram_lo = ram_lo;
+                    self.ram_hi = (val >> 2) & 0b11;
+                    self.rom_hi = (val >> 4) & 0b11;
+                    self.mode_lock = (val & 0x40) != 0;
+                }
+            }
+            0x6000..=0x7fff => {
+                if !(self.mapped && self.mode_lock) {
+                    self.mode = val & 0b1;
+                }
+                if !self.mapped {
+                    // lines up with the ROM bank bits 1-4 it covers
+                    self.rom_mask = (val >> 1) & 0x1e;
+                }
+                // bit 6 swaps some bank lines around, nothing's known to use it
+            }
+            _ => {
+                if self.ram_enabled() {
+                    self.storage.write_ram(self.ram_bank(), addr, val);
+                }
+            }
+        }
+    }
+
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::cartridge::{Cartridge, Storage};
+    use crate::memory::mmm01::Mmm01;
+
+    // 1 MiB, every bank starts with its own number
+    fn mmm01() -> Mmm01 {
+        let mut rom = vec![0; 0x100000];
+        for bank in 0..0x40 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        Mmm01::new(Storage::new(rom, 64, 0x8000))
+    }
+
+    #[test]
+    fn starts_on_last_32k() {
+        let mbc = mmm01();
+
+        assert_eq!(mbc.read(0x0000), 0x3e);
+        assert_eq!(mbc.read(0x4000), 0x3f);
+    }
+    #[test]
+    fn mapping_picks_game() {
+        let mut mbc = mmm01();
+        // second game in 128 KiB chunks starts at bank 8
+        mbc.write(0x2000, 0x08);
+        mbc.write(0x6000, 0b0011_0000); // keep bits 3-4
+        mbc.write(0x0000, 0x40);
+
+        assert_eq!(mbc.rom_bank_0000(), 8);
+        assert_eq!(mbc.read(0x0000), 0x08);
+        assert_eq!(mbc.read(0x4000), 0x09);
+
+        mbc.write(0x2000, 0x1f); // bits 3-4 stay put
+        assert_eq!(mbc.read(0x4000), 0x0f);
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.read(0x4000), 0x09);
+    }
+    #[test]
+    fn upper_bits_locked_after_mapping() {
+        let mut mbc = mmm01();
+        mbc.write(0x2000, 0x20); // bank 32
+        mbc.write(0x0000, 0x40);
+        mbc.write(0x2000, 0x62);
+        mbc.write(0x4000, 0x30);
+
+        assert_eq!(mbc.rom_bank_0000(), 0x20);
+        assert_eq!(mbc.rom_bank_4000(), 0x22);
+        // mapping can't be undone either
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.rom_bank_0000(), 0x20);
+    }
+    #[test]
+    fn ram_banks() {
+        let mut mbc = mmm01();
+        mbc.write(0x6000, 0x01);
+        mbc.write(0x0000, 0x4a);
+        mbc.write(0x4000, 0x02);
+        mbc.write(0xa000, 0x42);
+
+        mbc.write(0x4000, 0x01);
+        assert_eq!(mbc.read(0xa000), 0x00);
+        mbc.write(0x4000, 0x02);
+        assert_eq!(mbc.read(0xa000), 0x42);
+
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read(0xa000), 0xff);
+    }
+}
@@ -1,35 +1,61 @@
-use std::{
-    fmt::Display,
-    ops::{Index, IndexMut},
-};
+use std::fmt::Display;
 
 use thiserror::Error;
 
+use self::cartridge::{Cartridge, RomOnly};
 use self::cartridgeheader::{CartridgeHeader, CartridgeType};
+pub use self::imagesource::{FrameDirectory, ImageSource, StaticImage};
+pub use self::rtc::RtcClock;
+use crate::cpu::interrupts::Interrupt;
+use crate::model::Model;
 
+mod camera;
+mod cartridge;
 mod cartridgeheader;
+mod huc1;
+mod huc3;
+mod imagesource;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod mbc6;
+mod mbc7;
+mod mmm01;
+mod rtc;
+mod tama5;
+
+const DMG_BOOT_ROM_SIZE: usize = 0x0100;
+const CGB_BOOT_ROM_SIZE: usize = 0x0900;
 
 #[derive(Debug, Clone, Eq, PartialEq, Error)]
 pub enum MemoryError {
     CartTypeMismatch { ct: CartridgeType, reason: String },
-    UnsupportedCartType { ct: CartridgeType },
+    InvalidBootRomSize { size: usize },
+    InvalidSaveSize { size: usize, expected: usize },
 }
 
 impl Display for MemoryError {
-    // TODO: REMEMBER TO IMPLEMENT THIS IF WE END UP USING IT!!!
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::CartTypeMismatch { ct, reason } => todo!(),
-            Self::UnsupportedCartType { ct } => todo!(),
+            Self::CartTypeMismatch { ct, reason } => write!(f, "{:?} cartridge: {}", ct, reason),
+            Self::InvalidBootRomSize { size } => write!(
+                f,
+                "boot ROM should be {} (DMG) or {} (CGB) bytes, got {}",
+                DMG_BOOT_ROM_SIZE, CGB_BOOT_ROM_SIZE, size
+            ),
+            Self::InvalidSaveSize { size, expected } => write!(
+                f,
+                "save file should be at least {} bytes, got {}",
+                expected, size
+            ),
         }
     }
 }
 
 pub struct Memory {
     pub header: CartridgeHeader,
-    rom: Vec<u8>,
-    switchable_banks: Vec<Vec<u8>>,
-    ram: Vec<u8>,
+    cartridge: Box<dyn Cartridge>,
     vram: Vec<u8>,
     wram1: Vec<u8>,
     wram2: Vec<u8>,
@@ -37,6 +63,12 @@ pub struct Memory {
     io_registers: Vec<u8>,
     hram: Vec<u8>,
     interrupt_enable_reg: u8,
+    model: Model,              // what's being emulated, for the CGB-only registers
+    boot_rom: Option<Vec<u8>>, // dropped for good once 0xff50 is written
+    rumble: bool,              // motor state last reported to `on_rumble`
+    on_rumble: Option<Box<dyn FnMut(bool)>>,
+    infrared_led: bool, // LED state last reported to `on_infrared_led`
+    on_infrared_led: Option<Box<dyn FnMut(bool)>>,
 }
 
 impl Memory {
@@ -46,9 +78,7 @@ impl Memory {
     pub fn new() -> Memory {
         Memory {
             header: CartridgeHeader::new(), // always addresses $0100 - $014F
-            rom: Vec::new(),
-            switchable_banks: Vec::new(),
-            ram: Vec::new(),
+            cartridge: Box::new(RomOnly::default()), // empty slot
             vram: Vec::new(),
             wram1: Vec::new(),
             wram2: Vec::new(),
@@ -56,16 +86,32 @@ impl Memory {
             io_registers: Vec::new(),
             hram: Vec::new(),
             interrupt_enable_reg: 0,
+            model: Model::Dmg,
+            boot_rom: None,
+            rumble: false,
+            on_rumble: None,
+            infrared_led: false,
+            on_infrared_led: None,
         }
     }
 
     pub fn read(&mut self, data: Vec<u8>) -> Result<(), MemoryError> {
         self.header.read(&data[0x0100..0x014f + 1]);
+        // MMM01 compilations boot into a menu at the end of the ROM, and only the
+        // header there says so
+        if data.len() > 0x8000 {
+            let menu = data.len() - 0x8000 + 0x0100;
+            let menu_header = CartridgeHeader::from(&data[menu..menu + 0x50]);
+            if matches!(
+                menu_header.cartridge_type(),
+                CartridgeType::MMM01 | CartridgeType::MMM01_RAM | CartridgeType::MMM01_RAM_BATTERY
+            ) {
+                self.header = menu_header;
+            }
+        }
 
-        // big meaty part of code put in a different function for readability
-        self.organize_memory()?;
-
-        self.put_into_banks(data);
+        self.organize_memory();
+        self.cartridge = cartridge::from_rom(&self.header, data)?;
 
         Ok(())
     }
@@ -77,7 +123,7 @@ impl Memory {
         Ok(cd)
     }
 
-    fn organize_memory(&mut self) -> Result<(), MemoryError> {
+    fn organize_memory(&mut self) {
         // organize memory that's always involved
         self.vram = vec![0; 0x2000]; // 8KiB of VRAM
         self.wram1 = vec![0; 0x1000]; // 4KiB of WRAM (bank 1)
@@ -86,208 +132,359 @@ impl Memory {
         self.oam = vec![0; 0x00a0];
         self.io_registers = vec![0; 0x0080];
         self.hram = vec![0; 0xffff - 0xff80];
+    }
 
-        match self.header.cartridge_type() {
-            CartridgeType::ROM_ONLY => {
-                if self.header.rom_shift_count() > 0 {
-                    return Err(MemoryError::CartTypeMismatch {
-                        ct: self.header.cartridge_type(),
-                        reason: String::from("given ROM size is too large"),
-                    });
-                }
+    /// Overlays a boot ROM on top of the cartridge until the program writes to 0xff50.
+    /// DMG images cover 0x0000-0x00ff, CGB images also cover 0x0200-0x08ff.
+    pub fn load_boot_rom(&mut self, data: Vec<u8>) -> Result<(), MemoryError> {
+        if data.len() != DMG_BOOT_ROM_SIZE && data.len() != CGB_BOOT_ROM_SIZE {
+            return Err(MemoryError::InvalidBootRomSize { size: data.len() });
+        }
 
-                // no RAM specified
-                if self.header.ram_size() > 0 {
-                    return Err(MemoryError::CartTypeMismatch {
-                        ct: self.header.cartridge_type(),
-                        reason: String::from("header says RAM included with wrong cartridge type"),
-                    });
-                }
+        self.boot_rom = Some(data);
+        self.io_registers[0x50] = 0;
+        Ok(())
+    }
 
-                self.rom = vec![0; 0x4000];
-                self.switchable_banks.push(vec![0; 0x4000]);
-            }
+    fn boot_rom_at(&self, index: usize) -> Option<u8> {
+        let boot_rom = self.boot_rom.as_ref()?;
 
-            CartridgeType::ROM_RAM | CartridgeType::ROM_RAM_BATTERY => {
-                if self.header.rom_shift_count() > 0 {
-                    return Err(MemoryError::CartTypeMismatch {
-                        ct: self.header.cartridge_type(),
-                        reason: String::from("given ROM size is too large"),
-                    });
-                }
-                self.rom = vec![0; 0x4000];
-                self.switchable_banks.push(vec![0; 0x4000]);
-                self.ram = vec![0; 0x2000];
-            }
+        // the cartridge header always shows through
+        if index < 0x0100 || ((0x0200..boot_rom.len()).contains(&index)) {
+            boot_rom.get(index).copied()
+        } else {
+            None
+        }
+    }
 
-            CartridgeType::MBC1 => {
-                // no RAM specified
-                if self.header.ram_size() > 0 {
-                    return Err(MemoryError::CartTypeMismatch {
-                        ct: self.header.cartridge_type(),
-                        reason: String::from("header says RAM included with wrong cartridge type"),
-                    });
-                }
+    /// Fills in the I/O registers the way the boot ROM leaves them on the given model.
+    pub fn post_boot(&mut self, model: Model) {
+        self.model = model;
+        // https://gbdev.io/pandocs/Power_Up_Sequence.html#hardware-registers
+        let io = &mut self.io_registers;
+        io[0x00] = 0xcf; // P1
+        io[0x01] = 0x00; // SB
+        io[0x02] = if model.is_cgb() { 0x7f } else { 0x7e }; // SC
+        io[0x04] = match model {
+            // DIV
+            Model::Dmg0 => 0x18,
+            Model::Dmg | Model::Mgb => 0xab,
+            _ => 0x00, // depends on how long the boot ROM ran
+        };
+        io[0x05] = 0x00; // TIMA
+        io[0x06] = 0x00; // TMA
+        io[0x07] = 0xf8; // TAC
+        io[0x0f] = 0xe1; // IF
 
-                match self.header.rom_shift_count() {
-                    0x00 => {
-                        // 32 KiB of ROM, two banks
-                        self.rom = vec![0; 0x4000]; // 0000-3fff, bank X0
-                        self.switchable_banks.push(vec![0; 0x4000]); // 4000-7fff, bank 01
-                    }
-                    0x01 => {
-                        // 64 KiB of ROM, 4 banks
-                        self.add_banks_for_mbc1(4);
-                    }
-                    0x02 => {
-                        // 128 KiB of ROM, 8 banks
-                        self.add_banks_for_mbc1(8);
-                    }
-                    0x03 => {
-                        // 256 KiB of ROM, 16 banks
-                        self.add_banks_for_mbc1(16);
-                    }
-                    0x04 => {
-                        // 512 KiB of ROM, 32 banks
-                        self.add_banks_for_mbc1(32);
-                    }
-                    // for these last two cases, i.e. 1MiB+,
-                    // banks are different.
-                    // TODO: look into how they are different,
-                    // and organize accordingly
-                    0x05 => {
-                        // 1 MiB of ROM, 64 banks
-                        todo!()
-                    }
-                    0x06 => {
-                        // 2 MiB of ROM, 128 banks
-                        todo!()
-                    }
-                    _ => {
-                        return Err(MemoryError::CartTypeMismatch {
-                            ct: self.header.cartridge_type(),
-                            reason: String::from("given ROM size is too large or incorrect"),
-                        })
-                    }
-                }
+        // audio
+        io[0x10] = 0x80; // NR10
+        io[0x11] = 0xbf; // NR11
+        io[0x12] = 0xf3; // NR12
+        io[0x13] = 0xff; // NR13
+        io[0x14] = 0xbf; // NR14
+        io[0x16] = 0x3f; // NR21
+        io[0x17] = 0x00; // NR22
+        io[0x18] = 0xff; // NR23
+        io[0x19] = 0xbf; // NR24
+        io[0x1a] = 0x7f; // NR30
+        io[0x1b] = 0xff; // NR31
+        io[0x1c] = 0x9f; // NR32
+        io[0x1d] = 0xff; // NR33
+        io[0x1e] = 0xbf; // NR34
+        io[0x20] = 0xff; // NR41
+        io[0x21] = 0x00; // NR42
+        io[0x22] = 0x00; // NR43
+        io[0x23] = 0xbf; // NR44
+        io[0x24] = 0x77; // NR50
+        io[0x25] = 0xf3; // NR51
+        io[0x26] = if model.is_sgb() { 0xf0 } else { 0xf1 }; // NR52
+
+        // LCD
+        io[0x40] = 0x91; // LCDC
+        io[0x41] = if model == Model::Dmg0 { 0x81 } else { 0x85 }; // STAT
+        io[0x42] = 0x00; // SCY
+        io[0x43] = 0x00; // SCX
+        io[0x44] = 0x00; // LY
+        io[0x45] = 0x00; // LYC
+        io[0x46] = if model.is_cgb() { 0x00 } else { 0xff }; // DMA
+        io[0x47] = 0xfc; // BGP
+        io[0x4a] = 0x00; // WY
+        io[0x4b] = 0x00; // WX
+
+        if model.is_cgb() {
+            io[0x4d] = 0x7e; // KEY1, single speed
+            io[0x4f] = 0xfe; // VBK
+            io[0x51..=0x55].fill(0xff); // HDMA1-5
+            io[0x56] = 0x3e; // RP
+            io[0x70] = 0xf8; // SVBK
+        }
+
+        self.interrupt_enable_reg = 0x00;
+    }
+
+    /// Picks the hardware being emulated, without touching the registers like `post_boot` does.
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    // IF lives with the rest of the I/O registers at 0xff0f, IE on its own at 0xffff.
+    // only the lower 5 bits of either are connected to anything
+
+    pub fn interrupt_enable(&self) -> u8 {
+        self.interrupt_enable_reg & 0x1f
+    }
+    pub fn interrupt_flag(&self) -> u8 {
+        self.io_registers[0x0f] & 0x1f
+    }
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.io_registers[0x0f] |= interrupt.bit();
+    }
+    pub fn clear_interrupt(&mut self, interrupt: Interrupt) {
+        self.io_registers[0x0f] &= !interrupt.bit();
+    }
+
+    // KEY1 (0xff4d) on the CGB: bit 0 arms a speed switch for the next STOP,
+    // bit 7 reports the current speed
+
+    pub fn speed_switch_armed(&self) -> bool {
+        (self.io_registers[0x4d] & 0b1) == 0b1
+    }
+    pub fn double_speed(&self) -> bool {
+        (self.io_registers[0x4d] >> 7) == 0b1
+    }
+    pub fn switch_speed(&mut self) {
+        self.io_registers[0x4d] ^= 0x80;
+        self.io_registers[0x4d] &= !0b1;
+    }
+
+    /// Lets cartridge hardware with its own timing keep up with the CPU.
+    pub fn tick(&mut self, m_cycles: u8) {
+        self.cartridge.tick(m_cycles as u64);
+
+        let rumble = self.rumble();
+        if rumble != self.rumble {
+            self.rumble = rumble;
+            if let Some(on_rumble) = &mut self.on_rumble {
+                on_rumble(rumble);
             }
-            _ => {
-                return Err(MemoryError::UnsupportedCartType {
-                    ct: self.header.cartridge_type(),
-                })
+        }
+
+        let infrared_led = self.cartridge.infrared_led();
+        if infrared_led != self.infrared_led {
+            self.infrared_led = infrared_led;
+            if let Some(on_infrared_led) = &mut self.on_infrared_led {
+                on_infrared_led(infrared_led);
             }
         }
+    }
+    /// Picks what drives the cartridge's real-time clock, if it has one.
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.cartridge.set_rtc_clock(clock);
+    }
 
-        Ok(())
+    pub fn has_battery(&self) -> bool {
+        self.header.cartridge_type().has_battery()
+    }
+    pub fn has_clock(&self) -> bool {
+        self.header.cartridge_type().has_clock()
+    }
+    /// Cartridge RAM followed by anything else the controller keeps powered, like the
+    /// MBC3 and HuC3 clocks or MBC6 flash, laid out the same way as other emulators' .sav files.
+    pub fn save_data(&mut self) -> Vec<u8> {
+        self.cartridge.save_data()
+    }
+    pub fn load_save_data(&mut self, data: &[u8]) -> Result<(), MemoryError> {
+        self.cartridge.load_save_data(data)
+    }
+    /// Whether anything that belongs in the save file was written since this was last called.
+    pub fn take_save_dirty(&mut self) -> bool {
+        self.cartridge.storage_mut().take_dirty()
+    }
+
+    /// Whether the cartridge's rumble motor is currently running.
+    pub fn rumble(&self) -> bool {
+        self.cartridge.rumble()
+    }
+    /// Gets called from `tick` whenever the rumble motor turns on or off.
+    pub fn set_rumble_callback(&mut self, on_rumble: impl FnMut(bool) + 'static) {
+        self.on_rumble = Some(Box::new(on_rumble));
+    }
+
+    /// Tilts carts with an accelerometer, in g along each axis with 0 being flat.
+    pub fn set_tilt(&mut self, x: f32, y: f32) {
+        self.cartridge.set_tilt(x, y);
+    }
+    /// Whether the cartridge's infrared sensor is picking up any light.
+    pub fn set_infrared_light(&mut self, on: bool) {
+        self.cartridge.set_infrared_light(on);
+    }
+    /// Gets called from `tick` whenever the cartridge's infrared LED turns on or off.
+    pub fn set_infrared_led_callback(&mut self, on_infrared_led: impl FnMut(bool) + 'static) {
+        self.on_infrared_led = Some(Box::new(on_infrared_led));
+    }
+
+    pub fn has_camera(&self) -> bool {
+        self.header.cartridge_type() == CartridgeType::POCKET_CAMERA
+    }
+    /// Points the Pocket Camera at something else, it starts out on a test pattern.
+    pub fn set_image_source(&mut self, source: impl ImageSource + 'static) {
+        self.cartridge.set_image_source(Box::new(source));
+    }
+
+    // the PPU gets at VRAM, OAM and its own registers directly, without any of
+    // what the CPU would see getting in the way
+
+    pub fn vram(&self) -> &[u8] {
+        &self.vram
+    }
+    pub fn oam(&self) -> &[u8] {
+        &self.oam
+    }
+    pub fn io_register(&self, addr: u16) -> u8 {
+        self.io_registers[(addr - 0xff00) as usize]
+    }
+    pub fn set_io_register(&mut self, addr: u16, val: u8) {
+        self.io_registers[(addr - 0xff00) as usize] = val;
     }
 
-    fn add_banks_for_mbc1(&mut self, bank_count: u16) {
-        self.rom = vec![0; 0x4000]; // covers first bank
-        for _ in 0..bank_count - 1 {
-            self.switchable_banks.push(vec![0; 0x4000]);
+    /// Reads a byte the way the CPU sees it.
+    pub fn read8(&self, addr: u16) -> u8 {
+        let index = addr as usize;
+        if let Some(byte) = self.boot_rom_at(index) {
+            return byte;
+        }
+
+        match addr {
+            // cartridge ROM, banked however the cartridge likes
+            0x0000..=0x7fff => self.cartridge.read(index),
+            // the PPU has VRAM to itself while drawing
+            0x8000..=0x9fff if self.lcd_mode() == 3 => 0xff,
+            0x8000..=0x9fff => self.vram[index - 0x8000],
+            // external ram if any, reads 0xff when there's none or it's disabled
+            0xa000..=0xbfff => self.cartridge.read(index),
+            0xc000..=0xcfff => self.wram1[index - 0xc000],
+            0xd000..=0xdfff => self.wram2[index - 0xd000],
+            // mirror of C000~DDFF
+            0xe000..=0xfdff => self.read8(addr - 0x2000),
+            // and OAM while scanning it as well
+            0xfe00..=0xfe9f if self.lcd_mode() >= 2 => 0xff,
+            0xfe00..=0xfe9f => self.oam[index - 0xfe00],
+            // not usable
+            0xfea0..=0xfeff => 0,
+            0xff00..=0xff7f => self.read_io(addr),
+            0xff80..=0xfffe => self.hram[index - 0xff80],
+            0xffff => self.interrupt_enable_reg,
+        }
+    }
+    /// Writes a byte the way the CPU would, setting off whatever the write does.
+    pub fn write8(&mut self, addr: u16, val: u8) {
+        let index = addr as usize;
+        match addr {
+            // writes to ROM set the MBC's registers instead, if there is one
+            0x0000..=0x7fff => self.cartridge.write(index, val),
+            0x8000..=0x9fff if self.lcd_mode() == 3 => {}
+            0x8000..=0x9fff => self.vram[index - 0x8000] = val,
+            0xa000..=0xbfff => self.cartridge.write(index, val),
+            0xc000..=0xcfff => self.wram1[index - 0xc000] = val,
+            0xd000..=0xdfff => self.wram2[index - 0xd000] = val,
+            0xe000..=0xfdff => self.write8(addr - 0x2000, val),
+            0xfe00..=0xfe9f if self.lcd_mode() >= 2 => {}
+            0xfe00..=0xfe9f => self.oam[index - 0xfe00] = val,
+            0xfea0..=0xfeff => {}
+            0xff00..=0xff7f => self.write_io(addr, val),
+            0xff80..=0xfffe => self.hram[index - 0xff80] = val,
+            0xffff => self.interrupt_enable_reg = val,
         }
     }
 
-    fn put_into_banks(&mut self, data: Vec<u8>) {
-        // write in bank 00 first
-        let mut i = 0;
-        while (i < self.rom.len()) && (i < data.len()) {
-            self.rom[i] = data[i];
-            i += 1;
+    // little-endian, like everything else on the SM83
+
+    pub fn read16(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.read8(addr), self.read8(addr.wrapping_add(1))])
+    }
+    pub fn write16(&mut self, addr: u16, val: u16) {
+        let [lo, hi] = val.to_le_bytes();
+        self.write8(addr, lo);
+        self.write8(addr.wrapping_add(1), hi);
+    }
+
+    // https://gbdev.io/pandocs/Hardware_Reg_List.html
+    // registers without anything special going on read back what was written
+
+    fn read_io(&self, addr: u16) -> u8 {
+        let val = self.io_registers[(addr - 0xff00) as usize];
+        match addr {
+            // JOYP, no buttons are ever held down yet
+            0xff00 => 0xc0 | (val & 0x30) | 0x0f,
+            // IF, the upper 3 bits aren't connected
+            0xff0f => 0xe0 | val,
+            // STAT, bit 7 isn't connected
+            0xff41 => 0x80 | val,
+            // KEY1 isn't there at all before the CGB
+            0xff4d if !self.model.is_cgb() => 0xff,
+            _ => val,
         }
-        // write in rest of memory banks
-        while i < data.len() {
-            for bank in &mut self.switchable_banks {
-                for j in 0..bank.len() {
-                    bank[j] = data[i];
-                    i += 1;
+    }
+    fn write_io(&mut self, addr: u16, val: u8) {
+        let reg = (addr - 0xff00) as usize;
+        match addr {
+            // JOYP, only the select lines can be written
+            0xff00 => self.io_registers[reg] = (self.io_registers[reg] & !0x30) | (val & 0x30),
+            // DIV, any write resets it
+            0xff04 => self.io_registers[reg] = 0,
+            // STAT, the mode and LY=LYC bits are the PPU's
+            0xff41 => self.io_registers[reg] = (self.io_registers[reg] & 0x07) | (val & 0x78),
+            // LY, only the PPU moves it along
+            0xff44 => {}
+            0xff46 => {
+                self.io_registers[reg] = val;
+                self.oam_dma(val);
+            }
+            // KEY1, only the switch can be armed, the speed changes on STOP
+            0xff4d if !self.model.is_cgb() => {}
+            0xff4d => self.io_registers[reg] = (self.io_registers[reg] & !0b1) | (val & 0b1),
+            // BANK, only bit 0 does anything, and once set the boot ROM is gone for good
+            0xff50 => {
+                if (val & 0b1) != 0 {
+                    self.boot_rom = None;
+                    self.io_registers[reg] |= 0b1;
                 }
             }
+            _ => self.io_registers[reg] = val,
         }
     }
-}
 
-// might consider adding different functionality depending on MBC
-// for indexing implementations
-
-impl Index<usize> for Memory {
-    type Output = u8;
-
-    fn index(&self, index: usize) -> &Self::Output {
-        if index <= 0x3fff {
-            // rom bank 00, includes header
-            return &self.rom[index];
-        } else if (index >= 0x4000) && (index <= 0x7fff) {
-            // rom bank 01
-            return &self.switchable_banks[0][index - 0x4000];
-        } else if (index >= 0x8000) && (index <= 0x9fff) {
-            // VRAM
-            return &self.vram[index - 0x8000];
-        } else if (index >= 0xa000) && (index <= 0xbfff) {
-            // external ram if any
-            return &self.ram[index - 0xa000];
-        } else if (index >= 0xc000) && (index <= 0xcfff) {
-            // WRAM bank 1
-            return &self.wram1[index - 0xc000];
-        } else if (index >= 0xd000) && (index <= 0xdfff) {
-            // WRAM bank 2
-            return &self.wram2[index - 0xd000];
-        } else if (index >= 0xe000) && (index <= 0xfdff) {
-            // mirror of C000~DDFF
-            return &self[(index - 0xe000) + 0xc000];
-        } else if (index >= 0xfe00) && (index <= 0xfe9f) {
-            // OAM
-            return &self.oam[index - 0xfe00];
-        } else if (index >= 0xff00) && (index <= 0xff7f) {
-            // I/O registers
-            return &self.io_registers[index - 0xff00];
-        } else if (index >= 0xff80) && (index <= 0xfffe) {
-            // HRAM
-            return &self.hram[index - 0xff80];
-        } else if index == 0xffff {
-            // interrupt enable register
-            return &self.interrupt_enable_reg;
-        } else {
-            return &0;
+    // what the PPU's up to as far as the CPU's concerned, it doesn't get in
+    // the way while the LCD's off
+    fn lcd_mode(&self) -> u8 {
+        if (self.io_registers[0x40] & 0x80) == 0 {
+            return 0;
         }
+        self.io_registers[0x41] & 0b11
     }
-}
 
-impl IndexMut<usize> for Memory {
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        if index <= 0x3fff {
-            // rom bank 00, includes header
-            return &mut self.rom[index];
-        } else if (index >= 0x4000) && (index <= 0x7fff) {
-            // rom bank 01
-            return &mut self.switchable_banks[0][index - 0x4000];
-        } else if (index >= 0x8000) && (index <= 0x9fff) {
-            // VRAM
-            return &mut self.vram[index - 0x8000];
-        } else if (index >= 0xa000) && (index <= 0xbfff) {
-            // external ram if any
-            return &mut self.ram[index - 0xa000];
-        } else if (index >= 0xc000) && (index <= 0xcfff) {
-            // WRAM bank 1
-            return &mut self.wram1[index - 0xc000];
-        } else if (index >= 0xd000) && (index <= 0xdfff) {
-            // WRAM bank 2
-            return &mut self.wram2[index - 0xd000];
-        } else if (index >= 0xe000) && (index <= 0xfdff) {
-            // mirror of C000~DDFF
-            return &mut self[(index - 0xe000) + 0xc000];
-        } else if (index >= 0xfe00) && (index <= 0xfe9f) {
-            // OAM
-            return &mut self.oam[index - 0xfe00];
-        } else if (index >= 0xff00) && (index <= 0xff7f) {
-            // I/O registers
-            return &mut self.io_registers[index - 0xff00];
-        } else if (index >= 0xff80) && (index <= 0xfffe) {
-            // HRAM
-            return &mut self.hram[index - 0xff80];
-        } else {
-            // interrupt enable register
-            return &mut self.interrupt_enable_reg;
+    // copies 0xa0 bytes from 0xXX00 into OAM. on hardware this takes 160 M-cycles
+    // and locks the CPU out of most of the bus, here it's done all at once
+    fn oam_dma(&mut self, source: u8) {
+        let start = (source as u16) << 8;
+        for i in 0..0xa0 {
+            self.oam[i] = self.dma_read(start + i as u16);
+        }
+    }
+    // the DMA unit has its own way onto the bus, so it isn't shut out of VRAM
+    // while the PPU's drawing the way the CPU is
+    fn dma_read(&self, addr: u16) -> u8 {
+        let index = addr as usize;
+        match addr {
+            0x0000..=0x7fff | 0xa000..=0xbfff => self.cartridge.read(index),
+            0x8000..=0x9fff => self.vram[index - 0x8000],
+            0xc000..=0xcfff => self.wram1[index - 0xc000],
+            0xd000..=0xdfff => self.wram2[index - 0xd000],
+            // anything higher up sees WRAM through the echo
+            0xe000..=0xffff => self.dma_read(addr - 0x2000),
         }
     }
 }
@@ -296,18 +493,21 @@ impl IndexMut<usize> for Memory {
 mod tests {
     use crate::memory::{
         cartridgeheader::{CartridgeHeader, CartridgeType},
-        MemoryError,
+        imagesource::StaticImage,
+        MemoryError, RtcClock,
     };
+    use crate::model::Model;
 
     use super::Memory;
 
     #[test]
     fn new_blank_data() {
-        let cd = Memory::new();
+        let mut cd = Memory::new();
         assert_eq!(cd.header, CartridgeHeader::new());
-        assert_eq!(cd.rom, Vec::<u8>::new());
-        assert_eq!(cd.switchable_banks, Vec::<Vec<u8>>::new());
-        assert_eq!(cd.ram, Vec::<u8>::new());
+        assert_eq!(cd.save_data(), Vec::<u8>::new());
+        // nothing in the slot
+        assert_eq!(cd.read8(0x0000), 0xff);
+        assert_eq!(cd.read8(0xa000), 0xff);
     }
 
     /*
@@ -478,7 +678,148 @@ mod tests {
         let result = Memory::from(rom);
         assert!(if let Ok(_) = result { true } else { false });
     }
-    // TODO: add 1MiB+ tests whenever we introduce that in implementation
+
+    // every bank starts with its own number
+    fn mbc1_with_banks(bank_count: usize, rom_shift_count: u8) -> Memory {
+        let mut rom = vec![0; bank_count * 0x4000];
+        for bank in 0..bank_count {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x0147] = 0x01; // cartridge type is MBC1
+        rom[0x0148] = rom_shift_count;
+
+        Memory::from(rom).unwrap()
+    }
+
+    #[test]
+    fn mbc1_switches_rom_bank() {
+        let mut mem = mbc1_with_banks(8, 0x02);
+        assert_eq!(mem.read8(0x4000), 1);
+
+        mem.write8(0x2000, 0x05);
+        assert_eq!(mem.read8(0x4000), 5);
+        assert_eq!(mem.read8(0x0000), 0);
+    }
+    #[test]
+    fn mbc1_bank_0_selects_1() {
+        let mut mem = mbc1_with_banks(8, 0x02);
+        mem.write8(0x2000, 0x05);
+        mem.write8(0x3fff, 0x00);
+
+        assert_eq!(mem.read8(0x4000), 1);
+    }
+    #[test]
+    fn mbc1_bank_number_wraps_to_rom_size() {
+        let mut mem = mbc1_with_banks(4, 0x01);
+        mem.write8(0x2000, 0x06);
+
+        assert_eq!(mem.read8(0x4000), 2);
+    }
+    #[test]
+    fn mbc1_writes_dont_touch_rom() {
+        let mut mem = mbc1_with_banks(4, 0x01);
+        mem.write8(0x0000, 0x42);
+        mem.write8(0x4000, 0x42);
+
+        assert_eq!(mem.read8(0x0000), 0);
+        assert_eq!(mem.read8(0x4000), 1);
+    }
+    #[test]
+    fn mbc1_1mib_upper_bits_select_rom_bank() {
+        let mut mem = mbc1_with_banks(64, 0x05);
+        mem.write8(0x4000, 0x01);
+        mem.write8(0x2000, 0x02);
+        assert_eq!(mem.read8(0x4000), 0x22);
+
+        // advanced mode moves the upper bits onto the first bank too
+        mem.write8(0x6000, 0x01);
+        assert_eq!(mem.read8(0x0000), 0x20);
+    }
+    #[test]
+    fn mbc1_2mib_valid() {
+        let mut mem = mbc1_with_banks(128, 0x06);
+        mem.write8(0x4000, 0x03);
+        mem.write8(0x2000, 0x1f);
+
+        assert_eq!(mem.read8(0x4000), 0x7f);
+    }
+    #[test]
+    fn mbc1m_detected_from_second_logo() {
+        let mut rom = vec![0; 64 * 0x4000];
+        for bank in 0..64 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x0147] = 0x01; // cartridge type is MBC1
+        rom[0x0148] = 0x05; // ROM size is 1MiB
+        rom[0x40104..0x40134].copy_from_slice(&super::cartridgeheader::NINTENDO_LOGO);
+
+        let mut mem = Memory::from(rom).unwrap();
+        mem.write8(0x4000, 0x01);
+        mem.write8(0x2000, 0x02);
+        assert_eq!(mem.read8(0x4000), 0x12);
+    }
+
+    fn mbc1_with_ram(ram_size_tag: u8) -> Memory {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x03; // cartridge type is MBC1+RAM+BATTERY
+        rom[0x0149] = ram_size_tag;
+
+        Memory::from(rom).unwrap()
+    }
+    #[test]
+    fn mbc1_ram_needs_enabling() {
+        let mut mem = mbc1_with_ram(0x02);
+        mem.write8(0xa000, 0x42);
+        assert_eq!(mem.read8(0xa000), 0xff);
+
+        mem.write8(0x0000, 0x0a);
+        mem.write8(0xa000, 0x42);
+        assert_eq!(mem.read8(0xa000), 0x42);
+
+        mem.write8(0x0000, 0x00);
+        assert_eq!(mem.read8(0xa000), 0xff);
+    }
+    #[test]
+    fn mbc1_ram_banks_in_advanced_mode() {
+        let mut mem = mbc1_with_ram(0x03);
+        mem.write8(0x0000, 0x0a);
+        mem.write8(0x6000, 0x01);
+        for bank in 0..4 {
+            mem.write8(0x4000, bank);
+            mem.write8(0xa000, bank + 0x10);
+        }
+
+        for bank in 0..4 {
+            mem.write8(0x4000, bank);
+            assert_eq!(mem.read8(0xa000), bank + 0x10);
+        }
+        // simple mode always uses bank 0
+        mem.write8(0x6000, 0x00);
+        assert_eq!(mem.read8(0xa000), 0x10);
+    }
+    #[test]
+    fn mbc1_ram_too_large_invalid() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x02; // cartridge type is MBC1+RAM
+        rom[0x0149] = 0x04; // 128 KiB of RAM
+
+        let result = Memory::from(rom);
+        assert_eq!(
+            result.err(),
+            Some(MemoryError::CartTypeMismatch {
+                ct: CartridgeType::MBC1_RAM,
+                reason: String::from("given RAM size is too large"),
+            })
+        );
+    }
+    #[test]
+    fn mbc1_without_ram_reads_ff() {
+        let mut mem = mbc1_with_banks(4, 0x01);
+        mem.write8(0x0000, 0x0a);
+        mem.write8(0xa000, 0x42);
+
+        assert_eq!(mem.read8(0xa000), 0xff);
+    }
 
     #[test]
     fn reading_zerovec_mbc1_with_ram_invalid() {
@@ -511,24 +852,469 @@ mod tests {
         }
     }
 
+    fn mbc2_with_banks(bank_count: usize, rom_shift_count: u8) -> Memory {
+        let mut rom = vec![0; bank_count * 0x4000];
+        for bank in 0..bank_count {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x0147] = 0x06; // cartridge type is MBC2+BATTERY
+        rom[0x0148] = rom_shift_count;
+
+        Memory::from(rom).unwrap()
+    }
+
     #[test]
-    fn error_on_unsupported_carttype() {
-        // hopefully someday this test will be unnecessary.
-        let mut rom = vec![0; 4000];
-        rom[0x0100 + 71] = 0xfc; // pocket camera
+    fn mbc2_switches_rom_bank() {
+        let mut mem = mbc2_with_banks(16, 0x03);
+        assert_eq!(mem.read8(0x4000), 1);
+
+        mem.write8(0x2100, 0x0c);
+        assert_eq!(mem.read8(0x4000), 0x0c);
+        // address bit 8 clear goes to RAM enable instead
+        mem.write8(0x2000, 0x03);
+        assert_eq!(mem.read8(0x4000), 0x0c);
+    }
+    #[test]
+    fn mbc2_ram_is_half_bytes() {
+        let mut mem = mbc2_with_banks(2, 0x00);
+        mem.write8(0x0000, 0x0a);
+        mem.write8(0xa000, 0x35);
+
+        assert_eq!(mem.read8(0xa000), 0xf5);
+    }
+    #[test]
+    fn mbc2_ram_echoes() {
+        let mut mem = mbc2_with_banks(2, 0x00);
+        mem.write8(0x0000, 0x0a);
+        mem.write8(0xa042, 0x07);
+
+        assert_eq!(mem.read8(0xa242), 0xf7);
+        assert_eq!(mem.read8(0xbe42), 0xf7);
+    }
+    #[test]
+    fn mbc2_ram_needs_enabling() {
+        let mut mem = mbc2_with_banks(2, 0x00);
+        mem.write8(0xa000, 0x07);
+        assert_eq!(mem.read8(0xa000), 0xff);
+
+        mem.write8(0x0000, 0x0a);
+        assert_eq!(mem.read8(0xa000), 0xf0);
+    }
+    #[test]
+    fn mbc2_rom_too_large_invalid() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x05; // cartridge type is MBC2
+        rom[0x0148] = 0x04; // 512 KiB of ROM
 
         let result = Memory::from(rom);
-        if let Err(e) = result {
-            assert_eq!(
-                e,
-                MemoryError::UnsupportedCartType {
-                    ct: CartridgeType::POCKET_CAMERA
-                }
-            );
-        } else {
-            // not an error, fail this test
-            assert!(false);
+        assert_eq!(
+            result.err(),
+            Some(MemoryError::CartTypeMismatch {
+                ct: CartridgeType::MBC2,
+                reason: String::from("given ROM size is too large or incorrect"),
+            })
+        );
+    }
+
+    fn mbc3_with(cartridge_type: u8, ram_size_tag: u8) -> Memory {
+        let mut rom = vec![0; 16 * 0x4000];
+        for bank in 0..16 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        rom[0x0147] = cartridge_type;
+        rom[0x0148] = 0x03; // 256 KiB of ROM
+        rom[0x0149] = ram_size_tag;
+
+        let mut mem = Memory::from(rom).unwrap();
+        mem.set_rtc_clock(RtcClock::Emulated);
+        mem
+    }
+
+    #[test]
+    fn mbc3_switches_rom_bank() {
+        let mut mem = mbc3_with(0x11, 0x00);
+        assert_eq!(mem.read8(0x4000), 1);
+
+        mem.write8(0x2000, 0x0d);
+        assert_eq!(mem.read8(0x4000), 0x0d);
+        mem.write8(0x2000, 0x00);
+        assert_eq!(mem.read8(0x4000), 1);
+    }
+    #[test]
+    fn mbc3_ram_banks() {
+        let mut mem = mbc3_with(0x13, 0x03);
+        mem.write8(0x0000, 0x0a);
+        for bank in 0..4 {
+            mem.write8(0x4000, bank);
+            mem.write8(0xa000, bank + 0x10);
+        }
+
+        for bank in 0..4 {
+            mem.write8(0x4000, bank);
+            assert_eq!(mem.read8(0xa000), bank + 0x10);
+        }
+    }
+    #[test]
+    fn mbc3_rtc_through_memory() {
+        let mut mem = mbc3_with(0x10, 0x03);
+        mem.write8(0x0000, 0x0a);
+        mem.write8(0x4000, 0x09); // minutes
+        mem.write8(0xa000, 0x2a);
+        assert_eq!(mem.read8(0xa000), 0x2a);
+
+        // one minute later
+        mem.tick(0xff);
+        for _ in 0..(60 * (1 << 20) / 0xff) {
+            mem.tick(0xff);
+        }
+        mem.write8(0x6000, 0x00);
+        mem.write8(0x6000, 0x01);
+        assert_eq!(mem.read8(0xa000), 0x2b);
+
+        // RAM is still there when selected
+        mem.write8(0x4000, 0x00);
+        mem.write8(0xa000, 0x42);
+        assert_eq!(mem.read8(0xa000), 0x42);
+    }
+    #[test]
+    fn mbc3_without_ram_invalid() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x0f; // cartridge type is MBC3+TIMER+BATTERY
+        rom[0x0149] = 0x02; // 8 KiB of RAM
+
+        let result = Memory::from(rom);
+        assert_eq!(
+            result.err(),
+            Some(MemoryError::CartTypeMismatch {
+                ct: CartridgeType::MBC3_TIMER_BATTERY,
+                reason: String::from("header says RAM included with wrong cartridge type"),
+            })
+        );
+    }
+
+    fn mbc5_with(cartridge_type: u8, ram_size_tag: u8) -> Memory {
+        let mut rom = vec![0; 512 * 0x4000];
+        for bank in 0..512 {
+            rom[bank * 0x4000] = bank as u8;
+            rom[bank * 0x4000 + 1] = (bank >> 8) as u8;
+        }
+        rom[0x0147] = cartridge_type;
+        rom[0x0148] = 0x08; // 8 MiB of ROM
+        rom[0x0149] = ram_size_tag;
+
+        Memory::from(rom).unwrap()
+    }
+
+    #[test]
+    fn mbc5_switches_9_bit_rom_bank() {
+        let mut mem = mbc5_with(0x19, 0x00);
+        mem.write8(0x2000, 0x34);
+        mem.write8(0x3000, 0x01);
+        assert_eq!((mem.read8(0x4000), mem.read8(0x4001)), (0x34, 0x01));
+
+        // bank 0 isn't remapped
+        mem.write8(0x2000, 0x00);
+        mem.write8(0x3000, 0x00);
+        assert_eq!((mem.read8(0x4000), mem.read8(0x4001)), (0x00, 0x00));
+    }
+    #[test]
+    fn mbc5_16_ram_banks() {
+        let mut mem = mbc5_with(0x1b, 0x04);
+        mem.write8(0x0000, 0x0a);
+        for bank in 0..16 {
+            mem.write8(0x4000, bank);
+            mem.write8(0xa000, bank + 0x10);
+        }
+
+        for bank in 0..16 {
+            mem.write8(0x4000, bank);
+            assert_eq!(mem.read8(0xa000), bank + 0x10);
+        }
+    }
+    #[test]
+    fn mbc5_rumble_callback() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let mut mem = mbc5_with(0x1e, 0x03);
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let sink = reported.clone();
+        mem.set_rumble_callback(move |on| sink.borrow_mut().push(on));
+
+        mem.write8(0x4000, 0x08);
+        mem.tick(1);
+        assert!(mem.rumble());
+        mem.tick(1);
+        mem.write8(0x4000, 0x00);
+        mem.tick(1);
+
+        assert_eq!(*reported.borrow(), vec![true, false]);
+    }
+    #[test]
+    fn mbc5_no_rumble_without_motor() {
+        let mut mem = mbc5_with(0x1b, 0x04);
+        mem.write8(0x4000, 0x08);
+
+        assert!(!mem.rumble());
+    }
+
+    #[test]
+    fn mbc6_flash_marks_save_dirty() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x20;
+        let mut mem = Memory::from(rom).unwrap();
+        for (addr, val) in [
+            (0x0c00, 0x01),
+            (0x1000, 0x01),
+            (0x2800, 0x08),
+            (0x3800, 0x08),
+        ] {
+            mem.write8(addr, val);
+        }
+        mem.write8(0x2000, 0x02);
+        mem.write8(0x3000, 0x01);
+
+        // unlocking alone doesn't change anything
+        mem.write8(0x5555, 0xaa);
+        mem.write8(0x6aaa, 0x55);
+        mem.write8(0x5555, 0xa0);
+        assert!(!mem.take_save_dirty());
+        mem.write8(0x4000, 0x12);
+        assert!(mem.take_save_dirty());
+    }
+    #[test]
+    fn mbc7_tilt_through_memory() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x22;
+        let mut mem = Memory::from(rom).unwrap();
+        assert_eq!(mem.save_data().len(), 0x100);
+
+        mem.write8(0x0000, 0x0a);
+        mem.write8(0x4000, 0x40);
+        mem.set_tilt(0.0, 0.0);
+        mem.write8(0xa000, 0x55);
+        mem.write8(0xa010, 0xaa);
+        assert_eq!((mem.read8(0xa020), mem.read8(0xa030)), (0xd0, 0x81));
+    }
+    #[test]
+    fn huc1_infrared_through_memory() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0xff;
+        rom[0x0149] = 0x02;
+        let mut mem = Memory::from(rom).unwrap();
+        let reported = Rc::new(RefCell::new(Vec::new()));
+        let sink = reported.clone();
+        mem.set_infrared_led_callback(move |on| sink.borrow_mut().push(on));
+
+        mem.write8(0x0000, 0x0e);
+        mem.set_infrared_light(true);
+        assert_eq!(mem.read8(0xa000), 0xc1);
+        mem.write8(0xa000, 0x01);
+        mem.tick(1);
+        mem.write8(0xa000, 0x00);
+        mem.tick(1);
+
+        assert_eq!(*reported.borrow(), vec![true, false]);
+    }
+    #[test]
+    fn mmm01_detected_from_menu_header() {
+        // the menu's header in the last 32 KiB, the first game's at the start
+        let mut rom = vec![0; 0x20000];
+        rom[0x0147] = 0x01;
+        rom[0x18147] = 0x0b;
+        rom[0x18148] = 0x02;
+        rom[0x18000] = 0x42;
+        let mem = Memory::from(rom).unwrap();
+
+        assert_eq!(mem.header.cartridge_type(), CartridgeType::MMM01);
+        assert_eq!(mem.read8(0x0000), 0x42);
+    }
+    #[test]
+    fn tama5_rom_too_large_invalid() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0xfd;
+        rom[0x0148] = 0x05;
+
+        assert_eq!(
+            Memory::from(rom).err(),
+            Some(MemoryError::CartTypeMismatch {
+                ct: CartridgeType::BANDAI_TAMA5,
+                reason: String::from("given ROM size is too large or incorrect"),
+            })
+        );
+    }
+
+    #[test]
+    fn save_data_round_trip() {
+        let mut mem = mbc1_with_ram(0x03);
+        mem.write8(0x0000, 0x0a);
+        mem.write8(0x6000, 0x01);
+        mem.write8(0x4000, 0x02);
+        mem.write8(0xa123, 0x42);
+        let data = mem.save_data();
+        assert_eq!(data.len(), 0x8000);
+        assert_eq!(data[0x4123], 0x42);
+
+        let mut loaded = mbc1_with_ram(0x03);
+        loaded.load_save_data(&data).unwrap();
+        loaded.write8(0x0000, 0x0a);
+        loaded.write8(0x6000, 0x01);
+        loaded.write8(0x4000, 0x02);
+        assert_eq!(loaded.read8(0xa123), 0x42);
+    }
+    #[test]
+    fn save_data_too_short_invalid() {
+        let mut mem = mbc1_with_ram(0x02);
+
+        assert_eq!(
+            mem.load_save_data(&[0; 0x1000]),
+            Err(MemoryError::InvalidSaveSize {
+                size: 0x1000,
+                expected: 0x2000
+            })
+        );
+    }
+    #[test]
+    fn save_data_appends_rtc_block() {
+        let mut mem = mbc3_with(0x10, 0x02);
+        assert_eq!(mem.save_data().len(), 0x2000 + 48);
+
+        let mut mem = mbc3_with(0x13, 0x02);
+        assert_eq!(mem.save_data().len(), 0x2000);
+    }
+    #[test]
+    fn ram_writes_mark_save_dirty() {
+        let mut mem = mbc1_with_ram(0x02);
+        assert!(!mem.take_save_dirty());
+
+        // dropped with RAM disabled, so there's nothing new to save
+        mem.write8(0xa000, 0x42);
+        assert!(!mem.take_save_dirty());
+        mem.write8(0x0000, 0x0a);
+        assert!(!mem.take_save_dirty());
+        mem.write8(0xa000, 0x42);
+        assert!(mem.take_save_dirty());
+        assert!(!mem.take_save_dirty());
+    }
+    #[test]
+    fn rtc_writes_mark_save_dirty() {
+        let mut mem = mbc3_with(0x10, 0x02);
+        mem.write8(0x0000, 0x0a);
+        mem.write8(0x4000, 0x08);
+        mem.write8(0xa000, 0x30);
+
+        assert!(mem.take_save_dirty());
+    }
+
+    #[test]
+    fn pocket_camera_captures_from_source() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0xfc;
+        rom[0x0149] = 0x04;
+        let mut mem = Memory::from(rom).unwrap();
+        assert!(mem.has_camera());
+        // a flat grey wall, darker than every threshold once exposed
+        let grey = StaticImage::from_pgm(b"P2 1 1 255 128").unwrap();
+        mem.set_image_source(grey);
+
+        mem.write8(0x4000, 0x10);
+        mem.write8(0xa002, 0x08);
+        for i in 0..48 {
+            mem.write8(0xa006 + i, 0xff);
+        }
+        mem.write8(0xa000, 0x01);
+        mem.tick(u8::MAX);
+        assert_eq!(mem.read8(0xa000), 0x01);
+        for _ in 0..0x400 {
+            mem.tick(u8::MAX);
         }
+        assert_eq!(mem.read8(0xa000), 0x00);
+
+        mem.write8(0x4000, 0x00);
+        assert_eq!(mem.read8(0xa100), 0xff);
+        assert_eq!(mem.read8(0xa101), 0xff);
+    }
+
+    #[test]
+    fn post_boot_io_registers_dmg() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.post_boot(Model::Dmg);
+
+        assert_eq!(mem.read8(0xff00), 0xcf);
+        assert_eq!(mem.read8(0xff04), 0xab);
+        assert_eq!(mem.read8(0xff07), 0xf8);
+        assert_eq!(mem.read8(0xff0f), 0xe1);
+        assert_eq!(mem.read8(0xff26), 0xf1);
+        assert_eq!(mem.read8(0xff40), 0x91);
+        assert_eq!(mem.read8(0xff41), 0x85);
+        assert_eq!(mem.read8(0xff46), 0xff);
+        assert_eq!(mem.read8(0xff47), 0xfc);
+        assert_eq!(mem.read8(0xffff), 0x00);
+        assert!(!mem.double_speed());
+    }
+    #[test]
+    fn post_boot_io_registers_cgb() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.post_boot(Model::Cgb);
+
+        assert_eq!(mem.read8(0xff02), 0x7f);
+        assert_eq!(mem.read8(0xff46), 0x00);
+        assert_eq!(mem.read8(0xff70), 0xf8);
+        assert!(!mem.double_speed());
+        assert!(!mem.speed_switch_armed());
+    }
+
+    fn cart_with_boot_rom(boot_rom_size: usize) -> Memory {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.load_boot_rom(vec![0x22; boot_rom_size]).unwrap();
+        mem
+    }
+
+    #[test]
+    fn dmg_boot_rom_overlays_first_page() {
+        let mem = cart_with_boot_rom(0x0100);
+
+        assert_eq!(mem.read8(0x0000), 0x22);
+        assert_eq!(mem.read8(0x00ff), 0x22);
+        assert_eq!(mem.read8(0x0100), 0x00);
+        assert_eq!(mem.read8(0x0200), 0x00);
+    }
+    #[test]
+    fn cgb_boot_rom_leaves_header_visible() {
+        let mem = cart_with_boot_rom(0x0900);
+
+        assert_eq!(mem.read8(0x00ff), 0x22);
+        assert_eq!(mem.read8(0x0100), 0x00);
+        assert_eq!(mem.read8(0x01ff), 0x00);
+        assert_eq!(mem.read8(0x0200), 0x22);
+        assert_eq!(mem.read8(0x08ff), 0x22);
+        assert_eq!(mem.read8(0x0900), 0x00);
+    }
+    #[test]
+    fn writing_ff50_unmaps_boot_rom() {
+        let mut mem = cart_with_boot_rom(0x0100);
+        mem.write8(0xff50, 0x01);
+
+        assert_eq!(mem.read8(0x0000), 0x00);
+    }
+    #[test]
+    fn boot_rom_stays_unmapped() {
+        let mut mem = cart_with_boot_rom(0x0100);
+        mem.write8(0xff50, 0x01);
+        mem.write8(0xff50, 0x00);
+
+        assert_eq!(mem.read8(0x0000), 0x00);
+    }
+    #[test]
+    fn boot_rom_invalid_size() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+
+        assert_eq!(
+            mem.load_boot_rom(vec![0; 0x0200]),
+            Err(MemoryError::InvalidBootRomSize { size: 0x0200 })
+        );
+        assert_eq!(mem.read8(0x0000), 0x00);
     }
 
     /*
@@ -545,13 +1331,11 @@ mod tests {
 
                 let mut mem = Memory::from(rom).unwrap();
 
-                mem[$loc] = $x;
-                assert_eq!(mem[$loc], $x);
+                mem.write8($loc, $x);
+                assert_eq!(mem.read8($loc), $x);
             }
         };
     }
-    set_and_check_index_test!(set_and_check_rombank00, 0x42, 0x0200);
-    set_and_check_index_test!(set_and_check_rombank01, 0x42, 0x4100);
     set_and_check_index_test!(set_and_check_vram, 0x42, 0x8100);
     set_and_check_index_test!(set_and_check_ram, 0x42, 0xa100);
     set_and_check_index_test!(set_and_check_wram1, 0x42, 0xc100);
@@ -563,9 +1347,158 @@ mod tests {
     set_and_check_index_test!(set_and_check_interreg, 0x42, 0xffff);
 
     #[test]
-    fn out_of_bounds_index_returns_zero() {
-        let mem = Memory::from(vec![0; 4000]).unwrap();
+    fn rom_only_ignores_rom_writes() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0200] = 0x11;
+        rom[0x4100] = 0x22;
+        let mut mem = Memory::from(rom).unwrap();
+        mem.write8(0x0200, 0x42);
+        mem.write8(0x4100, 0x42);
+
+        assert_eq!(mem.read8(0x0200), 0x11);
+        assert_eq!(mem.read8(0x4100), 0x22);
+    }
+    #[test]
+    fn unusable_area_returns_zero() {
+        let mut mem = Memory::from(vec![0; 4000]).unwrap();
+        mem.write8(0xfea0, 0x42);
+
+        assert_eq!(mem.read8(0xfea0), 0);
+        assert_eq!(mem.read8(0xfeff), 0);
+    }
+    #[test]
+    fn sixteen_bit_access_is_little_endian() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.write16(0xc000, 0x1234);
+
+        assert_eq!(mem.read8(0xc000), 0x34);
+        assert_eq!(mem.read8(0xc001), 0x12);
+        assert_eq!(mem.read16(0xc000), 0x1234);
+    }
+    #[test]
+    fn echo_ram_writes_through() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.write8(0xe123, 0x42);
+
+        assert_eq!(mem.read8(0xc123), 0x42);
+    }
+
+    /*
+       I/O register tests
+    */
+
+    #[test]
+    fn writing_div_resets_it() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.post_boot(Model::Dmg);
+        mem.write8(0xff04, 0x42);
+
+        assert_eq!(mem.read8(0xff04), 0x00);
+    }
+    #[test]
+    fn joypad_only_takes_select_lines() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.write8(0xff00, 0x20);
+        assert_eq!(mem.read8(0xff00), 0xef);
+
+        mem.write8(0xff00, 0x0f);
+        assert_eq!(mem.read8(0xff00), 0xcf);
+    }
+    #[test]
+    fn interrupt_flag_upper_bits_read_set() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.write8(0xff0f, 0x01);
+
+        assert_eq!(mem.read8(0xff0f), 0xe1);
+        assert_eq!(mem.interrupt_flag(), 0x01);
+    }
+    #[test]
+    fn stat_mode_bits_read_only() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.set_io_register(0xff41, 0x02);
+        mem.write8(0xff41, 0xff);
+
+        assert_eq!(mem.read8(0xff41), 0xfa);
+    }
+    #[test]
+    fn ly_read_only() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.set_io_register(0xff44, 0x42);
+        mem.write8(0xff44, 0x00);
+
+        assert_eq!(mem.read8(0xff44), 0x42);
+    }
+    #[test]
+    fn vram_blocked_in_mode_3() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.write8(0x8000, 0x42);
+        mem.write8(0xff40, 0x80);
+        mem.set_io_register(0xff41, 0x03);
+        assert_eq!(mem.read8(0x8000), 0xff);
+        mem.write8(0x8000, 0x24);
+
+        mem.set_io_register(0xff41, 0x02);
+        assert_eq!(mem.read8(0x8000), 0x42);
+    }
+    #[test]
+    fn oam_blocked_in_modes_2_and_3() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.write8(0xfe00, 0x42);
+        mem.write8(0xff40, 0x80);
+        for mode in [0x02, 0x03] {
+            mem.set_io_register(0xff41, mode);
+            assert_eq!(mem.read8(0xfe00), 0xff);
+            mem.write8(0xfe00, 0x24);
+        }
+
+        mem.set_io_register(0xff41, 0x00);
+        assert_eq!(mem.read8(0xfe00), 0x42);
+        // nothing's blocked with the LCD off
+        mem.write8(0xff40, 0x00);
+        mem.set_io_register(0xff41, 0x03);
+        assert_eq!(mem.read8(0xfe00), 0x42);
+    }
+    #[test]
+    fn dma_copies_into_oam() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        for i in 0..0xa0 {
+            mem.write8(0xc100 + i, i as u8);
+        }
+        mem.write8(0xff46, 0xc1);
+
+        assert_eq!(mem.read8(0xfe00), 0x00);
+        assert_eq!(mem.read8(0xfe9f), 0x9f);
+    }
+    #[test]
+    fn dma_from_vram_while_drawing() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        for i in 0..0xa0 {
+            mem.write8(0x8100 + i, i as u8);
+        }
+        mem.write8(0xff40, 0x80);
+        mem.set_io_register(0xff41, 0x03);
+        mem.write8(0xff46, 0x81);
+
+        // OAM's only readable again once the PPU's done with it
+        mem.set_io_register(0xff41, 0x00);
+        assert_eq!(mem.read8(0xfe00), 0x00);
+        assert_eq!(mem.read8(0xfe9f), 0x9f);
+    }
+    #[test]
+    fn key1_only_arms_the_switch() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.set_model(Model::Cgb);
+        mem.write8(0xff4d, 0xff);
+
+        assert!(mem.speed_switch_armed());
+        assert!(!mem.double_speed());
+    }
+    #[test]
+    fn key1_missing_before_cgb() {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.write8(0xff4d, 0x01);
 
-        assert_eq!(mem[0x10000], 0);
+        assert_eq!(mem.read8(0xff4d), 0xff);
+        assert!(!mem.speed_switch_armed());
     }
 }
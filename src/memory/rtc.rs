@@ -0,0 +1,91 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// what keeps the MBC3 and HuC3 clocks going, they only differ in what they count
+
+pub const M_CYCLES_PER_SECOND: u64 = 1 << 20;
+
+/// What drives the real-time clock forward.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RtcClock {
+    Host,     // wall-clock time, like a real cartridge
+    Emulated, // M-cycles handed to `Cartridge::tick`, for deterministic runs
+}
+
+/// Hands out the whole seconds that have gone by, from whichever clock is in use.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ClockSource {
+    clock: RtcClock,
+    last_update: SystemTime, // host time the counters were last brought up to
+    subsecond_cycles: u64,   // emulated M-cycles not yet making up a whole second
+}
+
+impl ClockSource {
+    pub fn new() -> ClockSource {
+        ClockSource {
+            clock: RtcClock::Host,
+            last_update: SystemTime::now(),
+            subsecond_cycles: 0,
+        }
+    }
+
+    /// Seconds of host time since this was last asked, none when running from emulated time.
+    pub fn elapsed(&mut self) -> u64 {
+        if self.clock != RtcClock::Host {
+            return 0;
+        }
+
+        let now = SystemTime::now();
+        // the host clock going backwards just means no time passes
+        match now.duration_since(self.last_update) {
+            Ok(elapsed) => {
+                let seconds = elapsed.as_secs();
+                self.last_update += Duration::from_secs(seconds);
+                seconds
+            }
+            Err(_) => {
+                self.last_update = now;
+                0
+            }
+        }
+    }
+    /// Seconds that `m_cycles` more finish off, none when running from host time.
+    pub fn tick(&mut self, m_cycles: u64) -> u64 {
+        if self.clock != RtcClock::Emulated {
+            return 0;
+        }
+
+        self.subsecond_cycles += m_cycles;
+        let seconds = self.subsecond_cycles / M_CYCLES_PER_SECOND;
+        self.subsecond_cycles %= M_CYCLES_PER_SECOND;
+        seconds
+    }
+
+    /// Switches clocks, anything from `elapsed` should be counted before this.
+    pub fn set_clock(&mut self, clock: RtcClock) {
+        self.clock = clock;
+        self.last_update = SystemTime::now();
+    }
+    /// Starts the current second over, for when the game sets the time.
+    pub fn restart_second(&mut self) {
+        self.subsecond_cycles = 0;
+    }
+
+    /// Now as a unix timestamp, for save files.
+    pub fn timestamp(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs())
+    }
+    /// Picks up from a save made at `timestamp`, handing back the seconds spent
+    /// switched off when running from host time.
+    pub fn resume_from(&mut self, timestamp: u64) -> u64 {
+        self.subsecond_cycles = 0;
+        if self.clock != RtcClock::Host {
+            self.last_update = SystemTime::now();
+            return 0;
+        }
+
+        self.last_update = UNIX_EPOCH + Duration::from_secs(timestamp);
+        self.elapsed()
+    }
+}
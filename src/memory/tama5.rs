@@ -0,0 +1,145 @@
+use super::cartridge::{Cartridge, Storage};
+
+// https://gbdev.gg8.se/wiki/articles/TAMA5
+
+/// Bandai's TAMA5, which hides everything behind two ports at 0xa000 and 0xa001.
+/// A write to 0xa001 picks a register and the 4-bit value goes through 0xa000.
+/// Its 32 bytes of RAM are reached with commands rather than being mapped. The
+/// real-time clock chip isn't emulated, commands for it are ignored.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Tama5 {
+    select: u8,  // written to a001
+    rom_lo: u8,  // register 0x00
+    rom_hi: u8,  // register 0x01, only bit 0 is wired up
+    out_lo: u8,  // register 0x04, data for writes
+    out_hi: u8,  // register 0x05
+    command: u8, // register 0x06, bit 0 is address bit 4 and bits 1-3 pick the command
+    in_lo: u8,   // register 0x0c, the result of a read
+    in_hi: u8,   // register 0x0d
+    storage: Storage,
+}
+
+impl Tama5 {
+    pub fn new(storage: Storage) -> Tama5 {
+        Tama5 {
+            storage,
+            ..Tama5::default()
+        }
+    }
+
+    /// ROM bank mapped to 0x4000-0x7fff.
+    pub fn rom_bank_4000(&self) -> usize {
+        (((self.rom_hi & 0b1) << 4) | self.rom_lo) as usize
+    }
+
+    // writing the low address nibble is what sets a command off
+    fn run_command(&mut self, addr_lo: u8) {
+        let addr = (((self.command & 0b1) << 4) | addr_lo) as usize;
+        match (self.command >> 1) & 0b111 {
+            // write RAM
+            0 => self
+                .storage
+                .set_ram_at(addr, (self.out_hi << 4) | self.out_lo),
+            // read RAM
+            1 => {
+                let val = self.storage.ram_at(addr);
+                self.in_lo = val & 0x0f;
+                self.in_hi = val >> 4;
+            }
+            // the rest talk to the clock
+            _ => {}
+        }
+    }
+}
+
+impl Cartridge for Tama5 {
+    fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0x0000..=0x3fff => self.storage.read_rom(0, addr),
+            0x4000..=0x7fff => self.storage.read_rom(self.rom_bank_4000(), addr),
+            0xa000 => {
+                0xf0 | match self.select {
+                    // always ready, commands finish straight away
+                    0x0a => 0b1,
+                    0x0c => self.in_lo,
+                    0x0d => self.in_hi,
+                    _ => 0x0f,
+                }
+            }
+            _ => 0xff,
+        }
+    }
+    fn write(&mut self, addr: usize, val: u8) {
+        let val = val & 0x0f;
+        match addr {
+            0xa000 => match self.select {
+                0x00 => self.rom_lo = val,
+                0x01 => self.rom_hi = val,
+                0x04 => self.out_lo = val,
+                0x05 => self.out_hi = val,
+                0x06 => self.command = val,
+                0x07 => self.run_command(val),
+                _ => {}
+            },
+            0xa001 => self.select = val,
+            _ => {}
+        }
+    }
+
+    fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::cartridge::{Cartridge, Storage};
+    use crate::memory::tama5::Tama5;
+
+    fn tama5() -> Tama5 {
+        Tama5::new(Storage::new(Vec::new(), 32, 0x20))
+    }
+    fn set(mbc: &mut Tama5, reg: u8, val: u8) {
+        mbc.write(0xa001, reg);
+        mbc.write(0xa000, val);
+    }
+    fn get(mbc: &mut Tama5, reg: u8) -> u8 {
+        mbc.write(0xa001, reg);
+        mbc.read(0xa000) & 0x0f
+    }
+
+    #[test]
+    fn rom_bank_split_across_registers() {
+        let mut mbc = tama5();
+        set(&mut mbc, 0x00, 0x03);
+        set(&mut mbc, 0x01, 0x01);
+
+        assert_eq!(mbc.rom_bank_4000(), 0x13);
+    }
+    #[test]
+    fn ram_through_commands() {
+        let mut mbc = tama5();
+        set(&mut mbc, 0x04, 0x2);
+        set(&mut mbc, 0x05, 0x4);
+        set(&mut mbc, 0x06, 0x01); // write, address 0x1_
+        set(&mut mbc, 0x07, 0x3);
+
+        set(&mut mbc, 0x06, 0x03); // read, address 0x1_
+        set(&mut mbc, 0x07, 0x3);
+        assert_eq!(get(&mut mbc, 0x0c), 0x2);
+        assert_eq!(get(&mut mbc, 0x0d), 0x4);
+
+        set(&mut mbc, 0x06, 0x02); // read, address 0x0_
+        set(&mut mbc, 0x07, 0x3);
+        assert_eq!(get(&mut mbc, 0x0c), 0x0);
+    }
+    #[test]
+    fn always_ready() {
+        let mut mbc = tama5();
+
+        assert_eq!(get(&mut mbc, 0x0a), 0x1);
+    }
+}
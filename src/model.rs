@@ -0,0 +1,22 @@
+// https://gbdev.io/pandocs/Power_Up_Sequence.html
+
+/// Hardware revisions that leave the machine in a different state after boot.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Model {
+    Dmg0, // early original Game Boy
+    Dmg,  // original Game Boy
+    Mgb,  // Game Boy Pocket/Light
+    Sgb,  // Super Game Boy
+    Sgb2, // Super Game Boy 2
+    Cgb,  // Game Boy Color
+    Agb,  // Game Boy Advance
+}
+
+impl Model {
+    pub fn is_cgb(&self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+    pub fn is_sgb(&self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+}
@@ -0,0 +1,363 @@
+use std::collections::VecDeque;
+
+use super::scanline::{bg_tile_addr, colour, shade, sprites_on_line, tile_row, Sprite};
+use super::{Lcdc, BGP, LCDC, SCREEN_WIDTH, SCX, SCY, WX, WY};
+use crate::memory::Memory;
+
+// https://gbdev.io/pandocs/pixel_fifo.html
+
+// nothing gets shifted out at the start of each line while the fetcher gets
+// going, which makes mode 3 take at least 172 dots
+const LINE_START_DOTS: u8 = 6;
+// getting the tile number, then the low and high bitplanes, take 2 dots each
+const FETCH_DOTS: u8 = 6;
+// a sprite fetch, once the background fetcher's out of the way
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct SpritePixel {
+    colour: u8,
+    palette: u16,
+    behind_bg: bool,
+}
+
+/// Fetches a tile's row at a time for the background or window, 8 pixels per tile.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+struct Fetcher {
+    dots: u8,   // into the current fetch, it's waiting to push from FETCH_DOTS on
+    tile_x: u8, // tiles pushed so far
+    window: bool,
+    tile: u8,
+    row: (u8, u8),
+}
+
+impl Fetcher {
+    fn new(window: bool) -> Fetcher {
+        Fetcher {
+            dots: 0,
+            tile_x: 0,
+            window,
+            tile: 0,
+            row: (0, 0),
+        }
+    }
+}
+
+/// Draws a line a pixel per dot through the background and sprite FIFOs, so
+/// registers written partway through a line take effect where they would on
+/// hardware and mode 3 takes as long as it should.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PixelFifo {
+    ly: u8,
+    lx: u8,          // pixels pushed to the screen so far
+    start_dots: u8,  // left before anything happens
+    discard: u8,     // pixels left to throw away for SCX's fine scroll
+    window_line: u8, // which line of the window to fetch
+    window_triggered: bool,
+
+    bg: VecDeque<u8>, // colour numbers
+    obj: VecDeque<SpritePixel>,
+    fetcher: Fetcher,
+    sprites: Vec<Sprite>, // still to be fetched on this line, leftmost first
+    sprite_fetch: Option<(Sprite, u8)>, // and the dots it has left once started
+    sprite_waiting: bool, // the sprite fetch is waiting on the background fetcher
+}
+
+impl PixelFifo {
+    pub fn new() -> PixelFifo {
+        PixelFifo {
+            ly: 0,
+            lx: 0,
+            start_dots: 0,
+            discard: 0,
+            window_line: 0,
+            window_triggered: false,
+
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+            fetcher: Fetcher::new(false),
+            sprites: Vec::new(),
+            sprite_fetch: None,
+            sprite_waiting: false,
+        }
+    }
+
+    /// Gets ready to draw `ly`, at the end of OAM scan.
+    pub fn start_line(&mut self, mem: &Memory, ly: u8, window_line: u8) {
+        let lcdc = Lcdc(mem.io_register(LCDC));
+        let mut sprites = sprites_on_line(mem.oam(), ly, lcdc.tall_sprites());
+        // further left gets fetched first, then earlier in OAM
+        sprites.sort_by_key(|(_, sprite)| sprite.x);
+
+        *self = PixelFifo {
+            ly,
+            start_dots: LINE_START_DOTS,
+            discard: mem.io_register(SCX) & 0b111,
+            window_line,
+            sprites: sprites.into_iter().map(|(_, sprite)| sprite).collect(),
+            ..PixelFifo::new()
+        };
+    }
+
+    /// Whether the window showed up on this line, so the next line of it is due.
+    pub fn window_triggered(&self) -> bool {
+        self.window_triggered
+    }
+
+    /// Runs one dot of mode 3, returning whether the line's finished.
+    pub fn dot(&mut self, mem: &Memory, framebuffer: &mut [u8]) -> bool {
+        if self.start_dots > 0 {
+            self.start_dots -= 1;
+            return false;
+        }
+
+        let lcdc = Lcdc(mem.io_register(LCDC));
+        if let Some((sprite, dots)) = self.sprite_fetch {
+            if self.sprite_waiting {
+                // the background fetcher gets to finish its tile first, the
+                // sprite fetch starts on the same dot it does
+                if self.fetcher.dots < FETCH_DOTS || self.bg.is_empty() {
+                    self.fetch_bg(mem);
+                }
+                if self.fetcher.dots < FETCH_DOTS || self.bg.is_empty() {
+                    return false;
+                }
+                self.sprite_waiting = false;
+            }
+
+            if dots > 1 {
+                self.sprite_fetch = Some((sprite, dots - 1));
+            } else {
+                self.sprite_fetch = None;
+                self.fetch_sprite(mem, sprite, lcdc.tall_sprites());
+            }
+            return false;
+        }
+
+        let wx = mem.io_register(WX) as u16;
+        let window_starts = lcdc.window_enabled()
+            && lcdc.bg_enabled()
+            && !self.window_triggered
+            && self.ly >= mem.io_register(WY)
+            && (self.lx as u16 + 7) >= wx;
+        if window_starts {
+            // the window starts over with an empty FIFO, costing a fetch
+            self.window_triggered = true;
+            self.bg.clear();
+            self.fetcher = Fetcher::new(true);
+        }
+
+        if lcdc.sprites_enabled() && self.discard == 0 {
+            // sprites partly off the left edge start on the first pixel
+            let due = self.sprites.first().filter(|s| s.x.max(8) - 8 <= self.lx);
+            if let Some(&sprite) = due {
+                self.sprites.remove(0);
+                self.sprite_fetch = Some((sprite, SPRITE_FETCH_DOTS));
+                self.sprite_waiting = true;
+                return self.dot(mem, framebuffer);
+            }
+        }
+
+        let done = self.shift(mem, lcdc, framebuffer);
+        self.fetch_bg(mem);
+        done
+    }
+
+    // pushes out a pixel, if there's one ready
+    fn shift(&mut self, mem: &Memory, lcdc: Lcdc, framebuffer: &mut [u8]) -> bool {
+        let Some(bg) = self.bg.pop_front() else {
+            return false;
+        };
+        if self.discard > 0 {
+            self.discard -= 1;
+            return false;
+        }
+
+        let bg = if lcdc.bg_enabled() { bg } else { 0 };
+        let mut pixel = shade(mem.io_register(BGP), bg);
+        if let Some(obj) = self.obj.pop_front() {
+            let hidden = obj.colour == 0 || (obj.behind_bg && bg != 0);
+            if lcdc.sprites_enabled() && !hidden {
+                pixel = shade(mem.io_register(obj.palette), obj.colour);
+            }
+        }
+
+        framebuffer[self.ly as usize * SCREEN_WIDTH + self.lx as usize] = pixel;
+        self.lx += 1;
+        self.lx as usize == SCREEN_WIDTH
+    }
+
+    fn fetch_bg(&mut self, mem: &Memory) {
+        let lcdc = Lcdc(mem.io_register(LCDC));
+        let vram = mem.vram();
+        let fetcher = &mut self.fetcher;
+
+        // where in the tile map this fetch reads from, SCX and SCY are
+        // picked up fresh for every tile
+        let (map, x, y) = if fetcher.window {
+            (lcdc.window_tile_map(), fetcher.tile_x, self.window_line)
+        } else {
+            let x = (mem.io_register(SCX) >> 3).wrapping_add(fetcher.tile_x) & 0x1f;
+            (
+                lcdc.bg_tile_map(),
+                x,
+                mem.io_register(SCY).wrapping_add(self.ly),
+            )
+        };
+
+        if fetcher.dots < FETCH_DOTS {
+            fetcher.dots += 1;
+            match fetcher.dots {
+                2 => {
+                    let entry = map + ((y as u16 / 8) * 32) + x as u16;
+                    fetcher.tile = vram[(entry - 0x8000) as usize];
+                }
+                4 => fetcher.row.0 = tile_row(vram, bg_tile_addr(lcdc, fetcher.tile), y % 8).0,
+                6 => fetcher.row.1 = tile_row(vram, bg_tile_addr(lcdc, fetcher.tile), y % 8).1,
+                _ => {}
+            }
+        }
+
+        // waits until the FIFO's empty to push a whole tile's row, which can
+        // be on the same dot the fetch finishes
+        if fetcher.dots == FETCH_DOTS && self.bg.is_empty() {
+            let (lo, hi) = fetcher.row;
+            self.bg.extend((0..8).map(|x| colour(lo, hi, x)));
+            fetcher.tile_x = fetcher.tile_x.wrapping_add(1);
+            fetcher.dots = 0;
+        }
+    }
+
+    fn fetch_sprite(&mut self, mem: &Memory, sprite: Sprite, tall: bool) {
+        let (lo, hi) = sprite.row(mem.vram(), self.ly, tall);
+        // cut off whatever's past the left edge
+        let hidden = 8u8.saturating_sub(sprite.x);
+
+        for x in hidden..8 {
+            let pixel = SpritePixel {
+                colour: colour(lo, hi, x),
+                palette: sprite.palette(),
+                behind_bg: sprite.behind_bg(),
+            };
+            // sprites already in the FIFO were fetched first, so they win
+            match self.obj.get_mut((x - hidden) as usize) {
+                Some(existing) if existing.colour == 0 => *existing = pixel,
+                Some(_) => {}
+                None => self.obj.push_back(pixel),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::Memory;
+    use crate::ppu::fifo::PixelFifo;
+    use crate::ppu::testing::{frame, mem_with_tiles, sprite};
+    use crate::ppu::{
+        Ppu, Renderer, BGP, LCDC, LY, SCREEN_HEIGHT, SCREEN_WIDTH, SCX, STAT, WX, WY,
+    };
+
+    fn fifo_ppu() -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.set_renderer(Renderer::Fifo);
+        ppu
+    }
+    // dots spent in mode 3 on the first line
+    fn mode_3_length(mem: &Memory) -> u32 {
+        let mut fifo = PixelFifo::new();
+        let mut framebuffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        fifo.start_line(mem, 0, 0);
+        (1..).find(|_| fifo.dot(mem, &mut framebuffer)).unwrap()
+    }
+    #[test]
+    fn matches_scanline_renderer() {
+        let mut mem = mem_with_tiles();
+        // tile 3 is a single colour 1 column on the left, to catch pixels out by one
+        for row in 0..8 {
+            mem.write8(0x8030 + row * 2, 0x80);
+        }
+        for i in 0..0x400 {
+            mem.write8(0x9800 + i, (i % 4) as u8);
+        }
+        sprite(&mut mem, 0, 20, 4, 0x01, 0x00);
+        sprite(&mut mem, 1, 30, 50, 0x03, 0x00);
+        sprite(&mut mem, 2, 30, 52, 0x01, 0x00);
+        mem.write8(SCX, 3);
+        mem.write8(WY, 60);
+        mem.write8(WX, 90);
+        mem.write8(LCDC, 0xf3);
+
+        assert_eq!(
+            frame(&mut mem, Renderer::Fifo),
+            frame(&mut mem, Renderer::Scanline)
+        );
+    }
+    #[test]
+    fn mode_3_at_least_172_dots() {
+        let mut mem = mem_with_tiles();
+        mem.write8(LCDC, 0x91);
+
+        assert_eq!(mode_3_length(&mem), 172);
+    }
+    #[test]
+    fn fine_scroll_lengthens_mode_3() {
+        let mut mem = mem_with_tiles();
+        mem.write8(SCX, 0x05);
+        mem.write8(LCDC, 0x91);
+
+        assert_eq!(mode_3_length(&mem), 177);
+    }
+    #[test]
+    fn stat_follows_mode_3_length() {
+        let mut mem = mem_with_tiles();
+        mem.write8(SCX, 0x04);
+        mem.write8(LCDC, 0x91);
+        let mut ppu = fifo_ppu();
+
+        // 80 dots of OAM scan, then 176 of drawing
+        ppu.tick(&mut mem, 20 + 43);
+        assert_eq!(mem.read8(STAT) & 0b11, 3);
+        ppu.tick(&mut mem, 1);
+        assert_eq!(mem.read8(STAT) & 0b11, 0);
+    }
+    #[test]
+    fn sprites_lengthen_mode_3() {
+        let mut mem = mem_with_tiles();
+        sprite(&mut mem, 0, 16, 40, 0x01, 0x00);
+        sprite(&mut mem, 1, 16, 80, 0x01, 0x00);
+        mem.write8(LCDC, 0x93);
+
+        // lined up with a tile, each waits 5 dots on the background fetcher
+        assert_eq!(mode_3_length(&mem), 172 + 11 + 11);
+    }
+    #[test]
+    fn window_lengthens_mode_3() {
+        let mut mem = mem_with_tiles();
+        mem.write8(WX, 87);
+        mem.write8(LCDC, 0xb1);
+
+        // a fetch's worth
+        assert_eq!(mode_3_length(&mem), 172 + 6);
+    }
+    #[test]
+    fn palette_change_mid_line() {
+        let mut mem = mem_with_tiles();
+        for i in 0..32 {
+            mem.write8(0x9800 + i, 0x01);
+        }
+        mem.write8(LCDC, 0x91);
+        let mut ppu = fifo_ppu();
+
+        // partway through drawing the first line
+        ppu.tick(&mut mem, 20 + 2 + 20);
+        mem.write8(BGP, 0x00);
+        while mem.read8(LY) == 0 {
+            ppu.tick(&mut mem, 1);
+        }
+
+        let line = &ppu.framebuffer()[..SCREEN_WIDTH];
+        assert_eq!(line[0], 3);
+        assert_eq!(line[SCREEN_WIDTH - 1], 0);
+    }
+}
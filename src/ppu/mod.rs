@@ -0,0 +1,358 @@
+use self::fifo::PixelFifo;
+use crate::cpu::interrupts::Interrupt;
+use crate::memory::Memory;
+
+mod fifo;
+mod scanline;
+#[cfg(test)]
+mod testing;
+
+// https://gbdev.io/pandocs/Rendering.html
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const DOTS_PER_LINE: u32 = 456;
+const LINES_PER_FRAME: u8 = 154;
+const OAM_SCAN_DOTS: u32 = 80;
+// how long drawing takes with the scanline renderer, the pixel FIFO takes
+// anywhere from this up to about 289
+const DRAWING_DOTS: u32 = 172;
+
+// the registers the PPU looks at, all kept in memory's I/O registers
+pub const LCDC: u16 = 0xff40;
+pub const STAT: u16 = 0xff41;
+pub const SCY: u16 = 0xff42;
+pub const SCX: u16 = 0xff43;
+pub const LY: u16 = 0xff44;
+pub const LYC: u16 = 0xff45;
+pub const BGP: u16 = 0xff47;
+pub const OBP0: u16 = 0xff48;
+pub const OBP1: u16 = 0xff49;
+pub const WY: u16 = 0xff4a;
+pub const WX: u16 = 0xff4b;
+
+/// LCDC's bits, pulled out.
+/// https://gbdev.io/pandocs/LCDC.html
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Lcdc(pub u8);
+
+impl Lcdc {
+    pub fn lcd_enabled(&self) -> bool {
+        (self.0 & 0x80) != 0
+    }
+    pub fn window_tile_map(&self) -> u16 {
+        if (self.0 & 0x40) != 0 {
+            0x9c00
+        } else {
+            0x9800
+        }
+    }
+    pub fn window_enabled(&self) -> bool {
+        (self.0 & 0x20) != 0
+    }
+    /// Whether tiles are numbered up from 0x8000, or either side of 0x9000.
+    pub fn unsigned_tile_data(&self) -> bool {
+        (self.0 & 0x10) != 0
+    }
+    pub fn bg_tile_map(&self) -> u16 {
+        if (self.0 & 0x08) != 0 {
+            0x9c00
+        } else {
+            0x9800
+        }
+    }
+    pub fn tall_sprites(&self) -> bool {
+        (self.0 & 0x04) != 0
+    }
+    pub fn sprites_enabled(&self) -> bool {
+        (self.0 & 0x02) != 0
+    }
+    /// On the DMG this turns off the window along with the background.
+    pub fn bg_enabled(&self) -> bool {
+        (self.0 & 0x01) != 0
+    }
+}
+
+/// What part of the line the PPU is in, as reported in the lower 2 bits of STAT.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/// How each line gets drawn.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Renderer {
+    /// All at once at the end of mode 3, which always takes the same time. Fast,
+    /// and plenty for games that only change registers between lines.
+    Scanline,
+    /// A pixel per dot through the pixel FIFO, for raster effects partway
+    /// through a line and mode 3 timing that depends on what's drawn.
+    Fifo,
+}
+
+/// Walks through each line of the screen alongside the CPU, drawing into a
+/// framebuffer of shades from 0 (white) to 3 (black).
+pub struct Ppu {
+    renderer: Renderer,
+    mode: Mode,
+    dot: u32,        // into the current line
+    window_line: u8, // lines of the window drawn so far this frame
+    stat_line: bool, // whether any enabled STAT source is on
+    fifo: PixelFifo,
+    framebuffer: Vec<u8>,
+    frame_ready: bool, // a whole frame was drawn since the last `take_frame_ready`
+}
+
+impl Ppu {
+    pub fn new() -> Ppu {
+        Ppu {
+            renderer: Renderer::Scanline,
+            mode: Mode::HBlank,
+            dot: 0,
+            window_line: 0,
+            stat_line: false,
+            fifo: PixelFifo::new(),
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_ready: false,
+        }
+    }
+
+    /// Picks how lines get drawn from the next one on.
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    /// The last frame drawn, row by row.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+    /// Whether a frame was finished since this was last called.
+    pub fn take_frame_ready(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    /// Runs the PPU for as long as the CPU just took.
+    pub fn tick(&mut self, mem: &mut Memory, m_cycles: u8) {
+        // the PPU doesn't speed up along with the CPU
+        let dots_per_cycle = if mem.double_speed() { 2 } else { 4 };
+        for _ in 0..(m_cycles as u32 * dots_per_cycle) {
+            self.step(mem);
+        }
+    }
+
+    // one dot, a quarter of a single-speed M-cycle
+    fn step(&mut self, mem: &mut Memory) {
+        if !Lcdc(mem.io_register(LCDC)).lcd_enabled() {
+            // switched off, LY sits at 0 until it's back on and nothing
+            // raises an interrupt
+            self.dot = 0;
+            self.window_line = 0;
+            self.mode = Mode::HBlank;
+            mem.set_io_register(LY, 0);
+            self.write_stat(mem);
+            self.stat_line = false;
+            return;
+        }
+
+        let ly = mem.io_register(LY);
+        if ly < SCREEN_HEIGHT as u8 {
+            if self.dot == 0 {
+                self.mode = Mode::OamScan;
+            } else if self.dot == OAM_SCAN_DOTS {
+                self.mode = Mode::Drawing;
+                if self.renderer == Renderer::Fifo {
+                    self.fifo.start_line(mem, ly, self.window_line);
+                }
+            }
+
+            if self.mode == Mode::Drawing {
+                self.draw(mem, ly);
+            }
+        }
+
+        self.dot += 1;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            let ly = (ly + 1) % LINES_PER_FRAME;
+            mem.set_io_register(LY, ly);
+            if ly == SCREEN_HEIGHT as u8 {
+                self.frame_ready = true;
+                self.mode = Mode::VBlank;
+                mem.request_interrupt(Interrupt::VBlank);
+            } else if ly == 0 {
+                self.window_line = 0;
+            }
+        }
+
+        self.update_stat(mem);
+    }
+
+    // one dot of mode 3, moving on to HBlank once the line's done
+    fn draw(&mut self, mem: &mut Memory, ly: u8) {
+        let done = match self.renderer {
+            Renderer::Scanline => {
+                let done = self.dot == OAM_SCAN_DOTS + DRAWING_DOTS - 1;
+                if done {
+                    scanline::draw_line(mem, ly, &mut self.window_line, &mut self.framebuffer);
+                }
+                done
+            }
+            Renderer::Fifo => {
+                let done = self.fifo.dot(mem, &mut self.framebuffer);
+                if done && self.fifo.window_triggered() {
+                    self.window_line += 1;
+                }
+                done
+            }
+        };
+
+        if done {
+            self.mode = Mode::HBlank;
+        }
+    }
+
+    // https://gbdev.io/pandocs/STAT.html
+    // puts the mode and LY=LYC in STAT, handing back the whole register
+    fn write_stat(&self, mem: &mut Memory) -> u8 {
+        let coincidence = mem.io_register(LY) == mem.io_register(LYC);
+        let stat = (mem.io_register(STAT) & 0x78) | ((coincidence as u8) << 2) | self.mode as u8;
+        mem.set_io_register(STAT, 0x80 | stat);
+        stat
+    }
+    // raises the STAT interrupt when any of the sources it has enabled comes on.
+    // they all share one line, so a source coming on while another's already
+    // holding it high doesn't raise another
+    fn update_stat(&mut self, mem: &mut Memory) {
+        let stat = self.write_stat(mem);
+        let coincidence = (stat & 0x04) != 0;
+
+        let source = match self.mode {
+            Mode::HBlank => 0x08,
+            Mode::VBlank => 0x10,
+            Mode::OamScan => 0x20,
+            Mode::Drawing => 0x00,
+        };
+        let line = (stat & source) != 0 || (coincidence && (stat & 0x40) != 0);
+        if line && !self.stat_line {
+            mem.request_interrupt(Interrupt::LcdStat);
+        }
+        self.stat_line = line;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::interrupts::Interrupt;
+    use crate::memory::Memory;
+    use crate::ppu::{Ppu, LCDC, LY, LYC, SCREEN_HEIGHT, STAT};
+
+    fn lcd_on() -> (Ppu, Memory) {
+        let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+        mem.write8(LCDC, 0x91);
+        (Ppu::new(), mem)
+    }
+
+    #[test]
+    fn ly_counts_lines() {
+        let (mut ppu, mut mem) = lcd_on();
+        ppu.tick(&mut mem, 114);
+        assert_eq!(mem.read8(LY), 1);
+
+        for _ in 0..143 {
+            ppu.tick(&mut mem, 114);
+        }
+        assert_eq!(mem.read8(LY), SCREEN_HEIGHT as u8);
+        assert_eq!(mem.read8(STAT) & 0b11, 1);
+        assert!(ppu.take_frame_ready());
+        assert!(!ppu.take_frame_ready());
+
+        for _ in 0..10 {
+            ppu.tick(&mut mem, 114);
+        }
+        assert_eq!(mem.read8(LY), 0);
+    }
+    #[test]
+    fn modes_within_a_line() {
+        let (mut ppu, mut mem) = lcd_on();
+        ppu.tick(&mut mem, 1);
+        assert_eq!(mem.read8(STAT) & 0b11, 2);
+        ppu.tick(&mut mem, 20);
+        assert_eq!(mem.read8(STAT) & 0b11, 3);
+        ppu.tick(&mut mem, 43);
+        assert_eq!(mem.read8(STAT) & 0b11, 0);
+    }
+    #[test]
+    fn lcd_off_holds_ly_at_0() {
+        let (mut ppu, mut mem) = lcd_on();
+        for _ in 0..3 {
+            ppu.tick(&mut mem, 114);
+        }
+        mem.write8(LCDC, 0x11);
+        ppu.tick(&mut mem, 114);
+
+        assert_eq!(mem.read8(LY), 0);
+        assert!(!ppu.take_frame_ready());
+    }
+    #[test]
+    fn vblank_interrupt_at_line_144() {
+        let (mut ppu, mut mem) = lcd_on();
+        for _ in 0..143 {
+            ppu.tick(&mut mem, 114);
+        }
+        assert_eq!(mem.interrupt_flag() & Interrupt::VBlank.bit(), 0);
+
+        ppu.tick(&mut mem, 114);
+        assert_ne!(mem.interrupt_flag() & Interrupt::VBlank.bit(), 0);
+    }
+    #[test]
+    fn lyc_raises_stat_interrupt() {
+        let (mut ppu, mut mem) = lcd_on();
+        mem.write8(STAT, 0x40);
+        mem.write8(LYC, 2);
+        ppu.tick(&mut mem, 114);
+        assert_eq!(mem.interrupt_flag() & Interrupt::LcdStat.bit(), 0);
+
+        ppu.tick(&mut mem, 114);
+        assert_ne!(mem.interrupt_flag() & Interrupt::LcdStat.bit(), 0);
+        assert_ne!(mem.read8(STAT) & 0x04, 0);
+    }
+    #[test]
+    fn hblank_stat_interrupt() {
+        let (mut ppu, mut mem) = lcd_on();
+        mem.write8(STAT, 0x08);
+        ppu.tick(&mut mem, 62);
+        assert_eq!(mem.interrupt_flag() & Interrupt::LcdStat.bit(), 0);
+
+        ppu.tick(&mut mem, 1);
+        assert_ne!(mem.interrupt_flag() & Interrupt::LcdStat.bit(), 0);
+    }
+    #[test]
+    fn stat_blocking() {
+        // LY=LYC comes on while HBlank's already holding the line high
+        let (mut ppu, mut mem) = lcd_on();
+        mem.write8(STAT, 0x48);
+        mem.write8(LYC, 1);
+        ppu.tick(&mut mem, 64);
+        assert_ne!(mem.interrupt_flag() & Interrupt::LcdStat.bit(), 0);
+
+        mem.write8(0xff0f, 0x00);
+        ppu.tick(&mut mem, 60);
+        assert_eq!(mem.read8(LY), 1);
+        assert_eq!(mem.interrupt_flag() & Interrupt::LcdStat.bit(), 0);
+    }
+    #[test]
+    fn no_stat_interrupts_with_lcd_off() {
+        let (mut ppu, mut mem) = lcd_on();
+        mem.write8(LCDC, 0x11);
+        mem.write8(STAT, 0x48);
+        mem.write8(LYC, 0);
+        ppu.tick(&mut mem, 114);
+
+        assert_eq!(mem.interrupt_flag(), 0);
+        assert_eq!(mem.read8(STAT) & 0x07, 0x04);
+    }
+}
@@ -0,0 +1,272 @@
+use super::{Lcdc, BGP, LCDC, OBP0, OBP1, SCREEN_WIDTH, SCX, SCY, WX, WY};
+use crate::memory::Memory;
+
+// https://gbdev.io/pandocs/Tile_Data.html
+// https://gbdev.io/pandocs/OAM.html
+
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+/// One entry in OAM.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Sprite {
+    pub y: u8, // 16 more than the top of the sprite on screen
+    pub x: u8, // 8 more than its left edge
+    pub tile: u8,
+    pub flags: u8,
+}
+
+impl Sprite {
+    pub fn from_oam(oam: &[u8], index: usize) -> Sprite {
+        let entry = &oam[index * 4..index * 4 + 4];
+        Sprite {
+            y: entry[0],
+            x: entry[1],
+            tile: entry[2],
+            flags: entry[3],
+        }
+    }
+
+    /// Whether the background and window show through over colours 1-3.
+    pub fn behind_bg(&self) -> bool {
+        (self.flags & 0x80) != 0
+    }
+    pub fn y_flip(&self) -> bool {
+        (self.flags & 0x40) != 0
+    }
+    pub fn x_flip(&self) -> bool {
+        (self.flags & 0x20) != 0
+    }
+    pub fn palette(&self) -> u16 {
+        if (self.flags & 0x10) != 0 {
+            OBP1
+        } else {
+            OBP0
+        }
+    }
+
+    /// The tile row this sprite shows on `ly`, as the two bitplanes.
+    pub fn row(&self, vram: &[u8], ly: u8, tall: bool) -> (u8, u8) {
+        let height = if tall { 16 } else { 8 };
+        let mut row = ly.wrapping_sub(self.y.wrapping_sub(16)) % height;
+        if self.y_flip() {
+            row = height - 1 - row;
+        }
+        // the lower bit's ignored for 8x16 sprites, the second tile follows on
+        let tile = if tall { self.tile & 0xfe } else { self.tile };
+
+        let (lo, hi) = tile_row(vram, 0x8000 + (tile as u16 * 16), row);
+        if self.x_flip() {
+            (lo.reverse_bits(), hi.reverse_bits())
+        } else {
+            (lo, hi)
+        }
+    }
+}
+
+/// The first 10 sprites in OAM that cover `ly`, the rest don't get drawn.
+pub fn sprites_on_line(oam: &[u8], ly: u8, tall: bool) -> Vec<(usize, Sprite)> {
+    let height = if tall { 16 } else { 8 };
+    (0..40)
+        .map(|index| (index, Sprite::from_oam(oam, index)))
+        .filter(|(_, sprite)| {
+            let top = sprite.y as i16 - 16;
+            (top..top + height).contains(&(ly as i16))
+        })
+        .take(MAX_SPRITES_PER_LINE)
+        .collect()
+}
+
+/// Where the tile numbered `index` in the background or window starts.
+pub fn bg_tile_addr(lcdc: Lcdc, index: u8) -> u16 {
+    if lcdc.unsigned_tile_data() {
+        0x8000 + (index as u16 * 16)
+    } else {
+        0x9000u16.wrapping_add_signed(index as i8 as i16 * 16)
+    }
+}
+/// Both bitplanes of one row of the tile at `addr`.
+pub fn tile_row(vram: &[u8], addr: u16, row: u8) -> (u8, u8) {
+    let offset = (addr - 0x8000) as usize + (row as usize * 2);
+    (vram[offset], vram[offset + 1])
+}
+/// Colour number 0-3 of pixel `x` in a row, counting from the left.
+pub fn colour(lo: u8, hi: u8, x: u8) -> u8 {
+    let bit = 7 - x;
+    (((hi >> bit) & 0b1) << 1) | ((lo >> bit) & 0b1)
+}
+pub fn shade(palette: u8, colour: u8) -> u8 {
+    (palette >> (colour * 2)) & 0b11
+}
+
+/// Draws all of line `ly` in one go, using the registers as they are at the end of drawing.
+pub fn draw_line(mem: &Memory, ly: u8, window_line: &mut u8, framebuffer: &mut [u8]) {
+    let lcdc = Lcdc(mem.io_register(LCDC));
+    let vram = mem.vram();
+    let (scx, scy) = (mem.io_register(SCX), mem.io_register(SCY));
+    let (wx, wy) = (mem.io_register(WX), mem.io_register(WY));
+
+    // colour numbers rather than shades, sprites need those to sort out priority
+    let mut bg = [0u8; SCREEN_WIDTH];
+    if lcdc.bg_enabled() {
+        let y = scy.wrapping_add(ly);
+        for (x, pixel) in bg.iter_mut().enumerate() {
+            let x = scx.wrapping_add(x as u8);
+            *pixel = map_pixel(vram, lcdc, lcdc.bg_tile_map(), x, y);
+        }
+
+        // WX is 7 more than the window's left edge
+        let window_visible = lcdc.window_enabled() && ly >= wy && wx < (SCREEN_WIDTH + 7) as u8;
+        if window_visible {
+            let left = wx.saturating_sub(7) as usize;
+            for (x, pixel) in bg.iter_mut().enumerate().skip(left) {
+                let x = (x + 7 - wx as usize) as u8;
+                *pixel = map_pixel(vram, lcdc, lcdc.window_tile_map(), x, *window_line);
+            }
+            *window_line += 1;
+        }
+    }
+
+    let line = &mut framebuffer[ly as usize * SCREEN_WIDTH..(ly as usize + 1) * SCREEN_WIDTH];
+    let bgp = mem.io_register(BGP);
+    for (pixel, colour) in line.iter_mut().zip(bg) {
+        *pixel = shade(bgp, colour);
+    }
+
+    if !lcdc.sprites_enabled() {
+        return;
+    }
+    // further left wins, then earlier in OAM. the sort's stable so OAM order holds
+    let mut sprites = sprites_on_line(mem.oam(), ly, lcdc.tall_sprites());
+    sprites.sort_by_key(|(_, sprite)| sprite.x);
+
+    for x in 0..SCREEN_WIDTH {
+        // the first sprite with something to show here decides the pixel,
+        // even when it ends up hidden behind the background
+        let screen_x = x as i16 + 8;
+        let pixel = sprites.iter().find_map(|(_, sprite)| {
+            let column = screen_x - sprite.x as i16;
+            if !(0..8).contains(&column) {
+                return None;
+            }
+            let (lo, hi) = sprite.row(vram, ly, lcdc.tall_sprites());
+            match colour(lo, hi, column as u8) {
+                0 => None,
+                c => Some((sprite, c)),
+            }
+        });
+
+        if let Some((sprite, c)) = pixel {
+            if !(sprite.behind_bg() && bg[x] != 0) {
+                line[x] = shade(mem.io_register(sprite.palette()), c);
+            }
+        }
+    }
+}
+
+// colour number at (x, y) of the 256x256 picture a tile map makes up
+fn map_pixel(vram: &[u8], lcdc: Lcdc, map: u16, x: u8, y: u8) -> u8 {
+    let entry = map + ((y as u16 / 8) * 32) + (x as u16 / 8);
+    let index = vram[(entry - 0x8000) as usize];
+    let (lo, hi) = tile_row(vram, bg_tile_addr(lcdc, index), y % 8);
+    colour(lo, hi, x % 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::Memory;
+    use crate::ppu::scanline::sprites_on_line;
+    use crate::ppu::testing::{mem_with_tiles, sprite};
+    use crate::ppu::{testing, Renderer, LCDC, SCREEN_WIDTH, SCX, WX, WY};
+
+    fn frame(mem: &mut Memory) -> Vec<u8> {
+        testing::frame(mem, Renderer::Scanline)
+    }
+
+    #[test]
+    fn background_scrolls() {
+        let mut mem = mem_with_tiles();
+        mem.write8(0x9801, 0x01); // second tile across is black
+        mem.write8(SCX, 4);
+        mem.write8(LCDC, 0x91);
+        let frame = frame(&mut mem);
+
+        assert_eq!(&frame[0..4], &[0, 0, 0, 0]);
+        assert_eq!(&frame[4..12], &[3; 8]);
+        assert_eq!(frame[12], 0);
+    }
+    #[test]
+    fn signed_tile_data() {
+        let mut mem = mem_with_tiles();
+        // tile 0 at 0x9000 is colour 1, tile 0 at 0x8000 stays blank
+        for row in 0..8 {
+            mem.write8(0x9000 + row * 2, 0xff);
+        }
+        mem.write8(LCDC, 0x81);
+
+        assert_eq!(frame(&mut mem)[0], 1);
+    }
+    #[test]
+    fn window_covers_background() {
+        let mut mem = mem_with_tiles();
+        for i in 0..32 {
+            mem.write8(0x9c00 + i, 0x02);
+        }
+        mem.write8(WY, 8);
+        mem.write8(WX, 7 + 80);
+        mem.write8(LCDC, 0xf1);
+        let frame = frame(&mut mem);
+
+        assert_eq!(frame[7 * SCREEN_WIDTH + 80], 0);
+        assert_eq!(frame[8 * SCREEN_WIDTH + 79], 0);
+        assert_eq!(frame[8 * SCREEN_WIDTH + 80], 1);
+    }
+    #[test]
+    fn sprites_draw_over_background() {
+        let mut mem = mem_with_tiles();
+        sprite(&mut mem, 0, 16, 8, 0x01, 0x00);
+        sprite(&mut mem, 1, 16, 28, 0x01, 0x80);
+        mem.write8(0x9802, 0x02);
+        mem.write8(LCDC, 0x93);
+        let frame = frame(&mut mem);
+
+        assert_eq!(frame[0], 3);
+        // behind the background, only shows over colour 0
+        assert_eq!(frame[20], 1);
+        assert_eq!(frame[24], 3);
+    }
+    #[test]
+    fn leftmost_sprite_wins() {
+        let mut mem = mem_with_tiles();
+        sprite(&mut mem, 0, 16, 12, 0x02, 0x00);
+        sprite(&mut mem, 1, 16, 8, 0x01, 0x00);
+        mem.write8(LCDC, 0x93);
+        let frame = frame(&mut mem);
+
+        assert_eq!(frame[4], 3);
+        assert_eq!(frame[8], 1);
+    }
+    #[test]
+    fn ten_sprites_per_line() {
+        let mut mem = mem_with_tiles();
+        for i in 0..12 {
+            sprite(&mut mem, i, 16, 8 + (i as u8 * 8), 0x01, 0x00);
+        }
+        mem.write8(LCDC, 0x93);
+        assert_eq!(sprites_on_line(mem.oam(), 0, false).len(), 10);
+
+        let frame = frame(&mut mem);
+        assert_eq!(frame[9 * 8], 3);
+        assert_eq!(frame[10 * 8], 0);
+    }
+    #[test]
+    fn tall_sprites_flip() {
+        let mut mem = mem_with_tiles();
+        // tile 2 on top, 3 below it. flipped, the blank tile 3 ends up on top
+        sprite(&mut mem, 0, 16, 8, 0x03, 0x40);
+        mem.write8(LCDC, 0x97);
+        let frame = frame(&mut mem);
+
+        assert_eq!(frame[0], 0);
+        assert_eq!(frame[8 * SCREEN_WIDTH], 1);
+    }
+}
@@ -0,0 +1,36 @@
+use crate::memory::Memory;
+use crate::ppu::{Ppu, Renderer, BGP, OBP0};
+
+// helpers shared by the renderers' tests
+
+/// Tile 1 is solid colour 3, tile 2 solid colour 1, and both palettes leave
+/// colours as they are. The tile maps are left blank.
+pub fn mem_with_tiles() -> Memory {
+    let mut mem = Memory::from(vec![0; 0x8000]).unwrap();
+    for row in 0..8 {
+        mem.write8(0x8010 + row * 2, 0xff);
+        mem.write8(0x8011 + row * 2, 0xff);
+        mem.write8(0x8020 + row * 2, 0xff);
+    }
+    mem.write8(BGP, 0xe4);
+    mem.write8(OBP0, 0xe4);
+    mem
+}
+
+pub fn sprite(mem: &mut Memory, index: u16, y: u8, x: u8, tile: u8, flags: u8) {
+    let addr = 0xfe00 + index * 4;
+    mem.write8(addr, y);
+    mem.write8(addr + 1, x);
+    mem.write8(addr + 2, tile);
+    mem.write8(addr + 3, flags);
+}
+
+/// Draws a whole frame from scratch.
+pub fn frame(mem: &mut Memory, renderer: Renderer) -> Vec<u8> {
+    let mut ppu = Ppu::new();
+    ppu.set_renderer(renderer);
+    while !ppu.take_frame_ready() {
+        ppu.tick(mem, 1);
+    }
+    ppu.framebuffer().to_vec()
+}
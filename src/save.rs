@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::memory::Memory;
+use crate::Result;
+
+// about once every emulated second
+const FLUSH_INTERVAL: u64 = 1 << 20;
+
+/// The .sav file next to a ROM, holding the cartridge's battery-backed RAM and clock.
+pub struct SaveFile {
+    path: PathBuf,
+    cycles_since_flush: u64,
+}
+
+impl SaveFile {
+    pub fn for_rom(rom_path: &Path) -> SaveFile {
+        SaveFile {
+            path: rom_path.with_extension("sav"),
+            cycles_since_flush: 0,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Restores an existing save, if the cartridge has a battery and there's one to restore.
+    pub fn load(&self, mem: &mut Memory) -> Result<()> {
+        if !mem.has_battery() || !self.path.exists() {
+            return Ok(());
+        }
+
+        let data = fs::read(&self.path)?;
+        mem.load_save_data(&data)?;
+        Ok(())
+    }
+
+    /// Writes the save out every so often, as long as something in it has changed.
+    pub fn tick(&mut self, mem: &mut Memory, m_cycles: u8) -> Result<()> {
+        self.cycles_since_flush += m_cycles as u64;
+        if self.cycles_since_flush < FLUSH_INTERVAL {
+            return Ok(());
+        }
+
+        self.cycles_since_flush = 0;
+        if mem.take_save_dirty() {
+            self.flush(mem)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self, mem: &mut Memory) -> Result<()> {
+        if !mem.has_battery() {
+            return Ok(());
+        }
+
+        fs::write(&self.path, mem.save_data())?;
+        Ok(())
+    }
+}